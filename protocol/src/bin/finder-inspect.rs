@@ -0,0 +1,138 @@
+//! Decode a finder storage image, such as a RAM or flash dump, in to
+//! a timeline of its records.
+//!
+//! usage: finder-inspect [--json] [--ed25519] [--key KEY]... IMAGE
+//!
+//! Keys are those of nodes whose envelopes should be checked beyond
+//! what the image itself vouches for. They are PEM encoded RSA keys,
+//! public or private, or with `--ed25519`, for images written by the
+//! Ed25519 backend, public keys as 64 hex digits.
+
+use std::process::ExitCode;
+
+use protocol::crypto::ed25519::{Ed25519Crypto, PublicKey};
+use protocol::crypto::rust::RustCrypto;
+use protocol::crypto::Crypto;
+use protocol::inspect::{slab_size, write_size, Entry, Inspector};
+use protocol::storage::mem_io::MemIO;
+use protocol::storage::{Storage, StorageError};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+
+const USAGE: &str = "usage: finder-inspect [--json] [--ed25519] [--key KEY]... IMAGE";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("finder-inspect: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut json = false;
+    let mut ed25519 = false;
+    let mut keys = Vec::new();
+    let mut path = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--ed25519" => ed25519 = true,
+            "--key" => keys.push(args.next().ok_or(USAGE)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+    let path = path.ok_or(USAGE)?;
+
+    let mut image = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let seed = [0; 128];
+    let entries = if ed25519 {
+        let crypto = Ed25519Crypto::new(&seed).map_err(|e| format!("crypto: {:?}", e))?;
+        inspect(&crypto, &keys, read_ed25519_key, &path, &mut image)?
+    } else {
+        let crypto = RustCrypto::new(&seed).map_err(|e| format!("crypto: {:?}", e))?;
+        inspect(&crypto, &keys, read_key, &path, &mut image)?
+    };
+
+    if json {
+        let out = serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())?;
+        println!("{}", out);
+    } else {
+        for entry in &entries {
+            println!("{}", entry);
+        }
+    }
+
+    Ok(())
+}
+
+fn inspect<C: Crypto>(
+    crypto: &C,
+    keys: &[String],
+    read_key: fn(&str) -> Result<C::PubSigningKey, String>,
+    path: &str,
+    image: &mut [u8],
+) -> Result<Vec<Entry>, String> {
+    let mut inspector = Inspector::new(crypto);
+    for key in keys {
+        inspector.add_key(read_key(key)?);
+    }
+
+    let size = slab_size(image).map_err(|e| format!("{}: not a storage image, {:?}", path, e))?;
+    match size {
+        256 => timeline::<C, 256>(&mut inspector, image),
+        512 => timeline::<C, 512>(&mut inspector, image),
+        1024 => timeline::<C, 1024>(&mut inspector, image),
+        2048 => timeline::<C, 2048>(&mut inspector, image),
+        4096 => timeline::<C, 4096>(&mut inspector, image),
+        8192 => timeline::<C, 8192>(&mut inspector, image),
+        _ => return Err(format!("{}: unsupported slab size {}", path, size)),
+    }
+    .map_err(|e| format!("{}: {:?}", path, e))
+}
+
+fn timeline<C: Crypto, const SLAB_SIZE: usize>(
+    inspector: &mut Inspector<'_, C>,
+    image: &mut [u8],
+) -> Result<Vec<Entry>, StorageError> {
+    // Opening can repair a torn tail, only the copy in memory changes.
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::with_write_size(image, write_size(image)?)?;
+    inspector.timeline(&Storage::new(io))
+}
+
+fn read_key(path: &str) -> Result<RsaPublicKey, String> {
+    let pem = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+    RsaPublicKey::from_public_key_pem(&pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(&pem))
+        .or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem).map(|key| key.to_public_key()))
+        .or_else(|_| RsaPrivateKey::from_pkcs8_pem(&pem).map(|key| key.to_public_key()))
+        .map_err(|_| format!("{}: not a PEM RSA key", path))
+}
+
+fn read_ed25519_key(path: &str) -> Result<PublicKey, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let invalid = || format!("{}: not a hex Ed25519 public key", path);
+
+    let text = text.trim();
+    if text.len() != 64 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    let mut bytes = [0u8; 32];
+    for (byte, digits) in bytes.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+
+    PublicKey::from_bytes(&bytes).map_err(|_| invalid())
+}
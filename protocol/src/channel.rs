@@ -56,9 +56,17 @@ impl<T> Message<T> {
     pub fn sequence(&self) -> u64 {
         self.sequence
     }
+
+    pub fn cause(&self) -> NodeId {
+        self.cause
+    }
+
+    pub fn sender_last(&self) -> u64 {
+        self.sender_last
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct NodeSequence<P> {
     pub public_key: P,
     pub node: NodeId,
@@ -80,12 +88,16 @@ pub enum ChannelError {
     NodeExists,
     UnknownNode,
     AlreadyReceived,
+    /// The message's sequences contradict what the sender sent before.
+    BadSequence,
 }
 
 #[derive(Debug)]
 pub struct ChannelState<const MAX_NODES: usize, P> {
     nodes: Vec<NodeSequence<P>, { MAX_NODES }>,
     newest: NodeId,
+    /// Key epoch of a confidential channel, moved on by each rekey.
+    epoch: u32,
 }
 
 impl<const MAX_NODES: usize, P: Clone> ChannelState<MAX_NODES, P> {
@@ -107,13 +119,56 @@ impl<const MAX_NODES: usize, P: Clone> ChannelState<MAX_NODES, P> {
         Ok(Self {
             nodes,
             newest: initial,
+            epoch: 0,
         })
     }
 
+    /// Rebuild the state saved by a checkpoint from `newest` and the
+    /// node list as returned by `list_nodes`.
+    pub fn restore(
+        newest: NodeId,
+        nodes: Vec<NodeSequence<P>, { MAX_NODES }>,
+    ) -> Result<Self, ChannelError> {
+        // Lookups binary search the node list.
+        if nodes.windows(2).any(|pair| pair[0].node >= pair[1].node) {
+            return Err(ChannelError::Unreachable);
+        }
+
+        let state = Self {
+            nodes,
+            newest,
+            epoch: 0,
+        };
+        state.get_current()?;
+
+        Ok(state)
+    }
+
     pub fn list_nodes(&self) -> &[NodeSequence<P>] {
         &self.nodes
     }
 
+    /// Node that sent the message with the largest sequence.
+    pub fn newest(&self) -> NodeId {
+        self.newest
+    }
+
+    /// Largest sequence received so far.
+    pub fn max_sequence(&self) -> Result<u64, ChannelError> {
+        Ok(self.get_current()?.sequence)
+    }
+
+    /// Current key epoch, messages are encrypted with its secret.
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Move on to key epoch `epoch`. The owner's rekeys are taken in
+    /// the order it sent them, an older one changes nothing.
+    pub fn rekey(&mut self, epoch: u32) {
+        self.epoch = self.epoch.max(epoch);
+    }
+
     pub fn add_node(&mut self, node: NodeId, node_key: P) -> Result<(), ChannelError> {
         let pos = self.nodes.binary_search_by_key(&node, |ns| ns.node);
 
@@ -158,15 +213,145 @@ impl<const MAX_NODES: usize, P: Clone> ChannelState<MAX_NODES, P> {
         id: &EnvelopeId,
     ) -> Result<u64, ChannelError> {
         let index = self.check_receive_worker(from, message, id)?;
+        self.record_receive(index, from, message.sequence, id)
+    }
+
+    /// Take a message from a log that was checked when it was first
+    /// stored. The log may start after eviction so only the sender's
+    /// sequence going up is required, not what it was caused by.
+    pub fn restore_receive<T: Serialize>(
+        &mut self,
+        from: NodeId,
+        message: &Message<T>,
+        id: &EnvelopeId,
+    ) -> Result<u64, ChannelError> {
+        self.restore_sequence(from, message.sequence, id)
+    }
+
+    /// Take the place of an envelope deleted from the log, only what
+    /// it did to the sender's sequence is known.
+    pub fn restore_deleted(
+        &mut self,
+        from: NodeId,
+        sequence: u64,
+        id: &EnvelopeId,
+    ) -> Result<u64, ChannelError> {
+        self.restore_sequence(from, sequence, id)
+    }
+
+    /// The gaps taking `message` would leave when envelopes it follows
+    /// were never received, one for the sender and one for the cause.
+    /// `index` is stamped on each. Fails as `check_receive` does when
+    /// the message can't be taken even then.
+    pub fn find_gaps<T>(
+        &self,
+        from: NodeId,
+        message: &Message<T>,
+        index: u64,
+    ) -> Result<Vec<Gap, 2>, ChannelError> {
+        let pos = self
+            .nodes
+            .binary_search_by_key(&from, |ns| ns.node)
+            .or(Err(ChannelError::UnknownNode))?;
+        let record = self.nodes.get(pos).ok_or(ChannelError::Unreachable)?;
+
+        if record.sequence >= message.sequence {
+            return Err(ChannelError::AlreadyReceived);
+        }
+        if message.sender_last < record.sequence || message.sender_last >= message.sequence {
+            return Err(ChannelError::BadSequence);
+        }
+
+        let mut gaps = Vec::new();
+        if record.sequence < message.sender_last {
+            let gap = Gap {
+                node: from,
+                after: record.sequence,
+                until: message.sender_last,
+                index,
+            };
+            gaps.push(gap).or(Err(ChannelError::Unreachable))?;
+        }
 
+        let cause_target = message.sequence.saturating_sub(1);
+        if message.cause == from {
+            // The sender's last envelope was the newest it knew of.
+            if message.sender_last != cause_target {
+                return Err(ChannelError::BadSequence);
+            }
+            return Ok(gaps);
+        }
+
+        match self.nodes.binary_search_by_key(&message.cause, |ns| ns.node) {
+            Ok(pos) => {
+                let cause = self.nodes.get(pos).ok_or(ChannelError::Unreachable)?;
+                if cause.sequence < cause_target {
+                    let gap = Gap {
+                        node: cause.node,
+                        after: cause.sequence,
+                        until: cause_target,
+                        index,
+                    };
+                    gaps.push(gap).or(Err(ChannelError::Unreachable))?;
+                }
+            }
+            // Without the cause's key there is no telling what it sent.
+            Err(_) if cause_target != 0 => {
+                return Err(ChannelError::MissingFromSender {
+                    node: message.cause,
+                    have: 0,
+                    missing: cause_target,
+                });
+            }
+            Err(_) => {}
+        }
+
+        Ok(gaps)
+    }
+
+    /// Take a message `find_gaps` allowed, the sender's sequence moves
+    /// past any gap it found.
+    pub fn receive_over_gaps<T>(
+        &mut self,
+        from: NodeId,
+        message: &Message<T>,
+        id: &EnvelopeId,
+    ) -> Result<u64, ChannelError> {
+        self.restore_sequence(from, message.sequence, id)
+    }
+
+    fn restore_sequence(
+        &mut self,
+        from: NodeId,
+        sequence: u64,
+        id: &EnvelopeId,
+    ) -> Result<u64, ChannelError> {
+        let index = self
+            .nodes
+            .binary_search_by_key(&from, |ns| ns.node)
+            .or(Err(ChannelError::UnknownNode))?;
+
+        let record = self.nodes.get(index).ok_or(ChannelError::Unreachable)?;
+        if record.sequence >= sequence {
+            return Err(ChannelError::AlreadyReceived);
+        }
+
+        self.record_receive(index, from, sequence, id)
+    }
+
+    fn record_receive(
+        &mut self,
+        index: usize,
+        from: NodeId,
+        sequence: u64,
+        id: &EnvelopeId,
+    ) -> Result<u64, ChannelError> {
         let current = self.get_current()?;
 
         let max_sequence: u64;
         // Updated newest if needed.
-        if current.sequence < message.sequence
-            || (current.sequence == message.sequence && current.id < *id)
-        {
-            max_sequence = message.sequence;
+        if current.sequence < sequence || (current.sequence == sequence && current.id < *id) {
+            max_sequence = sequence;
             self.newest = from;
         } else {
             max_sequence = current.sequence;
@@ -174,11 +359,11 @@ impl<const MAX_NODES: usize, P: Clone> ChannelState<MAX_NODES, P> {
 
         let record_mut = self.nodes.get_mut(index).ok_or(ChannelError::Unreachable)?;
 
-        record_mut.sequence = message.sequence;
+        record_mut.sequence = sequence;
         record_mut.id = *id;
 
         if record_mut.first_sequence == 0 {
-            record_mut.first_sequence = message.sequence;
+            record_mut.first_sequence = sequence;
         }
 
         Ok(max_sequence)
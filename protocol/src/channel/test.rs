@@ -9,6 +9,22 @@ fn new_channel_state() -> Result<(), ChannelError> {
     Ok(())
 }
 
+#[test]
+fn rekey_epoch() -> Result<(), ChannelError> {
+    let key_pair = get_test_keys();
+    let mut state: ChannelState<3, RsaPublicKey> = ChannelState::new(NodeId::new(1), key_pair.public)?;
+    assert_eq!(state.epoch(), 0);
+
+    state.rekey(2);
+    assert_eq!(state.epoch(), 2);
+
+    // An older rekey does not take the channel back.
+    state.rekey(1);
+    assert_eq!(state.epoch(), 2);
+
+    Ok(())
+}
+
 #[test]
 fn address_envelope() -> Result<(), ChannelError> {
     let node1 = NodeId::new(1);
@@ -83,3 +99,68 @@ fn many_envelope() -> Result<(), ChannelError> {
 
     Ok(())
 }
+
+#[test]
+fn find_gaps() -> Result<(), ChannelError> {
+    let node1 = NodeId::new(1);
+    let node2 = NodeId::new(2);
+    let key_pair = get_test_keys();
+
+    let mut state: ChannelState<3, RsaPublicKey> =
+        ChannelState::new(node1, key_pair.public.clone())?;
+    state.add_node(node2, key_pair.public)?;
+
+    let message = Message {
+        cause: node1,
+        sender_last: 0,
+        sequence: 1,
+        data: 0,
+    };
+    assert!(state.find_gaps(node2, &message, 0)?.is_empty());
+
+    // Node 2 sent at 3 and 5 in reply to node 1 at 2 and 4, none of
+    // which arrived.
+    let message = Message {
+        cause: node1,
+        sender_last: 3,
+        sequence: 5,
+        data: 0,
+    };
+    assert!(matches!(
+        state.check_receive(node2, &message, &EnvelopeId::new(5)),
+        Err(ChannelError::MissingFromSender { .. })
+    ));
+    let gaps = state.find_gaps(node2, &message, 7)?;
+    assert_eq!(
+        gaps.as_slice(),
+        &[
+            Gap { node: node2, after: 0, until: 3, index: 7 },
+            Gap { node: node1, after: 0, until: 4, index: 7 },
+        ]
+    );
+    state.receive_over_gaps(node2, &message, &EnvelopeId::new(5))?;
+    assert_eq!(state.max_sequence()?, 5);
+
+    let replayed = Message {
+        cause: node1,
+        sender_last: 0,
+        sequence: 4,
+        data: 0,
+    };
+    assert!(matches!(
+        state.find_gaps(node2, &replayed, 0),
+        Err(ChannelError::AlreadyReceived)
+    ));
+    let backwards = Message {
+        cause: node2,
+        sender_last: 6,
+        sequence: 6,
+        data: 0,
+    };
+    assert!(matches!(
+        state.find_gaps(node2, &backwards, 0),
+        Err(ChannelError::BadSequence)
+    ));
+
+    Ok(())
+}
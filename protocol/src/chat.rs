@@ -3,20 +3,33 @@ use super::*;
 use heapless::FnvIndexMap;
 use heapless::String;
 
-const NAME_MAX: usize = 128;
+pub(crate) const NAME_MAX: usize = 128;
+
+/// Most removed nodes a chat remembers, the oldest removal is
+/// forgotten to make room. Snapshots from before it are still refused
+/// by their generation.
+pub const MAX_REMOVED: usize = 32;
 const CHAT_MAX: usize = 1024;
 
+/// Largest encrypted chat message, the text with its length and the
+/// tag.
+pub const CONFIDENTIAL_MAX: usize = CHAT_MAX + 8 + TAG_SIZE;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct NewChannel<P> {
     pub nonce: u128,
     pub name: String<NAME_MAX>,
     pub owner: P,
+    /// For a confidential channel its secret, wrapped to `owner`.
+    pub secret: Option<WrappedSecret>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct AddUser<P> {
     pub name: String<NAME_MAX>,
     pub key: P,
+    /// For a confidential channel its secret, wrapped to `key`.
+    pub secret: Option<WrappedSecret>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -24,11 +37,67 @@ pub struct ChatMessage {
     pub text: String<CHAT_MAX>,
 }
 
+/// A `ChatMessage` encrypted with the channel secret, see
+/// `Client::init_confidential_chat`. Only the members can read it but
+/// anyone holding the channel can check, store and pass it on.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Confidential {
+    /// The key epoch whose secret it was encrypted with.
+    pub epoch: u32,
+    pub body: Vec<u8, CONFIDENTIAL_MAX>,
+}
+
+/// The owner handing `member` the secret of a confidential channel's
+/// key epoch `epoch`, see `Client::rekey`. There is one for each
+/// member keeping access.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rekey {
+    pub epoch: u32,
+    pub member: NodeId,
+    /// The new secret, wrapped to `member`.
+    pub secret: WrappedSecret,
+}
+
+/// The owner taking `node` out of the channel, see
+/// `Client::remove_node`. Its envelopes are refused from then on.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RemoveUser {
+    pub node: NodeId,
+}
+
+/// A `ChatMessage` sent to the one member its envelope is addressed
+/// to, see `Client::send_direct`. It is encrypted under a key of its
+/// own that only that member and the sender are given, the other
+/// members store and pass it on without being able to read it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Direct {
+    /// The message key, wrapped to the recipient.
+    pub secret: WrappedSecret,
+    /// The message key, wrapped to the sender so it can read back what
+    /// it sent.
+    pub sender_secret: WrappedSecret,
+    pub body: Vec<u8, CONFIDENTIAL_MAX>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub enum Protocol<P> {
     AddUser(AddUser<P>),
     NewChannel(NewChannel<P>),
     ChatMessage(ChatMessage),
+    Confidential(Confidential),
+    Direct(Direct),
+    Rekey(Rekey),
+    RemoveUser(RemoveUser),
+}
+
+impl<P> Protocol<P> {
+    /// Whether this adds a message, rather than changing the channel.
+    pub fn is_message(&self) -> bool {
+        matches!(
+            self,
+            Protocol::ChatMessage(_) | Protocol::Confidential(_) | Protocol::Direct(_)
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -38,12 +107,23 @@ pub enum ChatError {
     Uninitlized,
     Unauthorized,
     Unreachable,
+    /// A `ChatMessage` sent in the clear to a confidential channel.
+    Unencrypted,
 }
 
 pub struct Chat<const MAX_USERS: usize, C: Crypto> {
     id: ChannelId,
     owner_id: Option<NodeId>,
     users: FnvIndexMap<NodeId, C::PubSigningKey, MAX_USERS>,
+    /// Taken out by the owner, kept so a membership snapshot from
+    /// before can't add them back.
+    removed: Vec<NodeId, MAX_REMOVED>,
+    /// Sequence of the newest membership change known, from the
+    /// owner's envelopes or a membership snapshot. Older snapshots are
+    /// refused.
+    generation: u64,
+    /// Started with a secret, so every chat message must be encrypted.
+    confidential: bool,
     message_count: u64,
     _phantom: PhantomData<C>,
 }
@@ -51,6 +131,8 @@ pub struct Chat<const MAX_USERS: usize, C: Crypto> {
 pub enum AcceptResult<C: Crypto> {
     AddUser(C::PubSigningKey),
     NewMessage(u64),
+    /// The channel moved on to this key epoch.
+    Rekey(u32),
     None,
 }
 
@@ -60,22 +142,122 @@ impl<'a, const MAX_USERS: usize, C: Crypto> Chat<MAX_USERS, C> {
             id,
             owner_id: None,
             users: FnvIndexMap::new(),
+            removed: Vec::new(),
+            generation: 0,
+            confidential: false,
             message_count: 0,
             _phantom: PhantomData::<C>,
         }
     }
 
+    /// Rebuild a chat saved by a checkpoint, users are added back
+    /// with `restore_user`.
+    pub fn restore(
+        id: ChannelId,
+        owner_id: Option<NodeId>,
+        confidential: bool,
+        message_count: u64,
+    ) -> Self {
+        Self {
+            id,
+            owner_id,
+            users: FnvIndexMap::new(),
+            removed: Vec::new(),
+            generation: 0,
+            confidential,
+            message_count,
+            _phantom: PhantomData::<C>,
+        }
+    }
+
+    pub fn restore_user(&mut self, key: &C::PubSigningKey) -> Result<NodeId, ChatError> {
+        self.add_user(key)
+    }
+
+    /// Take back a removal saved by a checkpoint or snapshot.
+    pub fn restore_removed(&mut self, node: NodeId) {
+        self.users.remove(&node);
+        if self.removed.contains(&node) {
+            return;
+        }
+        if self.removed.is_full() {
+            self.removed.remove(0);
+        }
+        // Room was made above.
+        let _ = self.removed.push(node);
+    }
+
+    pub fn removed(&self) -> &[NodeId] {
+        &self.removed
+    }
+
+    pub fn is_removed(&self, node: &NodeId) -> bool {
+        self.removed.contains(node)
+    }
+
+    /// Take the owner named by a membership snapshot, a chat only ever
+    /// has the one owner.
+    pub fn restore_owner(&mut self, owner_id: NodeId) -> Result<(), ChatError> {
+        match self.owner_id {
+            None => {
+                self.owner_id = Some(owner_id);
+                Ok(())
+            }
+            Some(current) if current == owner_id => Ok(()),
+            Some(_) => Err(ChatError::Unauthorized),
+        }
+    }
+
+    /// Carry on from a membership change at `generation`, an older
+    /// one changes nothing.
+    pub fn restore_generation(&mut self, generation: u64) {
+        self.generation = self.generation.max(generation);
+    }
+
+    /// Sequence of the newest membership change known, see
+    /// `MembershipHeader::generation`.
+    pub fn membership_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Take a confidential start from a membership snapshot, a chat
+    /// never stops being confidential.
+    pub fn restore_confidential(&mut self, confidential: bool) {
+        self.confidential |= confidential;
+    }
+
+    /// Whether the `NewChannel` that started the chat carried a
+    /// secret.
+    pub fn is_confidential(&self) -> bool {
+        self.confidential
+    }
+
+    /// Carry on counting from a log whose oldest messages were
+    /// evicted.
+    pub fn restore_message_count(&mut self, message_count: u64) {
+        self.message_count = message_count;
+    }
+
     pub fn message_count(&self) -> u64 {
         self.message_count
     }
 
+    pub fn owner_id(&self) -> Option<NodeId> {
+        self.owner_id
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &C::PubSigningKey> {
+        self.users.values()
+    }
+
     pub fn accept_message(
         &mut self,
         id: ChannelId,
         author: NodeId,
-        message: &Protocol<C::PubSigningKey>,
+        message: &Message<Protocol<C::PubSigningKey>>,
     ) -> Result<AcceptResult<C>, ChatError> {
-        match message {
+        let sequence = message.sequence();
+        match &message.data {
             Protocol::NewChannel(new_channel) => {
                 if id != self.id {
                     return Err(ChatError::UnexpectedId);
@@ -85,6 +267,8 @@ impl<'a, const MAX_USERS: usize, C: Crypto> Chat<MAX_USERS, C> {
                 // Do failable operation first.
                 let owner_id = self.add_user(key)?;
                 self.owner_id = Some(owner_id);
+                self.confidential = new_channel.secret.is_some();
+                self.restore_generation(sequence);
 
                 Ok(AcceptResult::None)
             }
@@ -97,13 +281,35 @@ impl<'a, const MAX_USERS: usize, C: Crypto> Chat<MAX_USERS, C> {
                     return Err(ChatError::Unauthorized);
                 }
 
-                self.add_user(&add_user.key)?;
+                // The owner can let a removed node back in.
+                let node = self.add_user(&add_user.key)?;
+                self.removed.retain(|removed| *removed != node);
+                self.restore_generation(sequence);
                 Ok(AcceptResult::AddUser(add_user.key.clone()))
             }
-            Protocol::ChatMessage(_chat_message) => {
+            Protocol::RemoveUser(remove_user) => {
+                if self.owner_id != Some(author) || author == remove_user.node {
+                    return Err(ChatError::Unauthorized);
+                }
+
+                self.restore_removed(remove_user.node);
+                self.restore_generation(sequence);
+                Ok(AcceptResult::None)
+            }
+            Protocol::Rekey(rekey) => {
+                if self.owner_id != Some(author) {
+                    return Err(ChatError::Unauthorized);
+                }
+
+                Ok(AcceptResult::Rekey(rekey.epoch))
+            }
+            Protocol::ChatMessage(_) | Protocol::Confidential(_) | Protocol::Direct(_) => {
                 if !self.users.contains_key(&author) {
                     return Err(ChatError::Unauthorized);
                 }
+                if self.confidential && matches!(message.data, Protocol::ChatMessage(_)) {
+                    return Err(ChatError::Unencrypted);
+                }
 
                 self.message_count = self
                     .message_count
@@ -0,0 +1,224 @@
+use super::*;
+
+/// Data records stored between the checkpoints `Client` writes.
+pub const CHECKPOINT_INTERVAL: u32 = 64;
+
+/// Largest serialized checkpoint part, one node key has to fit.
+const PART_MAX: usize = 1024;
+
+/// A checkpoint is stored as a `Start` record followed by exactly
+/// the `Node`, `User`, `Removed` and `Secret` records it promises, each in its own
+/// `RecordKind::Checkpoint` record so no single record has to hold
+/// every key. The storage root points at the `Start` of the last
+/// complete one, see `Storage::checkpoint`.
+// Parts only live long enough to be serialized, there is no heap to
+// box a `Secret` on.
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize)]
+pub enum CheckpointPart<P> {
+    Start {
+        newest: NodeId,
+        owner: Option<NodeId>,
+        /// See `Chat::is_confidential`.
+        confidential: bool,
+        /// See `Chat::membership_generation`.
+        generation: u64,
+        message_count: u64,
+        nodes: u32,
+        users: u32,
+        removed: u32,
+        /// Key epoch of a confidential channel.
+        epoch: u32,
+        secrets: u32,
+    },
+    Node(NodeSequence<P>),
+    User(P),
+    /// A node the owner removed, see `Chat::is_removed`.
+    Removed(NodeId),
+    /// A confidential channel's secret for `epoch`, wrapped to the
+    /// node that wrote the checkpoint.
+    Secret { epoch: u32, secret: WrappedSecret },
+}
+
+impl<const MAX_NODES: usize, I: IO, C: Crypto> Channel<MAX_NODES, I, C> {
+    /// Snapshot the channel state and chat counters in to storage.
+    pub(crate) fn write_checkpoint(&mut self, my_id: NodeId) -> Result<(), ClientError> {
+        // Written again so the list is not lost to eviction.
+        if !self.gaps.is_empty() {
+            self.storage.write_gaps(my_id, &self.gaps)?;
+        }
+
+        let mut target = [0u8; PART_MAX];
+        let max_sequence = self.state.max_sequence()?;
+        let message_count = self.chat.message_count();
+
+        let nodes = self.state.list_nodes();
+        let start: CheckpointPart<C::PubSigningKey> = CheckpointPart::Start {
+            newest: self.state.newest(),
+            owner: self.chat.owner_id(),
+            confidential: self.chat.is_confidential(),
+            generation: self.chat.membership_generation(),
+            message_count,
+            nodes: u32::try_from(nodes.len()).or(Err(ClientError::Unreachable))?,
+            users: u32::try_from(self.chat.users().count()).or(Err(ClientError::Unreachable))?,
+            removed: u32::try_from(self.chat.removed().len()).or(Err(ClientError::Unreachable))?,
+            epoch: self.state.epoch(),
+            secrets: u32::try_from(self.secrets.len()).or(Err(ClientError::Unreachable))?,
+        };
+        let bytes = to_slice(&start, target.as_mut_slice())?;
+        self.storage
+            .append_checkpoint(max_sequence, message_count, my_id, bytes)?;
+        let start = self.storage.last_cursor()?.ok_or(ClientError::Unreachable)?;
+
+        for node in nodes {
+            let part: CheckpointPart<C::PubSigningKey> = CheckpointPart::Node(node.clone());
+            let bytes = to_slice(&part, target.as_mut_slice())?;
+            self.storage
+                .append_checkpoint(max_sequence, message_count, my_id, bytes)?;
+        }
+
+        for key in self.chat.users() {
+            let part = CheckpointPart::User(key.clone());
+            let bytes = to_slice(&part, target.as_mut_slice())?;
+            self.storage
+                .append_checkpoint(max_sequence, message_count, my_id, bytes)?;
+        }
+
+        for node in self.chat.removed() {
+            let part: CheckpointPart<C::PubSigningKey> = CheckpointPart::Removed(*node);
+            let bytes = to_slice(&part, target.as_mut_slice())?;
+            self.storage
+                .append_checkpoint(max_sequence, message_count, my_id, bytes)?;
+        }
+
+        for secret in &self.secrets {
+            let part: CheckpointPart<C::PubSigningKey> = CheckpointPart::Secret {
+                epoch: secret.epoch(),
+                secret: secret.wrapped().clone(),
+            };
+            let bytes = to_slice(&part, target.as_mut_slice())?;
+            self.storage
+                .append_checkpoint(max_sequence, message_count, my_id, bytes)?;
+        }
+
+        // Only now is the checkpoint complete, one cut short by a crash
+        // never makes it in to the root.
+        let slab = start.slab();
+        self.storage.set_checkpoint(start)?;
+        self.since_checkpoint = 0;
+        self.checkpoint_slab = Some(slab);
+        Ok(())
+    }
+
+    /// Count a stored data record, checkpointing once enough have
+    /// built up since the last one or once eviction has dropped the
+    /// last one, as the records before it can no longer be replayed.
+    pub(crate) fn stored(&mut self, my_id: NodeId) {
+        self.quotas.stored();
+        self.since_checkpoint = self.since_checkpoint.saturating_add(1);
+        let evicted = match self.storage.io().get_head() {
+            Ok(head) => head > self.checkpoint_slab.unwrap_or(0),
+            Err(_) => false,
+        };
+        if (evicted || self.since_checkpoint >= CHECKPOINT_INTERVAL)
+            && self.write_checkpoint(my_id).is_err()
+        {
+            // The record is already stored so a failed checkpoint only
+            // costs a longer replay on the next open. Wait out another
+            // interval, or the eviction of the current last slab, rather
+            // than retrying on every store.
+            self.since_checkpoint = 0;
+            let io = self.storage.io();
+            if let (Ok(head), Ok(count)) = (io.get_head(), io.slab_count()) {
+                self.checkpoint_slab = Some(head.saturating_add(count.saturating_sub(1)));
+            }
+        }
+    }
+}
+
+/// Channel secrets restored from a checkpoint with their epochs,
+/// still wrapped.
+pub type WrappedSecrets = Vec<(u32, WrappedSecret), MAX_EPOCHS>;
+
+/// Channel state, chat and wrapped channel secrets restored from a
+/// checkpoint, with a cursor at the first record after it.
+pub type Restored<const MAX_NODES: usize, C> = (
+    ChannelState<MAX_NODES, <C as Crypto>::PubSigningKey>,
+    Chat<MAX_NODES, C>,
+    WrappedSecrets,
+    Cursor,
+);
+
+/// Rebuild the channel state and chat from the checkpoint starting
+/// at `cursor`, returning them with a cursor just past it.
+pub fn restore_checkpoint<const MAX_NODES: usize, I: IO, C: Crypto>(
+    storage: &Storage<I>,
+    cursor: Cursor,
+    channel_id: ChannelId,
+) -> Result<Restored<MAX_NODES, C>, ClientError> {
+    let mut buffer = I::Buffer::default();
+    let (record, mut cursor) = storage
+        .read_record(cursor, buffer.as_mut())?
+        .ok_or(ClientError::Unreachable)?;
+    let CheckpointPart::<C::PubSigningKey>::Start {
+        newest,
+        owner,
+        confidential,
+        generation,
+        message_count,
+        nodes,
+        users,
+        removed,
+        epoch,
+        secrets,
+    } = from_bytes(record.data())?
+    else {
+        return Err(ClientError::Unreachable);
+    };
+
+    let mut node_list = Vec::new();
+    let mut wrapped = WrappedSecrets::new();
+    let mut chat = Chat::<MAX_NODES, C>::restore(channel_id, owner, confidential, message_count);
+    chat.restore_generation(generation);
+
+    let mut parts = nodes
+        .checked_add(users)
+        .and_then(|parts| parts.checked_add(removed))
+        .and_then(|parts| parts.checked_add(secrets))
+        .ok_or(ClientError::Unreachable)?;
+    while parts > 0 {
+        let (record, next) = storage
+            .read_record(cursor, buffer.as_mut())?
+            .ok_or(ClientError::Unreachable)?;
+        cursor = next;
+        if record.kind() != RecordKind::Checkpoint {
+            continue;
+        }
+        parts -= 1;
+
+        match from_bytes::<CheckpointPart<C::PubSigningKey>>(record.data())? {
+            CheckpointPart::Node(node) => {
+                if node_list.push(node).is_err() {
+                    return Err(ChannelError::ClientMax(MAX_NODES).into());
+                }
+            }
+            CheckpointPart::User(key) => {
+                chat.restore_user(&key)?;
+            }
+            CheckpointPart::Removed(node) => {
+                chat.restore_removed(node);
+            }
+            CheckpointPart::Secret { epoch, secret } => {
+                if wrapped.push((epoch, secret)).is_err() {
+                    return Err(ClientError::Unreachable);
+                }
+            }
+            CheckpointPart::Start { .. } => return Err(ClientError::Unreachable),
+        }
+    }
+
+    let mut state = ChannelState::restore(newest, node_list)?;
+    state.rekey(epoch);
+
+    Ok((state, chat, wrapped, cursor))
+}
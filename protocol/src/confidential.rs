@@ -0,0 +1,180 @@
+use super::*;
+
+/// Most key epochs a node keeps the secret of, the oldest is dropped
+/// to make room so its messages can no longer be read.
+pub const MAX_EPOCHS: usize = 8;
+
+/// A confidential channel's secret for one key epoch, with the copy
+/// wrapped to this node's key that checkpoints carry so it outlives
+/// eviction of the record it came in.
+pub(crate) struct Secret {
+    epoch: u32,
+    key: ChannelSecret,
+    wrapped: WrappedSecret,
+}
+
+impl Secret {
+    pub(crate) fn new(epoch: u32, key: ChannelSecret, wrapped: WrappedSecret) -> Self {
+        Self {
+            epoch,
+            key,
+            wrapped,
+        }
+    }
+
+    pub(crate) fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    pub(crate) fn wrapped(&self) -> &WrappedSecret {
+        &self.wrapped
+    }
+}
+
+pub(crate) type Secrets = Vec<Secret, MAX_EPOCHS>;
+
+/// Keep `secret` in `secrets`, in place of any held for the same
+/// epoch.
+pub(crate) fn keep_secret(secrets: &mut Secrets, secret: Secret) {
+    secrets.retain(|held| held.epoch != secret.epoch);
+    if secrets.is_full() {
+        if let Some(oldest) = secrets.iter().map(Secret::epoch).min() {
+            secrets.retain(|held| held.epoch != oldest);
+        }
+    }
+    // Room was made above.
+    let _ = secrets.push(secret);
+}
+
+/// The secret `data` hands to this node, `NewChannel` hands it to the
+/// owner, `AddUser` to the added member, both for the current
+/// `epoch`, and `Rekey` to the member it names for the epoch it
+/// starts.
+pub(crate) fn offered_secret<C: Crypto>(
+    crypto: &C,
+    key_pair: &KeyPair<C::PrivateSigningKey, C::PubSigningKey>,
+    epoch: u32,
+    data: &Protocol<C::PubSigningKey>,
+) -> Result<Option<Secret>, ClientError> {
+    let my_id = C::compute_id(&key_pair.public);
+    let (member, epoch, wrapped) = match data {
+        Protocol::NewChannel(new_channel) => {
+            (C::compute_id(&new_channel.owner), epoch, &new_channel.secret)
+        }
+        Protocol::AddUser(add_user) => (C::compute_id(&add_user.key), epoch, &add_user.secret),
+        Protocol::Rekey(rekey) if rekey.member == my_id => {
+            let key = crypto.unwrap_secret(key_pair, &rekey.secret)?;
+            return Ok(Some(Secret::new(rekey.epoch, key, rekey.secret.clone())));
+        }
+        Protocol::ChatMessage(_)
+        | Protocol::Confidential(_)
+        | Protocol::Direct(_)
+        | Protocol::Rekey(_)
+        | Protocol::RemoveUser(_) => return Ok(None),
+    };
+
+    match wrapped {
+        Some(wrapped) if member == my_id => {
+            let key = crypto.unwrap_secret(key_pair, wrapped)?;
+            Ok(Some(Secret::new(epoch, key, wrapped.clone())))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// What a message's encryption is bound to besides the secret, so a
+/// member cannot pass off another's message as its own.
+pub(crate) fn body_context(channel_id: &ChannelId, from: NodeId, sequence: u64) -> [u8; 72] {
+    let mut context = [0u8; 72];
+    let (channel, rest) = context.split_at_mut(SHA256_SIZE);
+    let (node, sequence_bytes) = rest.split_at_mut(SHA256_SIZE);
+    channel.copy_from_slice(&channel_id.to_be_bytes());
+    node.copy_from_slice(&from.to_be_bytes());
+    sequence_bytes.copy_from_slice(&sequence.to_be_bytes());
+    context
+}
+
+impl<const MAX_NODES: usize, I: IO, C: Crypto> Channel<MAX_NODES, I, C> {
+    /// Whether the channel's messages are encrypted, as the
+    /// `NewChannel` that started it says. A member without the secret
+    /// can't send rather than sending in the clear.
+    pub(crate) fn is_confidential(&self) -> bool {
+        self.chat.is_confidential()
+    }
+
+    fn epoch_secret(&self, epoch: u32) -> Result<&Secret, ClientError> {
+        self.secrets
+            .iter()
+            .find(|secret| secret.epoch == epoch)
+            .ok_or(ClientError::NoChannelSecret)
+    }
+
+    /// The current epoch's secret wrapped for a member being added
+    /// with `key`, `None` unless the channel is confidential.
+    pub(crate) fn wrap_secret(
+        &self,
+        crypto: &mut C,
+        key: &C::PubSigningKey,
+    ) -> Result<Option<WrappedSecret>, ClientError> {
+        if !self.is_confidential() {
+            return Ok(None);
+        }
+        let secret = self.epoch_secret(self.state.epoch())?;
+        Ok(Some(crypto.wrap_secret(key, &secret.key)?))
+    }
+
+    /// In a confidential channel replace the chat message `message`
+    /// carries with its encryption under the current epoch's secret.
+    /// `from` and the sequence are left in the clear so every member
+    /// can still order it. A member left out of the last rekey can no
+    /// longer send.
+    pub(crate) fn encrypt_chat(
+        &self,
+        channel_id: &ChannelId,
+        from: NodeId,
+        message: &mut Message<Protocol<C::PubSigningKey>>,
+    ) -> Result<(), ClientError> {
+        let Protocol::ChatMessage(chat_message) = &message.data else {
+            return Ok(());
+        };
+        if !self.is_confidential() {
+            return Ok(());
+        }
+        let epoch = self.state.epoch();
+        let secret = self.epoch_secret(epoch)?;
+
+        let mut plain = [0u8; CONFIDENTIAL_MAX];
+        let plain = to_slice(chat_message, plain.as_mut_slice())?;
+        let context = body_context(channel_id, from, message.sequence());
+        let mut target = [0u8; CONFIDENTIAL_MAX];
+        let sealed = encrypt(&secret.key, &context, plain, &mut target)?;
+
+        let body = Vec::from_slice(sealed).or(Err(ClientError::MessageToLarge))?;
+        message.data = Protocol::Confidential(Confidential { epoch, body });
+        Ok(())
+    }
+
+    /// The chat message `message` from `from` carries, decrypted with
+    /// the secret of the epoch it was sent in if the channel is
+    /// confidential.
+    pub(crate) fn read_chat(
+        &self,
+        channel_id: &ChannelId,
+        from: NodeId,
+        message: Message<Protocol<C::PubSigningKey>>,
+    ) -> Result<ChatMessage, ClientError> {
+        let sequence = message.sequence();
+        let confidential = match message.data {
+            Protocol::ChatMessage(chat_message) => return Ok(chat_message),
+            Protocol::Confidential(confidential) => confidential,
+            _ => return Err(ClientError::Unreachable),
+        };
+        let secret = self.epoch_secret(confidential.epoch)?;
+
+        let context = body_context(channel_id, from, sequence);
+        let mut target = [0u8; CONFIDENTIAL_MAX];
+        let plain = decrypt(&secret.key, &context, &confidential.body, &mut target)?;
+
+        Ok(from_bytes(plain)?)
+    }
+}
@@ -6,6 +6,11 @@ pub const SHA256_SIZE: usize = 32; //bytes
 pub const RSA_KEY_SIZE: usize = 256; //bytes
 
 pub mod rust;
+pub mod secret;
+pub use secret::*;
+// The tests run against both backends.
+#[cfg(any(test, feature = "ed25519"))]
+pub mod ed25519;
 
 #[derive(Hash, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Id {
@@ -154,6 +159,8 @@ pub enum CryptoError {
     MaxEnvelope,
     MaxSig,
     VerifyError,
+    /// Sealed with a different secret, or tampered with.
+    DecryptError,
 }
 
 impl From<postcard::Error> for CryptoError {
@@ -208,8 +215,49 @@ pub trait Crypto {
         sealed_envelope: &SealedEnvelope<T, MAX_ENVELOPE, MAX_SIG>,
     ) -> Result<Message<T>, CryptoError>;
 
+    /// Sign `data` with `key_pair` writing the signature to the
+    /// start of `target` and returning its length.
+    fn sign(
+        &self,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        data: &[u8],
+        target: &mut [u8],
+    ) -> Result<usize, CryptoError>;
+
+    /// Check a signature made by `sign`.
+    fn verify(
+        &self,
+        key: &Self::PubSigningKey,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CryptoError>;
+
     fn nonce(&mut self) -> u128;
 
+    /// A fresh secret for a confidential channel or a direct message.
+    fn make_channel_secret(&mut self) -> ChannelSecret {
+        let mut secret = [0u8; SECRET_SIZE];
+        let (first, second) = secret.split_at_mut(SECRET_SIZE / 2);
+        first.copy_from_slice(&self.nonce().to_be_bytes());
+        second.copy_from_slice(&self.nonce().to_be_bytes());
+        secret
+    }
+
+    /// Encrypt `secret` so only the holder of the private half of
+    /// `key` can read it.
+    fn wrap_secret(
+        &mut self,
+        key: &Self::PubSigningKey,
+        secret: &ChannelSecret,
+    ) -> Result<WrappedSecret, CryptoError>;
+
+    /// Read a secret wrapped to `key_pair` by `wrap_secret`.
+    fn unwrap_secret(
+        &self,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        wrapped: &[u8],
+    ) -> Result<ChannelSecret, CryptoError>;
+
     fn make_signing_keys(
         &mut self,
     ) -> Result<KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>, CryptoError>;
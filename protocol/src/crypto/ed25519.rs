@@ -0,0 +1,269 @@
+use super::*;
+
+use rand_chacha::rand_core::SeedableRng;
+pub use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use curve25519_dalek::MontgomeryPoint;
+use ed25519_dalek::Signer;
+
+use hkdf::Hkdf;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+
+use rand::Rng;
+
+/// HKDF info for the keys secrets are wrapped with.
+const WRAP_INFO: &[u8] = b"finder wrapped secret";
+
+/// Ed25519 signatures are 64 bytes.
+pub const ED25519_SIG_SIZE: usize = ed25519_dalek::SIGNATURE_LENGTH;
+
+/// An Ed25519 public key as its 32 compressed bytes. Channels keep a
+/// key for every node, a `VerifyingKey` also holds the decompressed
+/// point and is six times the size, so it is only built to verify.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    /// Fails unless `bytes` are a point on the curve.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Result<Self, CryptoError> {
+        let key = VerifyingKey::from_bytes(bytes).or(Err(CryptoError::VerifyError))?;
+        Ok(key.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    fn verifying_key(&self) -> Result<VerifyingKey, CryptoError> {
+        VerifyingKey::from_bytes(&self.0).or(Err(CryptoError::VerifyError))
+    }
+}
+
+impl From<VerifyingKey> for PublicKey {
+    fn from(key: VerifyingKey) -> Self {
+        Self(key.to_bytes())
+    }
+}
+
+impl From<&SigningKey> for PublicKey {
+    fn from(key: &SigningKey) -> Self {
+        key.verifying_key().into()
+    }
+}
+
+/// A `Crypto` backend using Ed25519, its 32 byte keys and 64 byte
+/// signatures leave room for a message in a single radio frame.
+pub struct Ed25519Crypto {
+    rng: ChaCha20Rng,
+}
+
+impl Ed25519Crypto {
+    pub fn new(seed_bytes: &[u8]) -> Result<Self, CryptoError> {
+        // BUG: we should make a salt for this use
+        let hk = Hkdf::<Sha256>::new(None, seed_bytes);
+        let mut seed = [0u8; 32];
+        if hk.expand("'nonce seed".as_bytes(), &mut seed).is_err() {
+            return Err(CryptoError::Unreachable);
+        }
+
+        let seed: <ChaCha20Rng as SeedableRng>::Seed = seed;
+        let rng = ChaCha20Rng::from_seed(seed);
+
+        Ok(Self { rng })
+    }
+}
+
+impl Crypto for Ed25519Crypto {
+    type PubSigningKey = PublicKey;
+    type PrivateSigningKey = SigningKey;
+
+    fn compute_id(key: &Self::PubSigningKey) -> NodeId {
+        let mut hasher = Sha256::new();
+        hasher.update(key.as_bytes());
+        let arr: [u8; 32] = hasher.finalize().into();
+        NodeId::new(arr)
+    }
+
+    fn envelope_id<T, const MAX_ENVELOPE: usize, const MAX_SIG: usize>(
+        &self,
+        sealed: &SealedEnvelope<T, MAX_ENVELOPE, MAX_SIG>,
+    ) -> EnvelopeId {
+        let mut hasher = Sha256::new();
+        hasher.update(sealed.from.to_be_bytes());
+        hasher.update(sealed.to.to_be_bytes());
+        hasher.update(&sealed.serialized);
+        hasher.update(&sealed.signature);
+        let arr: [u8; 32] = hasher.finalize().into();
+        EnvelopeId::new(arr)
+    }
+
+    fn seal<T: Serialize, const MAX_ENVELOPE: usize, const MAX_SIG: usize>(
+        &self,
+        from: NodeId,
+        to: Recipient,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        message: &Message<T>,
+        target: &mut [u8],
+    ) -> Result<SealedEnvelope<T, MAX_ENVELOPE, MAX_SIG>, CryptoError> {
+        let serialized = to_slice(message, target)?;
+        let message_hash = envelope_hash(from, to, serialized);
+
+        let signature = key_pair.private.sign(&message_hash);
+
+        let result = SealedEnvelope::new(from, to, serialized, &signature.to_bytes())?;
+
+        Ok(result)
+    }
+
+    fn open<T: DeserializeOwned + Serialize, const MAX_ENVELOPE: usize, const MAX_SIG: usize>(
+        &self,
+        key: &Self::PubSigningKey,
+        sealed_envelope: &SealedEnvelope<T, MAX_ENVELOPE, MAX_SIG>,
+    ) -> Result<Message<T>, CryptoError> {
+        let message_hash = envelope_hash(
+            sealed_envelope.from,
+            sealed_envelope.to,
+            &sealed_envelope.serialized,
+        );
+
+        let Ok(signature) = Signature::from_slice(&sealed_envelope.signature) else {
+            return Err(CryptoError::InternalError);
+        };
+
+        key.verifying_key()?
+            .verify_strict(&message_hash, &signature)
+            .or(Err(CryptoError::VerifyError))?;
+
+        let opened = from_bytes(&sealed_envelope.serialized)?;
+
+        Ok(opened)
+    }
+
+    fn sign(
+        &self,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        data: &[u8],
+        target: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        let data_hash: [u8; 32] = Sha256::digest(data).into();
+
+        let sig_bytes = key_pair.private.sign(&data_hash).to_bytes();
+        let sig_target = target
+            .get_mut(..sig_bytes.len())
+            .ok_or(CryptoError::MaxSig)?;
+        sig_target.copy_from_slice(&sig_bytes);
+
+        Ok(sig_bytes.len())
+    }
+
+    fn verify(
+        &self,
+        key: &Self::PubSigningKey,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CryptoError> {
+        let data_hash: [u8; 32] = Sha256::digest(data).into();
+
+        let Ok(signature) = Signature::from_slice(signature) else {
+            return Err(CryptoError::VerifyError);
+        };
+
+        key.verifying_key()?
+            .verify_strict(&data_hash, &signature)
+            .or(Err(CryptoError::VerifyError))
+    }
+
+    fn nonce(&mut self) -> u128 {
+        self.rng.gen()
+    }
+
+    /// The secret is encrypted under a key agreed between a one off
+    /// X25519 key and the X25519 form of `key`, and stored after the
+    /// one off public key.
+    fn wrap_secret(
+        &mut self,
+        key: &Self::PubSigningKey,
+        secret: &ChannelSecret,
+    ) -> Result<WrappedSecret, CryptoError> {
+        let ephemeral: [u8; 32] = self.rng.gen();
+        let ephemeral_public = MontgomeryPoint::mul_base_clamped(ephemeral);
+        let shared = key.verifying_key()?.to_montgomery().mul_clamped(ephemeral);
+        let context = wrap_context(&ephemeral_public, key);
+        let wrap_key = wrap_key(&shared, &context)?;
+
+        let mut wrapped = WrappedSecret::new();
+        wrapped
+            .extend_from_slice(ephemeral_public.as_bytes())
+            .or(Err(CryptoError::MaxEnvelope))?;
+        let mut target = [0u8; SECRET_SIZE + TAG_SIZE];
+        let sealed = encrypt(&wrap_key, &context, secret, &mut target)?;
+        wrapped
+            .extend_from_slice(sealed)
+            .or(Err(CryptoError::MaxEnvelope))?;
+
+        Ok(wrapped)
+    }
+
+    fn unwrap_secret(
+        &self,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        wrapped: &[u8],
+    ) -> Result<ChannelSecret, CryptoError> {
+        let (ephemeral_public, sealed) = wrapped
+            .split_first_chunk::<32>()
+            .ok_or(CryptoError::DecryptError)?;
+        let ephemeral_public = MontgomeryPoint(*ephemeral_public);
+        let shared = ephemeral_public.mul_clamped(key_pair.private.to_scalar_bytes());
+        let context = wrap_context(&ephemeral_public, &key_pair.public);
+        let wrap_key = wrap_key(&shared, &context)?;
+
+        let mut secret = [0u8; SECRET_SIZE];
+        decrypt(&wrap_key, &context, sealed, &mut secret)?;
+        Ok(secret)
+    }
+
+    fn make_signing_keys(
+        &mut self,
+    ) -> Result<KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>, CryptoError> {
+        let secret: [u8; 32] = self.rng.gen();
+        let private = SigningKey::from_bytes(&secret);
+        let public = PublicKey::from(&private);
+
+        Ok(KeyPair { private, public })
+    }
+
+    fn channel_id_from_bytes(&self, data: &[u8]) -> ChannelId {
+        let arr: [u8; 32] = Sha256::digest(data).into();
+        ChannelId::new(arr)
+    }
+}
+
+/// The hash an envelope's signature covers, the same as `RustCrypto`
+/// signs.
+fn envelope_hash(from: NodeId, to: Recipient, serialized: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(from.to_be_bytes());
+    hasher.update(to.to_be_bytes());
+    hasher.update(serialized);
+    hasher.finalize().into()
+}
+
+/// Binds a wrapped secret to both public keys.
+fn wrap_context(ephemeral: &MontgomeryPoint, key: &PublicKey) -> [u8; 64] {
+    let mut context = [0u8; 64];
+    let (first, second) = context.split_at_mut(32);
+    first.copy_from_slice(ephemeral.as_bytes());
+    second.copy_from_slice(key.as_bytes());
+    context
+}
+
+fn wrap_key(shared: &MontgomeryPoint, context: &[u8]) -> Result<ChannelSecret, CryptoError> {
+    let mut key = [0u8; SECRET_SIZE];
+    Hkdf::<Sha256>::new(Some(context), shared.as_bytes())
+        .expand(WRAP_INFO, &mut key)
+        .or(Err(CryptoError::Unreachable))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+pub mod test;
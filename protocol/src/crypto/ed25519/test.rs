@@ -0,0 +1,169 @@
+use super::*;
+use crate::chat::{AddUser, Protocol};
+
+const SECRET: [u8; 32] = [7; 32];
+
+pub fn get_test_keys() -> KeyPair<SigningKey, PublicKey> {
+    let private = SigningKey::from_bytes(&SECRET);
+    let public = PublicKey::from(&private);
+    KeyPair { private, public }
+}
+
+#[test]
+fn test_sign_verify() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let crypto = Ed25519Crypto::new(&seed)?;
+    let key_pair = get_test_keys();
+
+    let node1 = NodeId::new(1);
+    let node2 = NodeId::new(2);
+    let to = Recipient::Node(node2);
+
+    let mut state: ChannelState<3, PublicKey> =
+        ChannelState::new(node1, key_pair.public)?;
+
+    let envelope = state.address(node1, 0)?;
+
+    let mut target = [0u8; 4000];
+    let sealed_envelope: SealedEnvelope<i32, 1025, ED25519_SIG_SIZE> =
+        crypto.seal(node1, to, &key_pair, &envelope, &mut target)?;
+
+    let opened = crypto.open(&key_pair.public, &sealed_envelope)?;
+    assert_eq!(envelope, opened);
+
+    let other = PublicKey::from(&SigningKey::from_bytes(&[8; 32]));
+    assert!(crypto.open(&other, &sealed_envelope).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_sign_verify_bytes() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let crypto = Ed25519Crypto::new(&seed)?;
+    let key_pair = get_test_keys();
+
+    let data = b"some bytes to sign";
+    let mut target = [0u8; ED25519_SIG_SIZE];
+    let len = crypto.sign(&key_pair, data, &mut target)?;
+    let signature = &target[..len];
+
+    crypto.verify(&key_pair.public, data, signature)?;
+    assert!(crypto
+        .verify(&key_pair.public, b"some other bytes", signature)
+        .is_err());
+
+    let mut short = [0u8; ED25519_SIG_SIZE - 1];
+    assert!(crypto.sign(&key_pair, data, &mut short).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_envlope_id() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let crypto = Ed25519Crypto::new(&seed)?;
+    let key_pair = get_test_keys();
+
+    let node1 = NodeId::new(1);
+    let node2 = NodeId::new(2);
+    let to = Recipient::Node(node2);
+
+    let mut state: ChannelState<3, PublicKey> =
+        ChannelState::new(node1, key_pair.public)?;
+
+    let envelope = state.address(node1, 0)?;
+
+    let mut target = [0u8; 4000];
+    let sealed_envelope: SealedEnvelope<i32, 1025, ED25519_SIG_SIZE> =
+        crypto.seal(node1, to, &key_pair, &envelope, &mut target)?;
+
+    assert_eq!(
+        crypto.envelope_id(&sealed_envelope),
+        crypto.envelope_id(&sealed_envelope)
+    );
+
+    state.add_node(node2, key_pair.public)?;
+
+    let envelope2 = state.address(node2, 0)?;
+    let sealed_envelope2: SealedEnvelope<i32, 1025, ED25519_SIG_SIZE> =
+        crypto.seal(node2, to, &key_pair, &envelope2, &mut target)?;
+
+    assert_ne!(
+        crypto.envelope_id(&sealed_envelope),
+        crypto.envelope_id(&sealed_envelope2)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_make_keys() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = Ed25519Crypto::new(&seed)?;
+    let key_pair = crypto.make_signing_keys()?;
+    let key_pair2 = crypto.make_signing_keys()?;
+
+    assert_eq!(PublicKey::from(&key_pair.private), key_pair.public);
+    assert_ne!(
+        Ed25519Crypto::compute_id(&key_pair.public),
+        Ed25519Crypto::compute_id(&key_pair2.public)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_add_user_fits_frame() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = Ed25519Crypto::new(&seed)?;
+    let key_pair = get_test_keys();
+    let added = crypto.make_signing_keys()?;
+
+    let node1 = Ed25519Crypto::compute_id(&key_pair.public);
+    let mut state: ChannelState<3, PublicKey> =
+        ChannelState::new(node1, key_pair.public)?;
+
+    let add_user = Protocol::AddUser(AddUser {
+        name: "A name".try_into().or(Err(ClientError::Unreachable))?,
+        key: added.public,
+        secret: None,
+    });
+    let envelope = state.address(node1, add_user)?;
+
+    let to = Recipient::Channel(crypto.channel_id_from_bytes(b"channel"));
+    let mut target = [0u8; 4000];
+    let sealed_envelope: SealedEnvelope<Protocol<PublicKey>, 1025, ED25519_SIG_SIZE> =
+        crypto.seal(node1, to, &key_pair, &envelope, &mut target)?;
+
+    let mut frame = [0u8; 4000];
+    let bytes = to_slice(&sealed_envelope, &mut frame)?;
+    // An ESP-Now frame.
+    assert!(bytes.len() < 250);
+
+    Ok(())
+}
+
+#[test]
+fn test_wrap_secret() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = Ed25519Crypto::new(&seed)?;
+    let key_pair = get_test_keys();
+    let other = crypto.make_signing_keys()?;
+
+    let secret = crypto.make_channel_secret();
+    let wrapped = crypto.wrap_secret(&key_pair.public, &secret)?;
+    assert_eq!(crypto.unwrap_secret(&key_pair, &wrapped)?, secret);
+
+    // Only the key it was wrapped to can read it.
+    assert!(crypto.unwrap_secret(&other, &wrapped).is_err());
+    assert!(!wrapped.windows(secret.len()).any(|window| window == secret));
+
+    let mut tampered = wrapped.clone();
+    if let Some(last) = tampered.last_mut() {
+        *last ^= 1;
+    }
+    assert!(crypto.unwrap_secret(&key_pair, &tampered).is_err());
+
+    Ok(())
+}
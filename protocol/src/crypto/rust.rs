@@ -2,6 +2,7 @@ use super::*;
 
 use rand_chacha::rand_core::SeedableRng;
 use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::Oaep;
 use rsa::pkcs8::{EncodePrivateKey, EncodePublicKey};
 use rsa::signature::Verifier;
 pub use rsa::RsaPrivateKey;
@@ -98,20 +99,7 @@ impl Crypto for RustCrypto {
             unimplemented!()
         };
 
-        let encoded = key_pair.private.to_pkcs8_der()?;
-
-        // BUG: we should make a salt for this use
-        let hk = Hkdf::<Sha256>::new(None, encoded.as_bytes());
-        let mut seed = [0u8; 32];
-        if hk.expand(&message_hash, &mut seed).is_err() {
-            return Err(CryptoError::Unreachable);
-        }
-
-        let seed: <ChaCha20Rng as SeedableRng>::Seed = seed;
-        let mut rng = ChaCha20Rng::from_seed(seed);
-
-        let signing_key = SigningKey::<Sha256>::new(key_pair.private.clone());
-        let signature = signing_key.sign_with_rng(&mut rng, &message_hash);
+        let signature = sign_hash(key_pair, &message_hash)?;
 
         let sig_bytes = signature.to_bytes();
         let sig_ref = sig_bytes.as_ref();
@@ -143,10 +131,71 @@ impl Crypto for RustCrypto {
         Ok(opened)
     }
 
+    fn sign(
+        &self,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        data: &[u8],
+        target: &mut [u8],
+    ) -> Result<usize, CryptoError> {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let data_hash: [u8; 32] = hasher.finalize().into();
+
+        let signature = sign_hash(key_pair, &data_hash)?;
+
+        let sig_bytes = signature.to_bytes();
+        let sig_target = target
+            .get_mut(..sig_bytes.len())
+            .ok_or(CryptoError::MaxSig)?;
+        sig_target.copy_from_slice(&sig_bytes);
+
+        Ok(sig_bytes.len())
+    }
+
+    fn verify(
+        &self,
+        key: &Self::PubSigningKey,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CryptoError> {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        let data_hash = hasher.finalize();
+
+        let verifying_key = VerifyingKey::<Sha256>::new(key.clone());
+        let Ok(signature) = Signature::try_from(signature) else {
+            return Err(CryptoError::VerifyError);
+        };
+
+        verifying_key.verify(&data_hash, &signature)?;
+
+        Ok(())
+    }
+
     fn nonce(&mut self) -> u128 {
         self.rng.gen()
     }
 
+    fn wrap_secret(
+        &mut self,
+        key: &Self::PubSigningKey,
+        secret: &ChannelSecret,
+    ) -> Result<WrappedSecret, CryptoError> {
+        let wrapped = key.encrypt(&mut self.rng, Oaep::new::<Sha256>(), secret)?;
+        Vec::from_slice(&wrapped).or(Err(CryptoError::MaxEnvelope))
+    }
+
+    fn unwrap_secret(
+        &self,
+        key_pair: &KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>,
+        wrapped: &[u8],
+    ) -> Result<ChannelSecret, CryptoError> {
+        let Ok(secret) = key_pair.private.decrypt(Oaep::new::<Sha256>(), wrapped) else {
+            return Err(CryptoError::DecryptError);
+        };
+        secret.as_slice().try_into().or(Err(CryptoError::DecryptError))
+    }
+
     fn make_signing_keys(
         &mut self,
     ) -> Result<KeyPair<Self::PrivateSigningKey, Self::PubSigningKey>, CryptoError> {
@@ -176,5 +225,27 @@ impl Crypto for RustCrypto {
     }
 }
 
+/// Sign `hash` with a signing RNG derived from the key and the hash so
+/// the same input always produces the same signature.
+fn sign_hash(
+    key_pair: &KeyPair<RsaPrivateKey, RsaPublicKey>,
+    hash: &[u8; 32],
+) -> Result<Signature, CryptoError> {
+    let encoded = key_pair.private.to_pkcs8_der()?;
+
+    // BUG: we should make a salt for this use
+    let hk = Hkdf::<Sha256>::new(None, encoded.as_bytes());
+    let mut seed = [0u8; 32];
+    if hk.expand(hash, &mut seed).is_err() {
+        return Err(CryptoError::Unreachable);
+    }
+
+    let seed: <ChaCha20Rng as SeedableRng>::Seed = seed;
+    let mut rng = ChaCha20Rng::from_seed(seed);
+
+    let signing_key = SigningKey::<Sha256>::new(key_pair.private.clone());
+    Ok(signing_key.sign_with_rng(&mut rng, hash))
+}
+
 #[cfg(test)]
 pub mod test;
@@ -78,6 +78,25 @@ fn test_sign_verify() -> Result<(), ClientError> {
     Ok(())
 }
 
+#[test]
+fn test_sign_verify_bytes() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let crypto = RustCrypto::new(&seed)?;
+    let key_pair = get_test_keys();
+
+    let data = b"some bytes to sign";
+    let mut target = [0u8; SIG_SIZE];
+    let len = crypto.sign(&key_pair, data, &mut target)?;
+    let signature = &target[..len];
+
+    crypto.verify(&key_pair.public, data, signature)?;
+    assert!(crypto
+        .verify(&key_pair.public, b"some other bytes", signature)
+        .is_err());
+
+    Ok(())
+}
+
 #[test]
 fn test_envlope_id() -> Result<(), ClientError> {
     let seed = [0; 128];
@@ -135,3 +154,23 @@ fn test_nonce() -> Result<(), CryptoError> {
 
     Ok(())
 }
+
+#[test]
+fn test_wrap_secret() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = RustCrypto::new(&seed)?;
+    let key_pair = get_test_keys();
+
+    let secret = crypto.make_channel_secret();
+    let wrapped = crypto.wrap_secret(&key_pair.public, &secret)?;
+    assert_eq!(wrapped.len(), RSA_KEY_SIZE);
+    assert_eq!(crypto.unwrap_secret(&key_pair, &wrapped)?, secret);
+
+    let mut tampered = wrapped.clone();
+    if let Some(last) = tampered.last_mut() {
+        *last ^= 1;
+    }
+    assert!(crypto.unwrap_secret(&key_pair, &tampered).is_err());
+
+    Ok(())
+}
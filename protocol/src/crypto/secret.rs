@@ -0,0 +1,76 @@
+use super::*;
+
+use aes_gcm_siv::aead::{AeadInPlace, KeyInit};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, Tag};
+use sha2::{Digest, Sha256};
+
+pub const SECRET_SIZE: usize = 32;
+
+/// Largest wrapped secret, one RSA-OAEP block.
+pub const WRAPPED_MAX: usize = RSA_KEY_SIZE;
+
+/// Bytes `encrypt` adds to what it is given.
+pub const TAG_SIZE: usize = 16;
+
+/// Key the members of a confidential channel encrypt its messages
+/// with.
+pub type ChannelSecret = [u8; SECRET_SIZE];
+
+/// A `ChannelSecret` only one node can read, see `Crypto::wrap_secret`.
+pub type WrappedSecret = Vec<u8, WRAPPED_MAX>;
+
+/// Encrypt `plain` with AES-GCM-SIV under `secret` in to the start of
+/// `target`, which needs `TAG_SIZE` more bytes than `plain`.
+///
+/// `context` is authenticated but not stored. The nonce is taken from
+/// it so it should not repeat under one secret, GCM-SIV only leaks
+/// that two messages matched if it does.
+pub fn encrypt<'a>(
+    secret: &ChannelSecret,
+    context: &[u8],
+    plain: &[u8],
+    target: &'a mut [u8],
+) -> Result<&'a [u8], CryptoError> {
+    let len = plain
+        .len()
+        .checked_add(TAG_SIZE)
+        .ok_or(CryptoError::MaxEnvelope)?;
+    let sealed = target.get_mut(..len).ok_or(CryptoError::MaxEnvelope)?;
+    let (body, tag_target) = sealed.split_at_mut(plain.len());
+    body.copy_from_slice(plain);
+
+    let tag = Aes256GcmSiv::new(secret.into())
+        .encrypt_in_place_detached(&context_nonce(context), context, body)
+        .or(Err(CryptoError::InternalError))?;
+    tag_target.copy_from_slice(&tag);
+
+    Ok(sealed)
+}
+
+/// Decrypt what `encrypt` made with the same `secret` and `context`
+/// in to the start of `target`.
+pub fn decrypt<'a>(
+    secret: &ChannelSecret,
+    context: &[u8],
+    sealed: &[u8],
+    target: &'a mut [u8],
+) -> Result<&'a [u8], CryptoError> {
+    let len = sealed
+        .len()
+        .checked_sub(TAG_SIZE)
+        .ok_or(CryptoError::DecryptError)?;
+    let (body, tag) = sealed.split_at(len);
+    let plain = target.get_mut(..len).ok_or(CryptoError::MaxEnvelope)?;
+    plain.copy_from_slice(body);
+
+    Aes256GcmSiv::new(secret.into())
+        .decrypt_in_place_detached(&context_nonce(context), context, plain, Tag::from_slice(tag))
+        .or(Err(CryptoError::DecryptError))?;
+
+    Ok(plain)
+}
+
+fn context_nonce(context: &[u8]) -> Nonce {
+    let hash = Sha256::digest(context);
+    Nonce::clone_from_slice(&hash[..12])
+}
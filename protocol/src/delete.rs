@@ -0,0 +1,53 @@
+use super::*;
+
+impl<const MAX_NODES: usize, I: IO, C: Crypto> Channel<MAX_NODES, I, C> {
+    /// The record stored for message `index`, read in to `buffer`,
+    /// failing with `MessageDeleted` if it has been deleted.
+    pub(crate) fn message_record<'b>(&self, index: u64, buffer: &'b mut [u8]) -> Result<Record<'b>, ClientError> {
+        let mut cursor = self
+            .storage
+            .get_cursor_from_index(index)?
+            .ok_or(ClientError::MessageIndexOutOfBounds)?;
+
+        // Find the record's cursor first, returning a record read in
+        // to `buffer` from inside the loop would hold the borrow.
+        loop {
+            let (record, next) = self
+                .storage
+                .read_record(cursor.clone(), buffer)?
+                .ok_or(ClientError::Unreachable)?;
+
+            match record.kind() {
+                RecordKind::Data if !self.is_deleted(&record) => break,
+                RecordKind::Data | RecordKind::Deleted => return Err(ClientError::MessageDeleted),
+                _ => cursor = next,
+            }
+        }
+
+        let (record, _) = self
+            .storage
+            .read_record(cursor, buffer)?
+            .ok_or(ClientError::Unreachable)?;
+        Ok(record)
+    }
+
+    /// Whether a tombstone waiting for compaction names `record`.
+    pub(crate) fn is_deleted(&self, record: &Record<'_>) -> bool {
+        self.deleted.iter().any(|tombstone| tombstone.names(record))
+    }
+
+    /// Move the channel on to `storage`, leaving deleted envelopes
+    /// behind, returning the storage it was using.
+    pub(crate) fn compact(&mut self, my_id: NodeId, mut storage: Storage<I>) -> Result<I, ClientError> {
+        storage.set_retention(self.storage.retention());
+        storage.set_compression(self.storage.compression());
+        self.storage.compact_into(&mut storage)?;
+
+        let old = core::mem::replace(&mut self.storage, storage);
+        self.deleted.clear();
+        // The old checkpoints were left behind.
+        self.write_checkpoint(my_id)?;
+
+        Ok(old.into_io())
+    }
+}
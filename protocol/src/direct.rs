@@ -0,0 +1,91 @@
+use super::*;
+
+impl<const MAX_NODES: usize, I: IO, C: Crypto> Channel<MAX_NODES, I, C> {
+    /// Replace the chat message `message` carries with a `Direct`
+    /// only `recipient` and `from` can read, under a key made for it
+    /// alone.
+    pub(crate) fn encrypt_direct(
+        &self,
+        crypto: &mut C,
+        channel_id: &ChannelId,
+        from: NodeId,
+        recipient: NodeId,
+        message: &mut Message<Protocol<C::PubSigningKey>>,
+    ) -> Result<(), ClientError> {
+        let Protocol::ChatMessage(chat_message) = &message.data else {
+            return Err(ClientError::Unreachable);
+        };
+        let key = self.state.get_node_key(recipient)?;
+        let sender_key = self.state.get_node_key(from)?;
+
+        let message_key = crypto.make_channel_secret();
+        let secret = crypto.wrap_secret(&key, &message_key)?;
+        let sender_secret = crypto.wrap_secret(&sender_key, &message_key)?;
+
+        let mut plain = [0u8; CONFIDENTIAL_MAX];
+        let plain = to_slice(chat_message, plain.as_mut_slice())?;
+        let context = body_context(channel_id, from, message.sequence());
+        let mut target = [0u8; CONFIDENTIAL_MAX];
+        let sealed = encrypt(&message_key, &context, plain, &mut target)?;
+
+        let body = Vec::from_slice(sealed).or(Err(ClientError::MessageToLarge))?;
+        message.data = Protocol::Direct(Direct {
+            secret,
+            sender_secret,
+            body,
+        });
+        Ok(())
+    }
+}
+
+/// Check `to` is where an envelope carrying `data` belongs, a member
+/// for a `Direct` and the channel for everything else.
+pub(crate) fn check_recipient<const MAX_NODES: usize, P: Clone>(
+    state: &ChannelState<MAX_NODES, P>,
+    channel_id: &ChannelId,
+    to: Recipient,
+    data: &Protocol<P>,
+) -> Result<(), ClientError> {
+    match (to, data) {
+        (Recipient::Node(node), Protocol::Direct(_)) if state.get_node_key(node).is_ok() => Ok(()),
+        (Recipient::Channel(id), Protocol::NewChannel(_))
+        | (Recipient::Channel(id), Protocol::AddUser(_))
+        | (Recipient::Channel(id), Protocol::ChatMessage(_))
+        | (Recipient::Channel(id), Protocol::Confidential(_))
+        | (Recipient::Channel(id), Protocol::Rekey(_))
+        | (Recipient::Channel(id), Protocol::RemoveUser(_))
+            if id == *channel_id =>
+        {
+            Ok(())
+        }
+        _ => Err(ClientError::WrongRecipient),
+    }
+}
+
+/// Decrypt a direct message `from` sent to `to`, one of which has to
+/// be the node `key_pair` belongs to.
+pub(crate) fn read_direct<C: Crypto>(
+    crypto: &C,
+    key_pair: &KeyPair<C::PrivateSigningKey, C::PubSigningKey>,
+    channel_id: &ChannelId,
+    from: NodeId,
+    to: Recipient,
+    sequence: u64,
+    direct: &Direct,
+) -> Result<ChatMessage, ClientError> {
+    let me = C::compute_id(&key_pair.public);
+    let wrapped = if to == Recipient::Node(me) {
+        &direct.secret
+    } else if from == me {
+        &direct.sender_secret
+    } else {
+        return Err(ClientError::NotRecipient);
+    };
+    let message_key = crypto.unwrap_secret(key_pair, wrapped)?;
+
+    let context = body_context(channel_id, from, sequence);
+    let mut target = [0u8; CONFIDENTIAL_MAX];
+    let plain = decrypt(&message_key, &context, &direct.body, &mut target)?;
+
+    Ok(from_bytes(plain)?)
+}
@@ -0,0 +1,79 @@
+use super::*;
+
+impl<const MAX_NODES: usize, I: IO, C: Crypto> Channel<MAX_NODES, I, C> {
+    /// Track `found`, merging it with the gaps it overlaps.
+    pub(crate) fn add_gaps(&mut self, my_id: NodeId, found: &[Gap]) -> Result<(), ClientError> {
+        if found.is_empty() {
+            return Ok(());
+        }
+
+        for gap in found {
+            let mut merged = *gap;
+            self.gaps.retain(|known| {
+                if !known.overlaps(&merged) {
+                    return true;
+                }
+                merged.after = merged.after.min(known.after);
+                merged.until = merged.until.max(known.until);
+                merged.index = merged.index.min(known.index);
+                false
+            });
+
+            if self.gaps.is_full() {
+                self.gaps.remove(0);
+            }
+            self.gaps.push(merged).or(Err(ClientError::Unreachable))?;
+        }
+
+        self.storage.write_gaps(my_id, &self.gaps)?;
+        Ok(())
+    }
+
+    /// Take the envelope `from` sent at `sequence` out of the gap it was
+    /// missing from. Only its envelopes after `sender_last` are known
+    /// not to be missing, so what the gap held up to `sender_last` stays
+    /// a gap of its own.
+    pub(crate) fn fill_gaps(
+        &mut self,
+        my_id: NodeId,
+        from: NodeId,
+        sender_last: u64,
+        sequence: u64,
+    ) -> Result<(), ClientError> {
+        // Gaps are merged as they are added, so only one can hold it.
+        let Some(pos) = self
+            .gaps
+            .iter()
+            .position(|gap| gap.node == from && gap.after < sequence && sequence <= gap.until)
+        else {
+            return Ok(());
+        };
+
+        let gap = self.gaps.remove(pos);
+        let before = Gap {
+            until: sender_last,
+            ..gap
+        };
+        let after = Gap {
+            after: sequence,
+            ..gap
+        };
+        let parts = [
+            (sender_last > gap.after).then_some(before),
+            (sequence < gap.until).then_some(after),
+        ];
+
+        let mut at = pos;
+        for part in parts.into_iter().flatten() {
+            if self.gaps.is_full() {
+                self.gaps.remove(0);
+                at = at.saturating_sub(1);
+            }
+            self.gaps.insert(at, part).or(Err(ClientError::Unreachable))?;
+            at += 1;
+        }
+
+        self.storage.write_gaps(my_id, &self.gaps)?;
+        Ok(())
+    }
+}
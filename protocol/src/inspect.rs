@@ -0,0 +1,427 @@
+//! Decoding of raw storage images for the `finder-inspect` tool.
+
+extern crate std;
+use std::collections::BTreeMap;
+use std::format;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use core::fmt;
+
+use super::*;
+
+/// Slab size of the storage image `image`, read from its `DbInfo`.
+pub fn slab_size(image: &[u8]) -> Result<usize, StorageError> {
+    Ok(layout(image)?.0)
+}
+
+/// Write size of the media the storage image `image` was taken from.
+pub fn write_size(image: &[u8]) -> Result<usize, StorageError> {
+    Ok(layout(image)?.1)
+}
+
+fn layout(image: &[u8]) -> Result<(usize, usize), StorageError> {
+    let info: DbInfo = from_bytes(image).or(Err(StorageError::CorruptDB))?;
+    let slab_size = usize::try_from(info.slab_size).or(Err(StorageError::CorruptDB))?;
+    let write_size = usize::try_from(info.write_size).or(Err(StorageError::CorruptDB))?;
+    info.validate(slab_size, write_size)?;
+    Ok((slab_size, write_size))
+}
+
+/// Whether an envelope's signature checked out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Signature {
+    Valid,
+    Invalid,
+    /// No key is known for the sender.
+    UnknownKey,
+    /// Checks out against a key only a checkpoint vouches for.
+    /// Checkpoints are not signed, so a tampered image can forge these.
+    CheckpointKey,
+}
+
+/// What an envelope carried.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type")]
+pub enum Body {
+    NewChannel { name: String, owner: String },
+    AddUser { name: String, node: String },
+    Chat { text: String },
+    /// An encrypted chat message, only its size is shown.
+    Confidential { epoch: u32, bytes: usize },
+    /// An encrypted direct message, see `EnvelopeEntry::to` for who
+    /// it was sent to.
+    Direct { bytes: usize },
+    /// A new key epoch's secret, wrapped to `member`.
+    Rekey { epoch: u32, member: String },
+    RemoveUser { node: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnvelopeEntry {
+    pub id: String,
+    pub to: String,
+    pub cause: String,
+    pub sender_last: u64,
+    pub signature: Signature,
+    pub body: Body,
+}
+
+/// One stored record.
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub slab: usize,
+    /// Position of the record in its slab.
+    pub record: u32,
+    /// `None` when the record could not be read, the rest of its slab
+    /// is skipped.
+    pub kind: Option<RecordKind>,
+    pub max_sequence: u64,
+    pub message_count: u64,
+    pub sequence: u64,
+    pub sender: String,
+    pub compressed: bool,
+    pub envelope: Option<EnvelopeEntry>,
+    /// Whatever else was decoded, or why decoding failed.
+    pub detail: String,
+}
+
+/// Walks a storage image checking envelopes against the keys it knows.
+///
+/// Keys come from `add_key`, the owner named by a `NewChannel`
+/// envelope signed with that key and `AddUser` envelopes whose
+/// signature checked out. Keys found in checkpoints, or added by
+/// envelopes signed with one, are only used to report
+/// `Signature::CheckpointKey`.
+pub struct Inspector<'a, C: Crypto> {
+    crypto: &'a C,
+    keys: BTreeMap<NodeId, C::PubSigningKey>,
+    checkpoint_keys: BTreeMap<NodeId, C::PubSigningKey>,
+}
+
+impl<'a, C: Crypto> Inspector<'a, C> {
+    pub fn new(crypto: &'a C) -> Self {
+        Self {
+            crypto,
+            keys: BTreeMap::new(),
+            checkpoint_keys: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_key(&mut self, key: C::PubSigningKey) {
+        self.keys.insert(C::compute_id(&key), key);
+    }
+
+    /// Every record in `storage`, oldest first.
+    pub fn timeline<I: IO>(&mut self, storage: &Storage<I>) -> Result<Vec<Entry>, StorageError> {
+        // Checkpoints hold keys whose envelopes may be gone.
+        self.walk(storage, |inspector, found| {
+            let Ok((_, _, record)) = found else {
+                return;
+            };
+            if record.kind() == RecordKind::Checkpoint {
+                if let Ok(CheckpointPart::Node(node)) = from_bytes(record.data()) {
+                    inspector.checkpoint_keys.insert(node.node, node.public_key);
+                }
+            }
+        })?;
+
+        let mut entries = Vec::new();
+        let mut expanded = [0u8; COMPRESS_MAX];
+        self.walk(storage, |inspector, found| {
+            let entry = match found {
+                Ok((slab, index, record)) => {
+                    let mut entry = Entry {
+                        slab,
+                        record: index,
+                        kind: Some(record.kind()),
+                        max_sequence: record.max_sequence(),
+                        message_count: record.message_count(),
+                        sequence: record.sequence(),
+                        sender: hex(&record.sender().to_be_bytes()),
+                        compressed: record.is_compressed(),
+                        envelope: None,
+                        detail: String::new(),
+                    };
+                    match inspector.detail(&record, &mut expanded) {
+                        Ok((envelope, detail)) => {
+                            entry.envelope = envelope;
+                            entry.detail = detail;
+                        }
+                        Err(e) => entry.detail = format!("undecodable: {:?}", e),
+                    }
+                    entry
+                }
+                Err((slab, index, e)) => Entry {
+                    slab,
+                    record: index,
+                    kind: None,
+                    max_sequence: 0,
+                    message_count: 0,
+                    sequence: 0,
+                    sender: String::new(),
+                    compressed: false,
+                    envelope: None,
+                    detail: format!("damaged: {:?}", e),
+                },
+            };
+            entries.push(entry);
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Hand each record, or where a slab could not be read, to `visit`.
+    #[allow(clippy::type_complexity)]
+    fn walk<I: IO>(
+        &mut self,
+        storage: &Storage<I>,
+        mut visit: impl FnMut(
+            &mut Self,
+            Result<(usize, u32, Record<'_>), (usize, u32, StorageError)>,
+        ),
+    ) -> Result<(), StorageError> {
+        let io = storage.io();
+        let head = io.get_head()?;
+        for index in head..head.saturating_add(io.slab_count()?) {
+            let slab = match io.get_slab(index) {
+                Ok(slab) => slab,
+                Err(e) => {
+                    visit(self, Err((index, 0, e)));
+                    continue;
+                }
+            };
+
+            let mut buffer = I::Buffer::default();
+            let mut cursor = slab.get_head();
+            let mut count = 0;
+            loop {
+                match slab.read(cursor, buffer.as_mut()) {
+                    Ok(Some((record, next))) => {
+                        visit(self, Ok((index, count, record)));
+                        count += 1;
+                        cursor = next;
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        visit(self, Err((index, count, e)));
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn detail(
+        &mut self,
+        record: &Record<'_>,
+        expanded: &mut [u8],
+    ) -> Result<(Option<EnvelopeEntry>, String), ClientError> {
+        let detail = match record.kind() {
+            RecordKind::Data => {
+                let envelope = self.envelope(record.decompress(expanded)?)?;
+                return Ok((Some(envelope), String::new()));
+            }
+            RecordKind::Checkpoint => {
+                match from_bytes::<CheckpointPart<C::PubSigningKey>>(record.data())? {
+                    CheckpointPart::Start {
+                        message_count,
+                        nodes,
+                        users,
+                        ..
+                    } => format!(
+                        "checkpoint start, {} messages {} nodes {} users",
+                        message_count, nodes, users
+                    ),
+                    CheckpointPart::Node(node) => format!(
+                        "checkpoint node {} at {}",
+                        short(&node.node.to_be_bytes()),
+                        node.sequence
+                    ),
+                    CheckpointPart::User(key) => {
+                        format!("checkpoint user {}", short(&C::compute_id(&key).to_be_bytes()))
+                    }
+                    CheckpointPart::Removed(node) => {
+                        format!("checkpoint removed {}", short(&node.to_be_bytes()))
+                    }
+                    CheckpointPart::Secret { epoch, .. } => {
+                        format!("checkpoint secret for epoch {}", epoch)
+                    }
+                }
+            }
+            RecordKind::Membership => format!("membership snapshot part, {} bytes", record.data().len()),
+            RecordKind::Tombstone | RecordKind::Deleted => {
+                let tombstone: Tombstone = from_bytes(record.data())?;
+                format!(
+                    "deletes {} at {}",
+                    short(&tombstone.sender.to_be_bytes()),
+                    tombstone.sequence
+                )
+            }
+            RecordKind::Gaps => {
+                let gaps: Gaps = from_bytes(record.data())?;
+                let ranges: Vec<String> = gaps
+                    .iter()
+                    .map(|gap| {
+                        format!(
+                            "{} {}..={}",
+                            short(&gap.node.to_be_bytes()),
+                            gap.after + 1,
+                            gap.until
+                        )
+                    })
+                    .collect();
+                format!("gaps [{}]", ranges.join(", "))
+            }
+        };
+
+        Ok((None, detail))
+    }
+
+    fn envelope(&mut self, bytes: &[u8]) -> Result<EnvelopeEntry, ClientError> {
+        let sealed: SealedEnvelope<Protocol<C::PubSigningKey>, MAX_ENVELOPE, MAX_SIG> =
+            from_bytes(bytes)?;
+        // Decoded whether or not the signature checks out.
+        let message: Message<Protocol<C::PubSigningKey>> = from_bytes(&sealed.serialized)?;
+
+        let from = sealed.from();
+        let cause = message.cause();
+        let sender_last = message.sender_last();
+        let key = match (self.keys.get(&from), &message.data) {
+            (Some(key), _) => Some(key.clone()),
+            (None, Protocol::NewChannel(new)) if C::compute_id(&new.owner) == from => {
+                Some(new.owner.clone())
+            }
+            _ => None,
+        };
+        let signature = match (key, self.checkpoint_keys.get(&from)) {
+            (Some(key), _) => match self.crypto.open(&key, &sealed) {
+                Ok(_) => {
+                    self.keys.insert(from, key);
+                    Signature::Valid
+                }
+                Err(_) => Signature::Invalid,
+            },
+            (None, Some(key)) => match self.crypto.open(key, &sealed) {
+                Ok(_) => Signature::CheckpointKey,
+                Err(_) => Signature::Invalid,
+            },
+            (None, None) => Signature::UnknownKey,
+        };
+
+        let body = match message.data {
+            Protocol::NewChannel(new) => Body::NewChannel {
+                name: new.name.to_string(),
+                owner: hex(&C::compute_id(&new.owner).to_be_bytes()),
+            },
+            Protocol::AddUser(add) => {
+                let node = C::compute_id(&add.key);
+                match signature {
+                    Signature::Valid => {
+                        self.keys.insert(node, add.key);
+                    }
+                    // No more trusted than the key it was signed with.
+                    Signature::CheckpointKey => {
+                        self.checkpoint_keys.insert(node, add.key);
+                    }
+                    Signature::Invalid | Signature::UnknownKey => {}
+                }
+                Body::AddUser {
+                    name: add.name.to_string(),
+                    node: hex(&node.to_be_bytes()),
+                }
+            }
+            Protocol::ChatMessage(chat) => Body::Chat {
+                text: chat.text.to_string(),
+            },
+            Protocol::Confidential(confidential) => Body::Confidential {
+                epoch: confidential.epoch,
+                bytes: confidential.body.len(),
+            },
+            Protocol::Direct(direct) => Body::Direct {
+                bytes: direct.body.len(),
+            },
+            Protocol::Rekey(rekey) => Body::Rekey {
+                epoch: rekey.epoch,
+                member: hex(&rekey.member.to_be_bytes()),
+            },
+            Protocol::RemoveUser(remove) => Body::RemoveUser {
+                node: hex(&remove.node.to_be_bytes()),
+            },
+        };
+
+        let to = match sealed.to {
+            Recipient::Node(node) => format!("node {}", hex(&node.to_be_bytes())),
+            Recipient::Channel(channel) => format!("channel {}", hex(&channel.to_be_bytes())),
+        };
+
+        Ok(EnvelopeEntry {
+            id: hex(&self.crypto.envelope_id(&sealed).to_be_bytes()),
+            to,
+            cause: hex(&cause.to_be_bytes()),
+            sender_last,
+            signature,
+            body,
+        })
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>4}.{:<3} ", self.slab, self.record)?;
+        let Some(kind) = self.kind else {
+            return f.write_str(&self.detail);
+        };
+
+        write!(
+            f,
+            "{:<10} seq {:<5} max {:<5} msg {:<5} {}",
+            format!("{:?}", kind),
+            self.sequence,
+            self.max_sequence,
+            self.message_count,
+            self.sender.get(..8).unwrap_or(&self.sender),
+        )?;
+        if self.compressed {
+            f.write_str(" lzss")?;
+        }
+
+        if let Some(envelope) = &self.envelope {
+            write!(f, " {:?} ", envelope.signature)?;
+            match &envelope.body {
+                Body::NewChannel { name, .. } => write!(f, "new channel {:?}", name)?,
+                Body::AddUser { name, node } => {
+                    write!(f, "add user {:?} {}", name, node.get(..8).unwrap_or(node))?
+                }
+                Body::Chat { text } => write!(f, "{:?}", text)?,
+                Body::Confidential { epoch, bytes } => {
+                    write!(f, "encrypted, epoch {}, {} bytes", epoch, bytes)?
+                }
+                Body::Direct { bytes } => write!(f, "direct, encrypted, {} bytes", bytes)?,
+                Body::Rekey { epoch, member } => {
+                    write!(f, "rekey epoch {} for {}", epoch, member.get(..8).unwrap_or(member))?
+                }
+                Body::RemoveUser { node } => write!(f, "remove user {}", node.get(..8).unwrap_or(node))?,
+            }
+        }
+        if !self.detail.is_empty() {
+            write!(f, " {}", self.detail)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Enough of an id to tell nodes apart in a timeline.
+fn short(bytes: &[u8]) -> String {
+    hex(bytes.get(..4).unwrap_or(bytes))
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,150 @@
+use super::*;
+
+use crypto::ed25519::Ed25519Crypto;
+use crypto::rust::{test::get_test_keys, RustCrypto};
+use crate::test::runner::TestCrypto;
+use storage::mem_io::MemIO;
+
+const SLAB_SIZE: usize = 1024;
+
+#[test]
+fn test_timeline() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = RustCrypto::new(&seed)?;
+    let key_pair = get_test_keys();
+    let owner = hex(&RustCrypto::compute_id(&key_pair.public).to_be_bytes());
+    let mut data = std::vec![0u8; 16 * SLAB_SIZE];
+
+    {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = std::boxed::Box::new(ClientChannels::<4, 8, _, RustCrypto>::new());
+        let mut client = Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Inspected", io)?;
+        client.send_message(&channel_id, "first")?;
+        client.set_compression(&channel_id, true)?;
+        client.send_message(&channel_id, &"again ".repeat(20))?;
+        client.checkpoint(&channel_id)?;
+        client.delete_local(&channel_id, 1)?;
+    }
+
+    assert_eq!(inspect::slab_size(&data)?, SLAB_SIZE);
+    assert!(inspect::slab_size(&[0xFF; 64]).is_err());
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+    let storage = Storage::new(io);
+    let mut inspector = Inspector::new(&crypto);
+    let entries = inspector.timeline(&storage)?;
+
+    let envelopes: std::vec::Vec<&EnvelopeEntry> =
+        entries.iter().filter_map(|entry| entry.envelope.as_ref()).collect();
+    assert_eq!(envelopes.len(), 3);
+    // The owner's key comes from the channel's first envelope.
+    assert!(envelopes
+        .iter()
+        .all(|envelope| envelope.signature == Signature::Valid));
+    assert_eq!(
+        envelopes[0].body,
+        Body::NewChannel {
+            name: "Inspected".into(),
+            owner: owner.clone(),
+        }
+    );
+    assert_eq!(envelopes[1].body, Body::Chat { text: "first".into() });
+    assert!(entries.iter().any(|entry| entry.compressed));
+    assert!(entries
+        .iter()
+        .any(|entry| entry.kind == Some(RecordKind::Checkpoint)));
+    assert!(entries
+        .iter()
+        .any(|entry| entry.kind == Some(RecordKind::Tombstone)));
+    assert!(entries.iter().all(|entry| entry.kind.is_some()));
+
+    // A timeline line names the record and what it holds.
+    let line = entries
+        .iter()
+        .find(|entry| entry.envelope.as_ref().map(|e| &e.body) == Some(&Body::Chat { text: "first".into() }))
+        .map(|entry| entry.to_string())
+        .expect("expected the message");
+    assert!(line.contains("Data"));
+    assert!(line.contains("Valid"));
+    assert!(line.contains("\"first\""));
+
+    Ok(())
+}
+
+#[test]
+fn test_checkpoint_keys_unverified() -> Result<(), ClientError> {
+    checkpoint_keys_unverified::<RustCrypto>()
+}
+
+#[test]
+fn test_checkpoint_keys_unverified_ed25519() -> Result<(), ClientError> {
+    checkpoint_keys_unverified::<Ed25519Crypto>()
+}
+
+fn checkpoint_keys_unverified<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let mut forger_crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let forger = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let mut data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut forger_data = std::vec![0u8; 16 * SLAB_SIZE];
+
+    {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = std::boxed::Box::new(ClientChannels::<4, 8, _, C>::new());
+        let mut client = Client::new(key_pair.clone(), &mut crypto, &mut channels);
+        let channel_id = client.init_chat("Inspected", io)?;
+        client.send_message(&channel_id, "first")?;
+    }
+    {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut forger_data)?;
+        let mut channels = std::boxed::Box::new(ClientChannels::<4, 8, _, C>::new());
+        let mut client = Client::new(forger.clone(), &mut forger_crypto, &mut channels);
+        let channel_id = client.init_chat("Elsewhere", io)?;
+        client.send_message(&channel_id, "forged")?;
+    }
+
+    // Slip the forger's key in to a checkpoint and their message after it.
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut forger_data)?;
+    let source = Storage::new(io);
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+    let mut storage = Storage::new(io);
+
+    let forger_id = C::compute_id(&forger.public);
+    let state: ChannelState<1, _> = ChannelState::new(forger_id, forger.public.clone())?;
+    let node = state.list_nodes()[0].clone();
+    let part: CheckpointPart<C::PubSigningKey> = CheckpointPart::Node(node);
+    let mut target = [0u8; SLAB_SIZE];
+    let bytes = to_slice(&part, target.as_mut_slice())?;
+    storage.append_checkpoint(10, 10, forger_id, bytes)?;
+
+    let cursor = source.get_cursor_from_index(1)?.expect("expected the message");
+    let mut buffer = [0u8; SLAB_SIZE];
+    let (record, _) = source
+        .read_record(cursor, &mut buffer)?
+        .expect("expected the message");
+    storage.append(11, 11, record.sequence(), forger_id, record.data())?;
+
+    let mut inspector = Inspector::new(&crypto);
+    let entries = inspector.timeline(&storage)?;
+    let forged = entries
+        .iter()
+        .filter_map(|entry| entry.envelope.as_ref())
+        .find(|envelope| envelope.body == Body::Chat { text: "forged".into() })
+        .expect("expected the forged message");
+    assert_eq!(forged.signature, Signature::CheckpointKey);
+
+    // Unless the key is known some other way.
+    let mut inspector = Inspector::new(&crypto);
+    inspector.add_key(forger.public.clone());
+    let entries = inspector.timeline(&storage)?;
+    assert!(entries
+        .iter()
+        .filter_map(|entry| entry.envelope.as_ref())
+        .all(|envelope| envelope.signature == Signature::Valid));
+
+    Ok(())
+}
@@ -1,9 +1,9 @@
 #![no_std]
 
-use core::{marker::PhantomData, mem::size_of};
+use core::{marker::PhantomData, mem::size_of, ops::Range};
 use heapless::{FnvIndexMap, String, Vec};
 
-use postcard::{from_bytes, to_slice};
+use postcard::{from_bytes, take_from_bytes, to_slice};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 pub mod channel;
@@ -15,6 +15,28 @@ use storage::*;
 pub mod chat;
 use chat::*;
 
+pub mod checkpoint;
+use checkpoint::*;
+
+pub mod membership;
+use membership::*;
+
+pub mod quota;
+use quota::*;
+
+pub mod delete;
+
+pub mod gap;
+
+pub mod confidential;
+use confidential::*;
+
+pub mod direct;
+use direct::*;
+
+pub mod onboarding;
+use onboarding::*;
+
 pub mod crypto;
 use crypto::*;
 
@@ -26,6 +48,9 @@ use sync::*;
 
 pub mod wire;
 
+#[cfg(any(test, feature = "std"))]
+pub mod inspect;
+
 #[cfg(test)]
 mod test;
 
@@ -36,6 +61,7 @@ pub enum ClientError {
     CryptoError(CryptoError),
     ChatError(ChatError),
     StorageError(StorageError),
+    OnboardingError(OnboardingError),
     ChannelLimit,
     Unreachable,
     StringTooLarge,
@@ -43,6 +69,24 @@ pub enum ClientError {
     MessageToLarge,
     SafeStaticError,
     MessageIndexOutOfBounds,
+    /// The sender has used up its quota in the channel.
+    QuotaExceeded(NodeId),
+    /// The message was deleted with `Client::delete_local`.
+    MessageDeleted,
+    /// `MAX_TOMBSTONES` deletes are waiting for `Client::compact`.
+    CompactionNeeded,
+    /// Only chat messages can be deleted.
+    NotDeletable,
+    /// The message is encrypted and this device has not been given
+    /// the channel secret.
+    NoChannelSecret,
+    /// The message is a direct message between other nodes.
+    NotRecipient,
+    /// The envelope is addressed somewhere its contents do not belong.
+    WrongRecipient,
+    /// The membership snapshot is older than a membership change
+    /// already known.
+    StaleMembership,
 }
 
 impl From<GuardCellError> for ClientError {
@@ -81,10 +125,43 @@ impl From<StorageError> for ClientError {
     }
 }
 
+impl From<OnboardingError> for ClientError {
+    fn from(value: OnboardingError) -> Self {
+        ClientError::OnboardingError(value)
+    }
+}
+
+/// Add a node named by an `AddUser` message, it may already be known
+/// from the membership snapshot.
+fn add_member<const MAX_NODES: usize, P: Clone>(
+    state: &mut ChannelState<MAX_NODES, P>,
+    node_id: NodeId,
+    key: P,
+) -> Result<(), ClientError> {
+    match state.add_node(node_id, key) {
+        Ok(()) | Err(ChannelError::NodeExists) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub struct Channel<const MAX_NODES: usize, I: IO, C: Crypto> {
     state: ChannelState<MAX_NODES, C::PubSigningKey>,
     storage: Storage<I>,
     chat: Chat<MAX_NODES, C>,
+    /// Data records stored since the last checkpoint.
+    since_checkpoint: u32,
+    /// Slab the last checkpoint starts in, once eviction passes it a
+    /// new one is written.
+    checkpoint_slab: Option<usize>,
+    quotas: Quotas<MAX_NODES>,
+    /// Tombstones not yet compacted away, their envelopes are still
+    /// stored but are no longer shown or sent.
+    deleted: Tombstones,
+    /// Envelopes known to be missing, see `Client::gaps`.
+    gaps: Gaps,
+    /// The secrets of a confidential channel's key epochs this node
+    /// has been given.
+    secrets: Secrets,
 }
 
 pub struct ClientChannels<const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto> {
@@ -258,11 +335,22 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
 
         let mut offset = 0;
         let mut count = 0;
+        let mut record_buffer = I::Buffer::default();
+        let mut expanded = [0u8; COMPRESS_MAX];
 
-        while let Some((data, next)) = channel.storage.read(cursor)? {
+        while let Some((record, next)) = channel
+            .storage
+            .read_record(cursor.clone(), record_buffer.as_mut())?
+        {
             // BUG: need to see if this is a message they need and update the `state`
             // Right now this will send them things they may not need
 
+            if record.kind() != RecordKind::Data || channel.is_deleted(&record) {
+                cursor = next;
+                continue;
+            }
+            let data = record.decompress(&mut expanded)?;
+
             if (buffer.len() - offset) < (data.len() + LEN_SIZE) {
                 break;
             }
@@ -292,6 +380,7 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
     ) -> Result<(), ClientError> {
         let mut offset = 0;
         let buffer = buffer;
+        let mut skipped: Vec<NodeId, MAX_NODES> = Vec::new();
         for _ in 0..count {
             let len: u32;
             (len, offset) = read_u32(buffer, offset)?;
@@ -302,9 +391,22 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
                 // error and I think all this code should move in the the sync mod.
                 .ok_or(ClientError::Unreachable)?;
             offset = end;
+
+            // The sender leads the envelope. Once one of its envelopes
+            // is over quota, or refused as it was removed or sent in
+            // the clear to a confidential channel, the rest would only
+            // look like gaps.
+            let (from, _): (NodeId, _) = take_from_bytes(envelope_bytes)?;
+            if skipped.contains(&from) {
+                continue;
+            }
             match self.do_receive(channel_id, envelope_bytes) {
                 Ok(_) => (),
                 Err(ClientError::ChannelError(ChannelError::AlreadyReceived)) => (),
+                Err(ClientError::QuotaExceeded(_))
+                | Err(ClientError::ChatError(ChatError::Unauthorized | ChatError::Unencrypted)) => {
+                    skipped.push(from).or(Err(ClientError::Unreachable))?;
+                }
                 Err(err) => return Err(err),
             }
         }
@@ -328,7 +430,28 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
 
         let data: Protocol<C::PubSigningKey> = Protocol::ChatMessage(ChatMessage { text });
 
-        self.do_send(channel_id, data)?;
+        self.do_send(channel_id, Recipient::Channel(*channel_id), data)?;
+
+        Ok(())
+    }
+
+    /// Send `msg` to the member `to` alone. It is stored and synced
+    /// with the rest of the channel, and takes its place in the
+    /// message count, but only `to` and the sender can read it,
+    /// `get_message` gives everyone else `ClientError::NotRecipient`.
+    pub fn send_direct(
+        &mut self,
+        channel_id: &ChannelId,
+        to: NodeId,
+        msg: &str,
+    ) -> Result<(), ClientError> {
+        let Ok(text) = String::try_from(msg) else {
+            return Err(ClientError::MessageToLarge);
+        };
+
+        let data: Protocol<C::PubSigningKey> = Protocol::ChatMessage(ChatMessage { text });
+
+        self.do_send(channel_id, Recipient::Node(to), data)?;
 
         Ok(())
     }
@@ -343,25 +466,77 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
             .get(channel_id)
             .ok_or(ClientError::UnknownChannel)?;
 
-        let cursor = channel
-            .storage
-            .get_cursor_from_index(index)?
-            .ok_or(ClientError::MessageIndexOutOfBounds)?;
+        let mut buffer = I::Buffer::default();
+        let record = channel.message_record(index, buffer.as_mut())?;
+        self.read_message(channel, channel_id, &record)?
+            .ok_or(ClientError::Unreachable)
+    }
+
+    /// The messages whose index is in `range`, oldest first, skipping
+    /// those evicted or deleted and the envelopes that change the
+    /// channel rather than add a message. Each is read like
+    /// `get_message`.
+    pub fn messages(
+        &self,
+        channel_id: &ChannelId,
+        range: Range<u64>,
+    ) -> Result<impl Iterator<Item = Result<(u64, ChatMessage), ClientError>> + '_, ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
 
-        let (bytes, _cursor) = channel
+        let channel_id = *channel_id;
+        let mut buffer = I::Buffer::default();
+        let found = channel
             .storage
-            .read(cursor)?
-            .ok_or(ClientError::Unreachable)?;
+            .iter_range(range.start, range.end)?
+            .skip_deleted(&channel.deleted);
+        Ok(found.filter_map(move |found| {
+            let read = found.map_err(ClientError::from).and_then(|found| {
+                let (record, _) = channel
+                    .storage
+                    .read_record(found.cursor, buffer.as_mut())?
+                    .ok_or(ClientError::Unreachable)?;
+                let message = self.read_message(channel, &channel_id, &record)?;
+                Ok(message.map(|message| (found.message_count, message)))
+            });
+            read.transpose()
+        }))
+    }
+
+    /// Open the envelope stored in `record`, `None` if it does not
+    /// hold a message.
+    fn read_message(
+        &self,
+        channel: &Channel<MAX_NODES, I, C>,
+        channel_id: &ChannelId,
+        record: &Record<'_>,
+    ) -> Result<Option<ChatMessage>, ClientError> {
+        let mut expanded = [0u8; COMPRESS_MAX];
+        let bytes = record.decompress(&mut expanded)?;
 
         let envelope: SealedEnvelope<Protocol<C::PubSigningKey>, MAX_ENVELOPE, MAX_SIG> =
             from_bytes(bytes)?;
         let key = channel.state.get_node_key(envelope.from)?;
         let message = self.crypto.open(&key, &envelope)?;
-        let Protocol::ChatMessage(message) = message.data else {
-            return Err(ClientError::Unreachable);
-        };
 
-        Ok(message)
+        match &message.data {
+            Protocol::Direct(direct) => read_direct(
+                self.crypto,
+                &self.key_pair,
+                channel_id,
+                envelope.from,
+                envelope.to,
+                message.sequence(),
+                direct,
+            )
+            .map(Some),
+            Protocol::ChatMessage(_) | Protocol::Confidential(_) => {
+                channel.read_chat(channel_id, envelope.from, message).map(Some)
+            }
+            _ => Ok(None),
+        }
     }
 
     pub fn add_node(
@@ -374,22 +549,118 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
             return Err(ClientError::MessageToLarge);
         };
 
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        let secret = channel.wrap_secret(self.crypto, &pub_key)?;
+
         let data: Protocol<C::PubSigningKey> = Protocol::AddUser(AddUser {
             name: name_string,
             key: pub_key,
+            secret,
         });
 
-        self.do_send(channel_id, data)?;
+        self.do_send(channel_id, Recipient::Channel(*channel_id), data)?;
 
         Ok(())
     }
 
-    pub fn remove_node(
-        &mut self,
-        _channel_id: &ChannelId,
-        _node_id: &NodeId,
-    ) -> Result<(), ClientError> {
-        unimplemented!()
+    /// Take `node_id` out of the channel. Its envelopes are refused
+    /// from then on and a later membership snapshot can't add it back,
+    /// only the owner adding it again can. A confidential channel is
+    /// rekeyed so it can't read what is sent after either. Only the
+    /// owner can remove, and never itself.
+    pub fn remove_node(&mut self, channel_id: &ChannelId, node_id: &NodeId) -> Result<(), ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        let confidential = channel.is_confidential();
+
+        let data: Protocol<C::PubSigningKey> = Protocol::RemoveUser(RemoveUser { node: *node_id });
+        self.do_send(channel_id, Recipient::Channel(*channel_id), data)?;
+
+        if confidential {
+            self.rekey(channel_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Move a confidential channel on to a new key epoch whose secret
+    /// is sent, in a `Rekey` each, to every current member. Members
+    /// taken out by `remove_node` can't read messages sent from then
+    /// on. Only the owner can rekey, it always keeps the secret.
+    pub fn rekey(&mut self, channel_id: &ChannelId) -> Result<(), ClientError> {
+        let my_id = self.node_id;
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        if !channel.is_confidential() {
+            return Err(ClientError::NoChannelSecret);
+        }
+        let epoch = channel
+            .state
+            .epoch()
+            .checked_add(1)
+            .ok_or(ClientError::Unreachable)?;
+        let mut members: Vec<C::PubSigningKey, MAX_NODES> = Vec::new();
+        for key in channel.chat.users() {
+            let member = C::compute_id(key);
+            if member != my_id {
+                members.push(key.clone()).or(Err(ClientError::Unreachable))?;
+            }
+        }
+
+        let key = self.crypto.make_channel_secret();
+        // The owner's own goes first so it is the one moving the
+        // channel on to the new epoch.
+        let own = self.crypto.wrap_secret(&self.key_pair.public, &key)?;
+        let rekey = Protocol::Rekey(Rekey {
+            epoch,
+            member: my_id,
+            secret: own.clone(),
+        });
+        self.do_send(channel_id, Recipient::Channel(*channel_id), rekey)?;
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        keep_secret(&mut channel.secrets, Secret::new(epoch, key, own));
+
+        for member_key in members {
+            let secret = self.crypto.wrap_secret(&member_key, &key)?;
+            let rekey = Protocol::Rekey(Rekey {
+                epoch,
+                member: C::compute_id(&member_key),
+                secret,
+            });
+            self.do_send(channel_id, Recipient::Channel(*channel_id), rekey)?;
+        }
+
+        Ok(())
+    }
+
+    /// Envelopes this device knows it never received, oldest first.
+    /// Each was dropped by the peers before they synced with it.
+    pub fn gaps(&self, channel_id: &ChannelId) -> Result<&[Gap], ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        Ok(&channel.gaps)
+    }
+
+    /// How full the channel's storage is, so a device can warn before
+    /// history starts being evicted.
+    pub fn storage_stats(&self, channel_id: &ChannelId) -> Result<StorageStats, ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        Ok(channel.storage.stats()?)
     }
 
     pub fn list_nodes(
@@ -404,20 +675,109 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
     }
 
     pub fn open_chat(&mut self, channel_id: ChannelId, io: I) -> Result<(), ClientError> {
+        let full_channel = self.load_channel(channel_id, io)?;
+
+        let Ok(_) = self.channels.insert(channel_id, full_channel) else {
+            return Err(ClientError::ChannelLimit);
+        };
+
+        Ok(())
+    }
+
+    /// Rebuild a channel from its storage. Kept apart from `open_chat`
+    /// so its frame, which holds the channel more than once in debug
+    /// builds, is gone before the channel is inserted.
+    fn load_channel(
+        &self,
+        channel_id: ChannelId,
+        io: I,
+    ) -> Result<Channel<MAX_NODES, I, C>, ClientError> {
         let my_id = C::compute_id(&self.key_pair.public);
 
         let storage = Storage::new(io);
-        let mut channel =
-            ChannelState::<MAX_NODES, C::PubSigningKey>::new(my_id, self.key_pair.public.clone())?;
 
-        let mut chat = Chat::<MAX_NODES, C>::new(channel_id.clone());
+        // Only the records after the last checkpoint need their
+        // signatures checked again.
+        let checkpoint = storage.checkpoint()?;
+        let checkpoint_slab = checkpoint.as_ref().map(Cursor::slab);
+        let (state, chat, wrapped, start) = match checkpoint {
+            Some(cursor) => {
+                let (state, chat, wrapped, next) =
+                    restore_checkpoint::<MAX_NODES, _, C>(&storage, cursor, channel_id)?;
+                (state, chat, wrapped, Some(next))
+            }
+            None => (
+                ChannelState::<MAX_NODES, C::PubSigningKey>::new(
+                    my_id,
+                    self.key_pair.public.clone(),
+                )?,
+                Chat::<MAX_NODES, C>::new(channel_id),
+                WrappedSecrets::new(),
+                storage.get_cursor_from_sequence(0)?,
+            ),
+        };
+        let mut secrets = Secrets::new();
+        for (epoch, wrapped) in wrapped {
+            let key = self.crypto.unwrap_secret(&self.key_pair, &wrapped)?;
+            keep_secret(&mut secrets, Secret::new(epoch, key, wrapped));
+        }
 
-        let start = storage.get_cursor_from_sequence(0)?;
+        let deleted = storage.tombstones()?;
+        let gaps = storage.read_gaps()?;
+        let mut full_channel = Channel {
+            state,
+            storage,
+            chat,
+            since_checkpoint: 0,
+            checkpoint_slab,
+            quotas: Quotas::new(),
+            deleted,
+            gaps,
+            secrets,
+        };
+
+        // Members added in records that have since been evicted are
+        // only known from the membership snapshot.
+        let mut buffer = [0u8; MEMBERSHIP_MAX + MAX_SIG];
+        if let Some(bytes) = full_channel.storage.read_membership(&mut buffer)? {
+            let (signed, _) = open_membership(bytes)?;
+            // A checkpoint written since already holds what it says.
+            match apply_membership(&mut full_channel.state, &mut full_channel.chat, channel_id, &signed) {
+                Ok(_) | Err(ClientError::StaleMembership) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let Channel {
+            state: channel,
+            storage,
+            chat,
+            secrets,
+            ..
+        } = &mut full_channel;
+        let mut replayed: u32 = 0;
 
+        let mut buffer = I::Buffer::default();
+        let mut expanded = [0u8; COMPRESS_MAX];
         if let Some(mut cursor) = start {
-            while let Some(found) = storage.read(cursor)? {
-                let data;
-                (data, cursor) = found;
+            while let Some(found) = storage.read_record(cursor, buffer.as_mut())? {
+                let record;
+                (record, cursor) = found;
+                match record.kind() {
+                    RecordKind::Data => {}
+                    RecordKind::Deleted => {
+                        // Only what the envelope did to the sender's
+                        // sequence is left.
+                        let tombstone: Tombstone = from_bytes(record.data())?;
+                        channel.restore_deleted(record.sender(), record.sequence(), &tombstone.id)?;
+                        chat.restore_message_count(record.message_count());
+                        replayed = replayed.saturating_add(1);
+                        continue;
+                    }
+                    _ => continue,
+                }
+
+                let data = record.decompress(&mut expanded)?;
                 let sealed_envelope: SealedEnvelope<
                     Protocol<C::PubSigningKey>,
                     MAX_ENVELOPE,
@@ -428,35 +788,373 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
                 let pub_key = channel.get_node_key(from)?;
                 let message = self.crypto.open(&pub_key, &sealed_envelope)?;
 
-                channel.check_receive(from, &message, &envelope_id)?;
-
-                let accept_result = chat.accept_message(channel_id, from, &message.data)?;
-
-                if let AcceptResult::AddUser(new_pub_key) = accept_result {
-                    let node_id = C::compute_id(&new_pub_key);
-                    channel.add_node(node_id, new_pub_key)?;
+                // Envelopes taken past a gap replay past it again, the
+                // gaps themselves were stored.
+                let over_gaps = match channel.check_receive(from, &message, &envelope_id) {
+                    Ok(()) => false,
+                    Err(ChannelError::MissingFromSender { .. }) => {
+                        channel.find_gaps(from, &message, 0)?;
+                        true
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                let accept_result = chat.accept_message(channel_id, from, &message)?;
+
+                match accept_result {
+                    AcceptResult::AddUser(new_pub_key) => {
+                        let node_id = C::compute_id(&new_pub_key);
+                        add_member(channel, node_id, new_pub_key)?;
+                    }
+                    AcceptResult::Rekey(epoch) => channel.rekey(epoch),
+                    AcceptResult::NewMessage(_) | AcceptResult::None => {}
+                }
+                if let Some(found) =
+                    offered_secret(self.crypto, &self.key_pair, channel.epoch(), &message.data)?
+                {
+                    keep_secret(secrets, found);
                 }
 
-                if let Err(_) = channel.receive(from, &message, &envelope_id) {
+                let received = match over_gaps {
+                    false => channel.receive(from, &message, &envelope_id),
+                    true => channel.receive_over_gaps(from, &message, &envelope_id),
+                };
+                if received.is_err() {
                     return Err(ClientError::Unreachable);
                 }
+
+                replayed = replayed.saturating_add(1);
             }
         }
 
-        let full_channel = Channel {
-            state: channel,
-            storage,
-            chat,
+        if replayed >= CHECKPOINT_INTERVAL {
+            // Spare the next open this replay. The channel is usable
+            // without it so a failure is not reported.
+            let _ = full_channel.write_checkpoint(my_id);
+        } else {
+            full_channel.since_checkpoint = replayed;
+        }
+
+        Ok(full_channel)
+    }
+
+    /// Write a checkpoint of the channel state now so the next
+    /// `open_chat` starts replaying from here. `Client` also writes
+    /// one every `CHECKPOINT_INTERVAL` stored records.
+    pub fn checkpoint(&mut self, channel_id: &ChannelId) -> Result<(), ClientError> {
+        let my_id = self.node_id;
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        channel.write_checkpoint(my_id)
+    }
+
+    /// The channel's signed membership snapshot, for sending to a
+    /// peer during sync, `None` until one has been stored.
+    pub fn membership<'c>(
+        &self,
+        channel_id: &ChannelId,
+        target: &'c mut [u8],
+    ) -> Result<Option<&'c [u8]>, ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        Ok(channel.storage.read_membership(target)?)
+    }
+
+    /// Check a membership snapshot from a peer against the owner's key
+    /// and learn the members and removals it lists. It is stored if it
+    /// named anyone new or is newer than the last, so it can be passed
+    /// on in turn. One older than what is known fails with
+    /// `StaleMembership`.
+    pub fn receive_membership(
+        &mut self,
+        channel_id: &ChannelId,
+        bytes: &[u8],
+    ) -> Result<(), ClientError> {
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        let (signed, header) = verify_membership(self.crypto, &channel.state, &channel.chat, bytes)?;
+
+        let known = channel.chat.membership_generation();
+        let added = apply_membership(&mut channel.state, &mut channel.chat, *channel_id, &signed)?;
+        if added > 0 || header.generation > known {
+            channel.storage.write_membership(header.owner, bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// What the channel's storage does once it is full, the oldest
+    /// records can be dropped as the membership snapshot and a
+    /// checkpoint are kept.
+    pub fn set_retention(
+        &mut self,
+        channel_id: &ChannelId,
+        retention: Retention,
+    ) -> Result<(), ClientError> {
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        channel.storage.set_retention(retention);
+        Ok(())
+    }
+
+    /// Limit what each sender can store in the channel, `None` lifts
+    /// the limit. `receive_buffer` leaves envelopes over it for a later
+    /// sync. What senders already have stored is read from the log, so
+    /// set it again each time the channel is opened.
+    pub fn set_quota(
+        &mut self,
+        channel_id: &ChannelId,
+        quota: Option<Quota>,
+    ) -> Result<(), ClientError> {
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        channel
+            .quotas
+            .set(quota, &channel.storage, channel.chat.owner_id(), self.node_id)
+    }
+
+    /// Store the channel's envelopes compressed from now on, see
+    /// `Storage::set_compression`.
+    pub fn set_compression(
+        &mut self,
+        channel_id: &ChannelId,
+        enabled: bool,
+    ) -> Result<(), ClientError> {
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        channel.storage.set_compression(enabled);
+        Ok(())
+    }
+
+    /// Delete the chat message at `index` from this device only. It
+    /// is hidden from `get_message` and no longer sent to peers at
+    /// once, its space is reclaimed by `compact`. Fails with
+    /// `ClientError::CompactionNeeded` once `MAX_TOMBSTONES` deletes
+    /// are waiting for a compaction.
+    pub fn delete_local(&mut self, channel_id: &ChannelId, index: u64) -> Result<(), ClientError> {
+        let my_id = self.node_id;
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        let tombstone = {
+            let mut buffer = I::Buffer::default();
+            let mut expanded = [0u8; COMPRESS_MAX];
+            let record = match channel.message_record(index, buffer.as_mut()) {
+                Ok(record) => record,
+                Err(ClientError::MessageDeleted) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let envelope: SealedEnvelope<Protocol<C::PubSigningKey>, MAX_ENVELOPE, MAX_SIG> =
+                from_bytes(record.decompress(&mut expanded)?)?;
+            let key = channel.state.get_node_key(envelope.from())?;
+            let message = self.crypto.open(&key, &envelope)?;
+            // Members and the like are part of the channel state.
+            let (Protocol::ChatMessage(_) | Protocol::Confidential(_) | Protocol::Direct(_)) =
+                message.data
+            else {
+                return Err(ClientError::NotDeletable);
+            };
+
+            Tombstone {
+                sender: record.sender(),
+                sequence: record.sequence(),
+                id: self.crypto.envelope_id(&envelope),
+            }
         };
 
-        let Ok(_) = self.channels.insert(channel_id.clone(), full_channel) else {
+        if channel.deleted.is_full() {
+            return Err(ClientError::CompactionNeeded);
+        }
+        channel.storage.append_tombstone(my_id, &tombstone)?;
+        channel
+            .deleted
+            .push(tombstone)
+            .or(Err(ClientError::Unreachable))?;
+
+        Ok(())
+    }
+
+    /// Move the channel on to `io`, leaving the envelopes deleted with
+    /// `delete_local` behind, and hand back the storage it was using.
+    pub fn compact(&mut self, channel_id: &ChannelId, io: I) -> Result<I, ClientError> {
+        let my_id = self.node_id;
+        let channel = self
+            .channels
+            .get_mut(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        channel.compact(my_id, Storage::new(io))
+    }
+
+    /// Write the channel's log in to `target` as a snapshot that
+    /// `import_channel` can load on another device.
+    pub fn export_channel<'c>(
+        &self,
+        channel_id: &ChannelId,
+        target: &'c mut [u8],
+    ) -> Result<&'c [u8], ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+
+        Ok(channel.storage.export(*channel_id, target)?)
+    }
+
+    /// Add a channel from a snapshot written by `export_channel`,
+    /// storing it in `io` which must be empty.
+    ///
+    /// Like `add_channel` the `owner` key is trusted, every other key
+    /// comes from the owner's signed membership or `AddUser` records
+    /// and every record's signature is checked before it is stored.
+    pub fn import_channel(
+        &mut self,
+        owner: C::PubSigningKey,
+        bytes: &[u8],
+        io: I,
+    ) -> Result<ChannelId, ClientError> {
+        let (channel_id, full_channel) = self.import_snapshot(owner, bytes, io)?;
+
+        let Ok(_) = self.channels.insert(channel_id, full_channel) else {
             return Err(ClientError::ChannelLimit);
         };
 
-        Ok(())
+        Ok(channel_id)
+    }
+
+    fn import_snapshot(
+        &self,
+        owner: C::PubSigningKey,
+        bytes: &[u8],
+        io: I,
+    ) -> Result<(ChannelId, Channel<MAX_NODES, I, C>), ClientError> {
+        let my_id = C::compute_id(&self.key_pair.public);
+        let (header, _) = read_header(bytes)?;
+        let channel_id = header.channel_id;
+
+        let mut state = ChannelState::<MAX_NODES, C::PubSigningKey>::new(C::compute_id(&owner), owner)?;
+        let mut chat = Chat::<MAX_NODES, C>::new(channel_id);
+        let mut storage = Storage::new(io);
+        let mut first = true;
+        let mut secrets = Secrets::new();
+
+        storage.import(bytes, |entry| -> Result<(), ClientError> {
+            match entry {
+                SnapshotEntry::Membership { data, .. } => {
+                    let (signed, _) = verify_membership(self.crypto, &state, &chat, data)?;
+                    apply_membership(&mut state, &mut chat, channel_id, &signed)?;
+                }
+                SnapshotEntry::Record {
+                    message_count,
+                    sequence,
+                    sender,
+                    data,
+                    ..
+                } => {
+                    let sealed_envelope: SealedEnvelope<
+                        Protocol<C::PubSigningKey>,
+                        MAX_ENVELOPE,
+                        MAX_SIG,
+                    > = from_bytes(data)?;
+                    let from = sealed_envelope.from();
+                    let key = state.get_node_key(from)?;
+                    let message = self.crypto.open(&key, &sealed_envelope)?;
+                    if from != *sender || message.sequence() != *sequence {
+                        return Err(StorageError::CorruptDB.into());
+                    }
+                    check_recipient(&state, &channel_id, sealed_envelope.to, &message.data)?;
+
+                    let envelope_id = self.crypto.envelope_id(&sealed_envelope);
+                    state.restore_receive(from, &message, &envelope_id)?;
+                    let accept_result = chat.accept_message(channel_id, from, &message)?;
+                    match accept_result {
+                        AcceptResult::AddUser(new_pub_key) => {
+                            let node_id = C::compute_id(&new_pub_key);
+                            add_member(&mut state, node_id, new_pub_key)?;
+                        }
+                        AcceptResult::Rekey(epoch) => state.rekey(epoch),
+                        AcceptResult::NewMessage(_) | AcceptResult::None => {}
+                    }
+                    if let Some(found) =
+                        offered_secret(self.crypto, &self.key_pair, state.epoch(), &message.data)?
+                    {
+                        keep_secret(&mut secrets, found);
+                    }
+
+                    // The log may start part way through the channel
+                    // after eviction, from there the counts must agree.
+                    if first {
+                        chat.restore_message_count(*message_count);
+                        first = false;
+                    } else if chat.message_count() != *message_count {
+                        return Err(StorageError::CorruptDB.into());
+                    }
+                }
+            }
+            Ok(())
+        })?;
+
+        let mut full_channel = Channel {
+            state,
+            storage,
+            chat,
+            since_checkpoint: 0,
+            checkpoint_slab: None,
+            quotas: Quotas::new(),
+            deleted: Tombstones::new(),
+            gaps: Gaps::new(),
+            secrets,
+        };
+        // The snapshot's first records may not replay from an empty
+        // state, the next open starts from here instead.
+        full_channel.write_checkpoint(my_id)?;
+
+        Ok((channel_id, full_channel))
     }
 
     pub fn init_chat(&mut self, name_str: &str, io: I) -> Result<ChannelId, ClientError> {
+        self.create_chat(name_str, io, None)
+    }
+
+    /// Start a channel whose chat messages are encrypted with a
+    /// secret only its members are given, each in their `AddUser`.
+    /// Nodes passing the channel on can still check and store them.
+    pub fn init_confidential_chat(
+        &mut self,
+        name_str: &str,
+        io: I,
+    ) -> Result<ChannelId, ClientError> {
+        let key = self.crypto.make_channel_secret();
+        let wrapped = self.crypto.wrap_secret(&self.key_pair.public, &key)?;
+        self.create_chat(name_str, io, Some(Secret::new(0, key, wrapped)))
+    }
+
+    fn create_chat(
+        &mut self,
+        name_str: &str,
+        io: I,
+        secret: Option<Secret>,
+    ) -> Result<ChannelId, ClientError> {
         let nonce = self.crypto.nonce();
 
         let Ok(name) = name_str.try_into() else {
@@ -467,6 +1165,7 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
             nonce,
             name,
             owner: self.key_pair.public.clone(),
+            secret: secret.as_ref().map(|secret| secret.wrapped().clone()),
         };
 
         let mut target = [0; 4096]; // BUG: should we take this as an argument?
@@ -503,21 +1202,30 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
         // can send junk messages and overflow memory.
         channel.check_receive(my_id, &message, &envelope_id)?;
         // -check the message on chat
-        chat.accept_message(channel_id.clone(), my_id, &message.data)?;
+        chat.accept_message(channel_id, my_id, &message)?;
         // -receive it
         let max_sequence = channel.receive(my_id, &message, &envelope_id)?;
         // -store it
         let message_count = chat.message_count();
-        let mut slab_writer = storage.get_writer()?;
         let serialized_envelope = to_slice(&sealed_envelope, target.as_mut_slice())?;
-        slab_writer.write_record(max_sequence, message_count, sequence, my_id, &serialized_envelope)?;
-        slab_writer.commit()?;
+        storage.append(max_sequence, message_count, sequence, my_id, serialized_envelope)?;
 
-        let full_channel = Channel {
+        let mut secrets = Secrets::new();
+        if let Some(secret) = secret {
+            keep_secret(&mut secrets, secret);
+        }
+        let mut full_channel = Channel {
             state: channel,
             storage,
             chat,
+            since_checkpoint: 0,
+            checkpoint_slab: None,
+            quotas: Quotas::new(),
+            deleted: Tombstones::new(),
+            gaps: Gaps::new(),
+            secrets,
         };
+        full_channel.write_membership(self.crypto, &self.key_pair, channel_id, my_id)?;
 
         let Ok(_) = self.channels.insert(channel_id.clone(), full_channel) else {
             return Err(ClientError::ChannelLimit);
@@ -537,6 +1245,12 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
             state: channel,
             storage,
             chat,
+            since_checkpoint: 0,
+            checkpoint_slab: None,
+            quotas: Quotas::new(),
+            deleted: Tombstones::new(),
+            gaps: Gaps::new(),
+            secrets: Secrets::new(),
         };
 
         let Ok(_) = self.channels.insert(channel_id.clone(), full_channel) else {
@@ -546,14 +1260,77 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
         Ok(())
     }
 
+    /// Start onboarding a member in to `channel_id`, which this node
+    /// owns. The `Invite` is broadcast and `password` given to the new
+    /// user some other way, their `Join` goes to `admit`.
+    pub fn invite(
+        &mut self,
+        channel_id: &ChannelId,
+        password: &[u8],
+    ) -> Result<(Inviter, Invite), ClientError> {
+        let channel = self
+            .channels
+            .get(channel_id)
+            .ok_or(ClientError::UnknownChannel)?;
+        if channel.chat.owner_id() != Some(self.node_id) {
+            return Err(ChatError::Unauthorized.into());
+        }
+
+        Ok(Inviter::new(self.crypto, *channel_id, password))
+    }
+
+    /// Add the node that sent `join` once it has shown it knows the
+    /// password, returning the `Confirm` to send back. `inviter` is
+    /// used up by the first `Join` whatever its outcome, a new invite
+    /// is needed to try again.
+    pub fn admit(
+        &mut self,
+        inviter: &mut Inviter,
+        join: &Join<C::PubSigningKey>,
+    ) -> Result<Confirm<C::PubSigningKey>, ClientError> {
+        let key = inviter.accept(join)?;
+        self.add_node(&inviter.channel_id(), join.key.clone(), &join.name)?;
+
+        inviter.confirm(&key, &self.key_pair.public)
+    }
+
+    /// Answer an `Invite` with this node's key and `name`, `password`
+    /// being the one the admin gave out. The admin's `Confirm` goes to
+    /// `finish_join`.
+    pub fn join(
+        &mut self,
+        invite: &Invite,
+        password: &[u8],
+        name: &str,
+    ) -> Result<(Joiner, Join<C::PubSigningKey>), ClientError> {
+        let Ok(name) = String::try_from(name) else {
+            return Err(ClientError::StringTooLarge);
+        };
+
+        Joiner::new(self.crypto, invite, password, name, self.key_pair.public.clone())
+    }
+
+    /// Check `confirm` came from the admin that knew the password and
+    /// add the channel it names, storing it in `io`.
+    pub fn finish_join(
+        &mut self,
+        joiner: &Joiner,
+        confirm: &Confirm<C::PubSigningKey>,
+        io: I,
+    ) -> Result<ChannelId, ClientError> {
+        joiner.check(confirm)?;
+        self.add_channel(confirm.owner.clone(), confirm.channel_id, io)?;
+
+        Ok(confirm.channel_id)
+    }
 
     fn do_send(
         &mut self,
         channel_id: &ChannelId,
+        to: Recipient,
         data: Protocol<C::PubSigningKey>,
     ) -> Result<(), ClientError> {
         let from = self.node_id;
-        let to = Recipient::Channel(channel_id.clone());
         let mut target = [0u8; 4096]; // BUG: should we take this as an argument?
 
         let channel = self
@@ -561,7 +1338,14 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
             .get_mut(channel_id)
             .ok_or(ClientError::UnknownChannel)?;
 
-        let message = channel.state.address(from, data)?;
+        let removed_user = matches!(data, Protocol::RemoveUser(_));
+        let mut message = channel.state.address(from, data)?;
+        match to {
+            Recipient::Node(recipient) => {
+                channel.encrypt_direct(self.crypto, channel_id, from, recipient, &mut message)?
+            }
+            Recipient::Channel(_) => channel.encrypt_chat(channel_id, from, &mut message)?,
+        }
         let sequence = message.sequence();
         let envelope = self.crypto.seal::<_, MAX_ENVELOPE, MAX_SIG>(
             from,
@@ -581,7 +1365,7 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
         // -check the message on chat
         let result = channel
             .chat
-            .accept_message(channel_id.clone(), from, &message.data)?;
+            .accept_message(*channel_id, from, &message)?;
 
         // -receive it
         //let max_sequence = channel.state.receive(from, &message, &envelope_id)?;
@@ -590,18 +1374,28 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
         };
 
         // - store the pub key for later
-        if let AcceptResult::AddUser(new_pub_key) = result {
-            let node_id = C::compute_id(&new_pub_key);
-            channel.state.add_node(node_id, new_pub_key)?;
+        let membership_changed = removed_user || matches!(result, AcceptResult::AddUser(_));
+        match result {
+            AcceptResult::AddUser(new_pub_key) => {
+                let node_id = C::compute_id(&new_pub_key);
+                add_member(&mut channel.state, node_id, new_pub_key)?;
+            }
+            AcceptResult::Rekey(epoch) => channel.state.rekey(epoch),
+            AcceptResult::NewMessage(_) | AcceptResult::None => {}
         }
 
         // -store it
         let message_count = channel.chat.message_count();
-        let mut slab_writer = channel.storage.get_writer()?;
         let serialized_envelope = to_slice(&envelope, target.as_mut_slice())?;
 
-        slab_writer.write_record(max_sequence, message_count, sequence, from, &serialized_envelope)?;
-        slab_writer.commit()?;
+        channel
+            .storage
+            .append(max_sequence, message_count, sequence, from, serialized_envelope)?;
+        channel.stored(from);
+
+        if membership_changed {
+            channel.write_membership(self.crypto, &self.key_pair, *channel_id, from)?;
+        }
 
         Ok(())
     }
@@ -611,6 +1405,7 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
             from_bytes(bytes)?;
 
         let from = sealed_envelope.from();
+        let my_id = self.node_id;
         let channel = self
             .channels
             .get_mut(channel_id)
@@ -619,34 +1414,113 @@ impl<'a, 'b, const MAX_CHANNELS: usize, const MAX_NODES: usize, I: IO, C: Crypto
 
         let message = self.crypto.open(&key, &sealed_envelope)?;
         let sequence = message.sequence();
+        check_recipient(&channel.state, channel_id, sealed_envelope.to, &message.data)?;
 
         let envelope_id = self.crypto.envelope_id(&sealed_envelope);
         // -check that we can receive it
         // BUG: This actually allocates a new client
         // So there is a DOS here where and attacker
         // can send junk messages and overflow memory.
-        channel.state.check_receive(from, &message, &envelope_id)?;
+        let gaps = match channel.state.check_receive(from, &message, &envelope_id) {
+            Ok(()) => Vec::new(),
+            // Sync sends what came before first, so what is missing
+            // here the responder no longer has.
+            Err(ChannelError::MissingFromSender { .. }) => {
+                let index = channel.chat.message_count();
+                channel.state.find_gaps(from, &message, index)?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // -check the sender has room left, the owner running the
+        // channel is never held back, nor is the `NewChannel` that
+        // makes it the owner
+        let counted = message.data.is_message()
+            || channel.chat.owner_id().is_some_and(|owner| owner != from);
+        let stored = counted.then(|| channel.storage.stored_len(bytes));
+        if let Some(stored) = stored {
+            channel.quotas.check(from, stored)?;
+        }
         // -check the message on chat
         let result = channel
             .chat
-            .accept_message(channel_id.clone(), from, &message.data)?;
+            .accept_message(*channel_id, from, &message)?;
 
         // -receive it
         //let max_sequence = channel.state.receive(from, &message, &envelope_id)?;
-        let Ok(max_sequence) = channel.state.receive(from, &message, &envelope_id) else {
+        let received = match gaps.is_empty() {
+            true => channel.state.receive(from, &message, &envelope_id),
+            false => channel
+                .state
+                .receive_over_gaps(from, &message, &envelope_id),
+        };
+        let Ok(max_sequence) = received else {
             return Err(ClientError::Unreachable);
         };
+        // -note what is missing, before the envelope so it is never
+        // stored without its gaps
+        channel.fill_gaps(my_id, from, message.sender_last(), sequence)?;
+        channel.add_gaps(my_id, &gaps)?;
         // - store the pub key for later
-        if let AcceptResult::AddUser(new_pub_key) = result {
-            let node_id = C::compute_id(&new_pub_key);
-            channel.state.add_node(node_id, new_pub_key)?;
+        match result {
+            AcceptResult::AddUser(new_pub_key) => {
+                let node_id = C::compute_id(&new_pub_key);
+                add_member(&mut channel.state, node_id, new_pub_key)?;
+            }
+            AcceptResult::Rekey(epoch) => channel.state.rekey(epoch),
+            AcceptResult::NewMessage(_) | AcceptResult::None => {}
+        }
+        let epoch = channel.state.epoch();
+        if let Some(secret) = offered_secret(self.crypto, &self.key_pair, epoch, &message.data)? {
+            keep_secret(&mut channel.secrets, secret);
         }
         // -store it
         let message_count = channel.chat.message_count();
-        let mut slab_writer = channel.storage.get_writer()?;
+        channel
+            .storage
+            .append(max_sequence, message_count, sequence, from, bytes)?;
+        if let Some(stored) = stored {
+            channel.quotas.charge(from, stored)?;
+        }
+        channel.stored(my_id);
 
-        slab_writer.write_record(max_sequence, message_count, sequence, from, &bytes)?;
-        slab_writer.commit()?;
+        Ok(())
+    }
+}
+
+impl<
+        'a,
+        'b,
+        'c,
+        const MAX_CHANNELS: usize,
+        const MAX_NODES: usize,
+        M: Media,
+        const SLAB_SIZE: usize,
+        const MAX_RANGES: usize,
+        C: Crypto,
+    > Client<'a, 'b, MAX_CHANNELS, MAX_NODES, pool_io::PooledIO<'c, M, SLAB_SIZE, MAX_RANGES>, C>
+{
+    /// Move every channel whose range holds more than `slabs` slabs
+    /// to one holding `slabs`, keeping their newest messages, so the
+    /// room they leave can be opened for new channels. Each move needs
+    /// a free range of the new size, see `StoragePool::move_to`. Every
+    /// channel must have been opened from the same pool.
+    pub fn rebalance_storage(&mut self, slabs: usize) -> Result<(), ClientError> {
+        let my_id = self.node_id;
+        for channel in self.channels.values_mut() {
+            let io = channel.storage.io();
+            if io.quota() <= slabs {
+                continue;
+            }
+            let pool = io.pool();
+            let target = Storage::new(pool.move_to(io.range().key, slabs)?);
+
+            // Leave room for the checkpoint the move writes.
+            while channel.storage.io().slab_count()? > slabs.saturating_sub(1) {
+                channel.storage.evict_oldest()?;
+            }
+            let old = channel.compact(my_id, target)?;
+            pool.finish_move(old)?;
+        }
 
         Ok(())
     }
@@ -0,0 +1,155 @@
+use super::*;
+
+/// Largest signed membership snapshot `Client` builds or accepts.
+pub const MEMBERSHIP_MAX: usize = 4096;
+
+/// Start of a membership snapshot, followed by `members` keys each
+/// serialized on its own so the snapshot can be read a key at a time,
+/// then the `removed` nodes.
+///
+/// The snapshot is signed by the channel owner as only the owner can
+/// add users, so any member can pass it on during sync.
+#[derive(Serialize, Deserialize)]
+pub struct MembershipHeader {
+    pub channel_id: ChannelId,
+    pub owner: NodeId,
+    /// See `Chat::is_confidential`.
+    pub confidential: bool,
+    /// Sequence of the owner's membership change the snapshot was
+    /// written for, so a peer can tell it from an older one.
+    pub generation: u64,
+    pub members: u32,
+    pub removed: u32,
+}
+
+/// A membership snapshot and the owner's signature over it, this is
+/// what is stored and sent to peers.
+#[derive(Serialize, Deserialize)]
+pub struct SignedMembership<'a> {
+    pub data: &'a [u8],
+    pub signature: &'a [u8],
+}
+
+impl<const MAX_NODES: usize, I: IO, C: Crypto> Channel<MAX_NODES, I, C> {
+    /// Sign and store the current membership, only the owner can.
+    pub(crate) fn write_membership(
+        &mut self,
+        crypto: &C,
+        key_pair: &KeyPair<C::PrivateSigningKey, C::PubSigningKey>,
+        channel_id: ChannelId,
+        my_id: NodeId,
+    ) -> Result<(), ClientError> {
+        if self.chat.owner_id() != Some(my_id) {
+            return Err(ChatError::Unauthorized.into());
+        }
+
+        let mut data = [0u8; MEMBERSHIP_MAX];
+        let header = MembershipHeader {
+            channel_id,
+            owner: my_id,
+            confidential: self.chat.is_confidential(),
+            generation: self.chat.membership_generation(),
+            members: u32::try_from(self.chat.users().count()).or(Err(ClientError::Unreachable))?,
+            removed: u32::try_from(self.chat.removed().len()).or(Err(ClientError::Unreachable))?,
+        };
+        let mut used = to_slice(&header, data.as_mut_slice())?.len();
+        for key in self.chat.users() {
+            let target = data.get_mut(used..).ok_or(ClientError::MessageToLarge)?;
+            used += to_slice(key, target)?.len();
+        }
+        for node in self.chat.removed() {
+            let target = data.get_mut(used..).ok_or(ClientError::MessageToLarge)?;
+            used += to_slice(node, target)?.len();
+        }
+        let data = data.get(..used).ok_or(ClientError::Unreachable)?;
+
+        let mut signature = [0u8; MAX_SIG];
+        let signature_len = crypto.sign(key_pair, data, &mut signature)?;
+        let signed = SignedMembership {
+            data,
+            signature: signature.get(..signature_len).ok_or(ClientError::Unreachable)?,
+        };
+
+        let mut target = [0u8; MEMBERSHIP_MAX + MAX_SIG];
+        let bytes = to_slice(&signed, target.as_mut_slice())?;
+        self.storage.write_membership(my_id, bytes)?;
+
+        Ok(())
+    }
+}
+
+/// Check a snapshot's signature against the owner's key, the owner
+/// has to be known to `state` already.
+pub(crate) fn verify_membership<'a, const MAX_NODES: usize, C: Crypto>(
+    crypto: &C,
+    state: &ChannelState<MAX_NODES, C::PubSigningKey>,
+    chat: &Chat<MAX_NODES, C>,
+    bytes: &'a [u8],
+) -> Result<(SignedMembership<'a>, MembershipHeader), ClientError> {
+    let (signed, header) = open_membership(bytes)?;
+    // Before the channel's first message arrives the owner is the
+    // node the channel was added with.
+    if let Some(owner) = chat.owner_id() {
+        if owner != header.owner {
+            return Err(ChatError::Unauthorized.into());
+        }
+    }
+    let owner_key = state.get_node_key(header.owner)?;
+    crypto.verify(&owner_key, signed.data, signed.signature)?;
+
+    Ok((signed, header))
+}
+
+/// Learn every member listed in `signed` and every removal, returning
+/// how many members were new. The signature must already have been
+/// checked. A snapshot older than the newest membership change known
+/// fails with `StaleMembership`, nodes the owner has since removed are
+/// left out of the chat either way.
+pub(crate) fn apply_membership<const MAX_NODES: usize, C: Crypto>(
+    state: &mut ChannelState<MAX_NODES, C::PubSigningKey>,
+    chat: &mut Chat<MAX_NODES, C>,
+    channel_id: ChannelId,
+    signed: &SignedMembership<'_>,
+) -> Result<u32, ClientError> {
+    let (header, mut rest) = take_from_bytes::<MembershipHeader>(signed.data)?;
+    if header.channel_id != channel_id {
+        return Err(ClientError::UnknownChannel);
+    }
+    if header.generation < chat.membership_generation() {
+        return Err(ClientError::StaleMembership);
+    }
+    chat.restore_owner(header.owner)?;
+    chat.restore_confidential(header.confidential);
+
+    let mut added = 0;
+    for _ in 0..header.members {
+        let key: C::PubSigningKey;
+        (key, rest) = take_from_bytes(rest)?;
+
+        let node_id = C::compute_id(&key);
+        if !chat.is_removed(&node_id) {
+            chat.restore_user(&key)?;
+        }
+        match state.add_node(node_id, key) {
+            Ok(()) => added += 1,
+            Err(ChannelError::NodeExists) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    for _ in 0..header.removed {
+        let node: NodeId;
+        (node, rest) = take_from_bytes(rest)?;
+        chat.restore_removed(node);
+    }
+    chat.restore_generation(header.generation);
+
+    Ok(added)
+}
+
+/// Split a stored or received snapshot in to its parts.
+pub fn open_membership(bytes: &[u8]) -> Result<(SignedMembership<'_>, MembershipHeader), ClientError> {
+    let signed: SignedMembership<'_> = from_bytes(bytes)?;
+    let (header, _) = take_from_bytes::<MembershipHeader>(signed.data)?;
+    Ok((signed, header))
+}
@@ -0,0 +1,268 @@
+//! Joining a channel authenticated by a password both sides were
+//! given, using the CPace balanced PAKE over Ristretto255.
+//!
+//! ```text
+//! admin                                  new user
+//! Client::invite     -- Invite  -->
+//!                    <-- Join   --       Client::join
+//! Client::admit      -- Confirm -->      Client::finish_join
+//! ```
+//!
+//! `Join` and `Confirm` are tagged with the key the exchange agreed,
+//! which only holders of the password can work out, so no one nearby
+//! can slip in their own key or send the new user to another channel.
+//! Each invite allows an attacker a single guess: the first `Join`
+//! sent against it uses it up, whether or not it had the password.
+use super::*;
+
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+/// Domain separation for the password derived generator.
+const GENERATOR_DSI: &[u8] = b"finder onboarding generator";
+/// Domain separation for the key the exchange agrees.
+const KEY_DSI: &[u8] = b"finder onboarding key";
+const JOIN_LABEL: &[u8] = b"join";
+const CONFIRM_LABEL: &[u8] = b"confirm";
+
+/// Largest serialized `Join` or `Confirm` contents a tag covers.
+const TAGGED_MAX: usize = 1024;
+
+pub type Share = [u8; 32];
+pub type Tag = [u8; SHA256_SIZE];
+
+#[derive(Debug)]
+pub enum OnboardingError {
+    /// The message belongs to another invite.
+    WrongSession,
+    /// The other side's share is not a usable point.
+    BadShare,
+    /// Sent by someone without the password, or tampered with.
+    BadTag,
+    /// The invite already had its `Join`.
+    Spent,
+}
+
+/// Broadcast by the admin to start onboarding.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Invite {
+    pub session: u128,
+    pub share: Share,
+}
+
+/// The new user's answer to an `Invite`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Join<P> {
+    pub session: u128,
+    pub share: Share,
+    pub name: String<NAME_MAX>,
+    pub key: P,
+    pub tag: Tag,
+}
+
+/// The admin's answer to a `Join`, sent once the user was added.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Confirm<P> {
+    pub session: u128,
+    pub channel_id: ChannelId,
+    pub owner: P,
+    pub tag: Tag,
+}
+
+/// The messages of the handshake, for sending them over one link.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Onboarding<P> {
+    Invite(Invite),
+    Join(Join<P>),
+    Confirm(Confirm<P>),
+}
+
+/// The admin's side of an onboarding, kept from `Client::invite` to
+/// `Client::admit`.
+pub struct Inviter {
+    channel_id: ChannelId,
+    session: u128,
+    secret: Scalar,
+    share: Share,
+    spent: bool,
+}
+
+impl Inviter {
+    pub(crate) fn new<C: Crypto>(
+        crypto: &mut C,
+        channel_id: ChannelId,
+        password: &[u8],
+    ) -> (Self, Invite) {
+        let session = crypto.nonce();
+        let secret = random_scalar(crypto);
+        let share = (generator(password, session) * secret).compress().to_bytes();
+
+        let inviter = Self {
+            channel_id,
+            session,
+            secret,
+            share,
+            spent: false,
+        };
+        (inviter, Invite { session, share })
+    }
+
+    pub fn channel_id(&self) -> ChannelId {
+        self.channel_id
+    }
+
+    /// Check `join` was sent by someone with the password, returning
+    /// the key the exchange agreed. Only the first `Join` for this
+    /// invite is checked, so a wrong password can't be tried again.
+    pub(crate) fn accept<P: Serialize>(
+        &mut self,
+        join: &Join<P>,
+    ) -> Result<Tag, OnboardingError> {
+        if join.session != self.session {
+            return Err(OnboardingError::WrongSession);
+        }
+        if self.spent {
+            return Err(OnboardingError::Spent);
+        }
+        self.spent = true;
+        let key = agreed_key(self.session, &self.secret, &self.share, &join.share, &join.share)?;
+        check_tag(&key, JOIN_LABEL, &(&join.name, &join.key), &join.tag)?;
+
+        Ok(key)
+    }
+
+    /// The `Confirm` handing the joiner the channel and its `owner`.
+    pub(crate) fn confirm<P: Serialize + Clone>(
+        &self,
+        key: &Tag,
+        owner: &P,
+    ) -> Result<Confirm<P>, ClientError> {
+        let tag = tag(key, CONFIRM_LABEL, &(&self.channel_id, owner))?;
+
+        Ok(Confirm {
+            session: self.session,
+            channel_id: self.channel_id,
+            owner: owner.clone(),
+            tag,
+        })
+    }
+}
+
+/// The new user's side of an onboarding, kept from `Client::join` to
+/// `Client::finish_join`.
+pub struct Joiner {
+    session: u128,
+    key: Tag,
+}
+
+impl Joiner {
+    pub(crate) fn new<C: Crypto>(
+        crypto: &mut C,
+        invite: &Invite,
+        password: &[u8],
+        name: String<NAME_MAX>,
+        key: C::PubSigningKey,
+    ) -> Result<(Self, Join<C::PubSigningKey>), ClientError> {
+        let secret = random_scalar(crypto);
+        let share = (generator(password, invite.session) * secret)
+            .compress()
+            .to_bytes();
+        let agreed = agreed_key(invite.session, &secret, &invite.share, &share, &invite.share)?;
+        let tag = tag(&agreed, JOIN_LABEL, &(&name, &key))?;
+
+        let joiner = Self {
+            session: invite.session,
+            key: agreed,
+        };
+        let join = Join {
+            session: invite.session,
+            share,
+            name,
+            key,
+            tag,
+        };
+        Ok((joiner, join))
+    }
+
+    /// Check `confirm` came from the admin that sent the invite.
+    pub(crate) fn check<P: Serialize>(&self, confirm: &Confirm<P>) -> Result<(), OnboardingError> {
+        if confirm.session != self.session {
+            return Err(OnboardingError::WrongSession);
+        }
+        check_tag(&self.key, CONFIRM_LABEL, &(&confirm.channel_id, &confirm.owner), &confirm.tag)
+    }
+}
+
+/// The point both sides build their shares from, only found again
+/// with the password.
+fn generator(password: &[u8], session: u128) -> RistrettoPoint {
+    let mut hasher = Sha512::new();
+    hasher.update(GENERATOR_DSI);
+    hasher.update((password.len() as u64).to_be_bytes());
+    hasher.update(password);
+    hasher.update(session.to_be_bytes());
+    let bytes: [u8; 64] = hasher.finalize().into();
+    RistrettoPoint::from_uniform_bytes(&bytes)
+}
+
+fn random_scalar<C: Crypto>(crypto: &mut C) -> Scalar {
+    let mut bytes = [0u8; 64];
+    for chunk in bytes.chunks_exact_mut(16) {
+        chunk.copy_from_slice(&crypto.nonce().to_be_bytes());
+    }
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+/// The key `secret` and the other side's share `theirs` agree on,
+/// bound to both shares, the admin's first.
+fn agreed_key(
+    session: u128,
+    secret: &Scalar,
+    invite: &Share,
+    join: &Share,
+    theirs: &Share,
+) -> Result<Tag, OnboardingError> {
+    let point = CompressedRistretto(*theirs)
+        .decompress()
+        .ok_or(OnboardingError::BadShare)?;
+    let shared = point * secret;
+    if shared.is_identity() {
+        return Err(OnboardingError::BadShare);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(KEY_DSI);
+    hasher.update(session.to_be_bytes());
+    hasher.update(invite);
+    hasher.update(join);
+    hasher.update(shared.compress().as_bytes());
+    Ok(hasher.finalize().into())
+}
+
+fn mac<T: Serialize>(key: &Tag, label: &[u8], data: &T) -> Result<Hmac<Sha256>, ClientError> {
+    let mut target = [0u8; TAGGED_MAX];
+    let serialized = to_slice(data, target.as_mut_slice())?;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).or(Err(ClientError::Unreachable))?;
+    mac.update(label);
+    mac.update(serialized);
+    Ok(mac)
+}
+
+fn tag<T: Serialize>(key: &Tag, label: &[u8], data: &T) -> Result<Tag, ClientError> {
+    Ok(mac(key, label, data)?.finalize().into_bytes().into())
+}
+
+fn check_tag<T: Serialize>(
+    key: &Tag,
+    label: &[u8],
+    data: &T,
+    tag: &Tag,
+) -> Result<(), OnboardingError> {
+    // Too large to have been tagged.
+    let mac = mac(key, label, data).or(Err(OnboardingError::BadTag))?;
+    mac.verify_slice(tag).or(Err(OnboardingError::BadTag))
+}
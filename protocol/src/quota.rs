@@ -0,0 +1,169 @@
+use super::*;
+
+/// Limits on what a single sender can have stored in a channel.
+///
+/// Usage is counted over windows of `window` records stored in the
+/// channel, so a member flooding the channel gets at most
+/// `max_records` records and `max_bytes` bytes in to each window.
+/// `Client::receive_buffer` skips an envelope over quota along with
+/// the sender's later envelopes, sync offers them again once the
+/// window has moved on. The owner's envelopes changing the channel,
+/// adding a member say, are not counted. Bytes are counted as the
+/// envelope is stored, compressed if it is.
+///
+/// Usage is not stored, setting a quota counts it again from the last
+/// `window` records in the log, with each sender's window taken to
+/// start at its first record among them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub window: u32,
+    pub max_records: u32,
+    pub max_bytes: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Usage {
+    /// Value of `Quotas::stored` when the window started.
+    start: u64,
+    records: u32,
+    bytes: u32,
+}
+
+/// A channel's quota and what each sender has used of it.
+pub(crate) struct Quotas<const MAX_NODES: usize> {
+    quota: Option<Quota>,
+    usage: FnvIndexMap<NodeId, Usage, MAX_NODES>,
+    /// Records stored since the channel was opened.
+    stored: u64,
+}
+
+impl<const MAX_NODES: usize> Quotas<MAX_NODES> {
+    pub(crate) fn new() -> Self {
+        Self {
+            quota: None,
+            usage: FnvIndexMap::new(),
+            stored: 0,
+        }
+    }
+
+    /// Set the quota, counting what each sender has in the last
+    /// `window` records of `storage`. `owner` is not counted for the
+    /// records that change the channel, nor is `my_id`, whose own
+    /// envelopes are never checked.
+    pub(crate) fn set<I: IO>(
+        &mut self,
+        quota: Option<Quota>,
+        storage: &Storage<I>,
+        owner: Option<NodeId>,
+        my_id: NodeId,
+    ) -> Result<(), ClientError> {
+        self.quota = quota;
+        self.usage.clear();
+        self.stored = 0;
+        let Some(quota) = quota else {
+            return Ok(());
+        };
+
+        // Go back slab by slab until there are `window` records.
+        let head = storage.io().get_head()?;
+        let mut slab = head
+            .checked_add(storage.io().slab_count()?)
+            .ok_or(ClientError::Unreachable)?;
+        let mut buffer = I::Buffer::default();
+        let mut records: u64 = 0;
+        while slab > head && records < u64::from(quota.window) {
+            slab -= 1;
+            let found = storage.io().get_slab(slab)?;
+            let mut cursor = found.get_head();
+            while let Some((record, next)) = found.read(cursor, buffer.as_mut())? {
+                if record.kind() == RecordKind::Data {
+                    records += 1;
+                }
+                cursor = next;
+            }
+        }
+        if records == 0 {
+            return Ok(());
+        }
+
+        let mut skip = records.saturating_sub(u64::from(quota.window));
+        let mut before = storage.count_before(slab)?;
+        let mut cursor = storage.io().get_slab(slab)?.get_head();
+        while let Some((record, next)) = storage.read_record(cursor, buffer.as_mut())? {
+            cursor = next;
+            let message = is_message(&record, before);
+            before = before.max(Some(record.message_count()));
+            if record.kind() != RecordKind::Data {
+                continue;
+            }
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+
+            let sender = record.sender();
+            if sender != my_id && (message || owner != Some(sender)) {
+                self.charge(sender, record.data().len())?;
+            }
+            self.stored();
+        }
+
+        Ok(())
+    }
+
+    /// Check that `from` can store a record of `len` bytes, nothing
+    /// is counted until `charge`.
+    pub(crate) fn check(&self, from: NodeId, len: usize) -> Result<(), ClientError> {
+        let Some(quota) = self.quota else {
+            return Ok(());
+        };
+
+        let (records, bytes) = match self.usage.get(&from) {
+            Some(usage) if !self.expired(&quota, usage) => (usage.records, usage.bytes),
+            _ => (0, 0),
+        };
+
+        let len = u32::try_from(len).unwrap_or(u32::MAX);
+        if records >= quota.max_records || bytes.saturating_add(len) > quota.max_bytes {
+            return Err(ClientError::QuotaExceeded(from));
+        }
+
+        Ok(())
+    }
+
+    /// Count a record of `len` bytes from `from` once it is stored.
+    pub(crate) fn charge(&mut self, from: NodeId, len: usize) -> Result<(), ClientError> {
+        let Some(quota) = self.quota else {
+            return Ok(());
+        };
+
+        let fresh = Usage {
+            start: self.stored,
+            records: 0,
+            bytes: 0,
+        };
+        let mut usage = match self.usage.get(&from) {
+            Some(usage) if !self.expired(&quota, usage) => *usage,
+            _ => fresh,
+        };
+        usage.records = usage.records.saturating_add(1);
+        usage.bytes = usage
+            .bytes
+            .saturating_add(u32::try_from(len).unwrap_or(u32::MAX));
+
+        if self.usage.insert(from, usage).is_err() {
+            return Err(ChannelError::ClientMax(MAX_NODES).into());
+        }
+
+        Ok(())
+    }
+
+    /// Count a record stored by anyone, moving the windows along.
+    pub(crate) fn stored(&mut self) {
+        self.stored = self.stored.saturating_add(1);
+    }
+
+    fn expired(&self, quota: &Quota, usage: &Usage) -> bool {
+        self.stored.saturating_sub(usage.start) >= u64::from(quota.window)
+    }
+}
@@ -1,13 +1,54 @@
-//use ascon_hash::{XofReader};
 use core::mem::size_of;
 
 use super::*;
 pub mod mem_io;
 
+#[cfg(any(test, feature = "std"))]
+pub mod file_io;
+
+pub mod nor_io;
+
+pub mod pool_io;
+
+pub mod encrypted_io;
+
+#[cfg(any(test, feature = "std"))]
+pub mod sim_flash;
+
+#[cfg(any(test, feature = "std"))]
+pub mod fault_io;
+
+mod layout;
+pub use layout::*;
+
+mod slab_io;
+pub use slab_io::*;
 
 mod slab;
 use slab::*;
 
+mod membership;
+pub use membership::*;
+
+mod snapshot;
+pub use snapshot::*;
+
+mod compress;
+pub use compress::COMPRESS_MAX;
+use compress::*;
+
+mod iter;
+pub use iter::*;
+
+mod tombstone;
+pub use tombstone::*;
+
+mod gap;
+pub use gap::*;
+
+mod stats;
+pub use stats::*;
+
 #[derive(Debug)]
 pub enum StorageError {
     DbFull,
@@ -19,6 +60,8 @@ pub enum StorageError {
     PostcardError(postcard::Error),
     SlabFull,
     OutOfOrder,
+    IoError,
+    BadChecksum,
 }
 
 impl From<postcard::Error> for StorageError {
@@ -30,19 +73,37 @@ impl From<postcard::Error> for StorageError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cursor {
     slab: usize,
     offset: usize,
     read_count: u32,
 }
 
+impl Cursor {
+    /// Index of the slab the cursor is in.
+    pub fn slab(&self) -> usize {
+        self.slab
+    }
+}
+
 pub trait IO {
+    /// Room for any one record, reads copy records in to one.
+    type Buffer: AsMut<[u8]> + Default;
+    /// Drop the oldest committed slab, the one at `get_head`.
     fn truncate(&mut self) -> Result<(), StorageError>;
     fn slab_size(&self) -> usize;
+    /// Every write to a slab starts on a multiple of this, see
+    /// `Media::write_size`.
+    fn write_size(&self) -> usize;
     fn free_slabs(&self) -> Result<usize, StorageError>;
     fn slab_count(&self) -> Result<usize, StorageError>;
     fn get_slab<'a>(&'a self, index: usize) -> Result<Slab<'a>, StorageError>;
+    /// Writer continuing the last committed slab, `None` if there is
+    /// no committed slab or the last one takes no more records.
+    fn append_writer<'a>(&'a mut self) -> Result<Option<SlabWriter<'a, Self>>, StorageError>
+    where
+        Self: Sized;
     fn new_writer<'a>(&'a mut self) -> Result<SlabWriter<'a, Self>, StorageError>
     where
         Self: Sized;
@@ -51,90 +112,229 @@ pub trait IO {
         &mut self,
         record_count: u32,
         max_sequence: u64,
-        offset: usize,
+        max_message: u64,
+        slab: usize,
+    ) -> Result<(), StorageError>;
+    /// Make the first `record_count` records of `slab` the end of the
+    /// log, dropping every later slab. A zero count drops `slab` too.
+    ///
+    /// This is a repair path. A slab cut back to some of its records
+    /// takes no more, as what followed them can not be written over.
+    fn rewind(
+        &mut self,
+        slab: usize,
+        record_count: u32,
+        max_sequence: u64,
+        max_message: u64,
     ) -> Result<(), StorageError>;
     fn get_head(&self) -> Result<usize, StorageError>;
+    /// Where the last complete checkpoint starts. Forgotten once its
+    /// slab is evicted or rewound past.
+    fn checkpoint(&self) -> Result<Option<Cursor>, StorageError>;
+    /// Record `cursor` as the start of the last complete checkpoint.
+    fn set_checkpoint(&mut self, cursor: Cursor) -> Result<(), StorageError>;
+}
+
+/// Byte addressable backing store for a `SlabIO`.
+///
+/// Slabs are read on demand in to the caller's buffer, so nothing
+/// has to keep an image of the contents in memory.
+pub trait Media {
+    /// Bytes the media holds.
+    fn size(&self) -> usize;
+    /// Fill `target` with the bytes starting at `offset`.
+    fn read(&self, offset: usize, target: &mut [u8]) -> Result<(), StorageError>;
+    /// `SlabIO` starts every write on a multiple of this. Media that
+    /// programs whole words pads the last one with `0xFF`.
+    fn write_size(&self) -> usize {
+        1
+    }
+    /// Write `data` at `offset`, which `SlabIO` only does to bytes
+    /// erased since they were last written.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), StorageError>;
+    /// Returns once every previous `write` is durable.
+    fn sync(&mut self) -> Result<(), StorageError>;
+    /// Reset `offset..(offset + len)` to all `0xFF`, the state flash
+    /// is left in by an erase. `SlabIO` only erases whole pages.
+    fn erase(&mut self, offset: usize, len: usize) -> Result<(), StorageError> {
+        const ERASED: [u8; 64] = [0xFF; 64];
+        let end = offset.checked_add(len).ok_or(StorageError::OutOfBounds)?;
+        let mut at = offset;
+        while at < end {
+            let step = ERASED.len().min(end - at);
+            self.write(at, &ERASED[..step])?;
+            at += step;
+        }
+        Ok(())
+    }
+}
+
+/// What a stored record holds. Only `Data` records are handed out
+/// by `Storage::read_into`, everything else is local book keeping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordKind {
+    /// A sealed envelope as it was sent or received.
+    Data,
+    /// Part of a snapshot of the channel state, see `Client::checkpoint`.
+    Checkpoint,
+    /// Part of the membership snapshot, which `Storage` keeps ahead of
+    /// eviction, see `Storage::write_membership`.
+    Membership,
+    /// Marks a stored envelope as deleted, see `Storage::append_tombstone`.
+    Tombstone,
+    /// Left by `Storage::compact_into` where a deleted envelope was,
+    /// keeping its counters.
+    Deleted,
+    /// The envelopes known to be missing, see `Storage::write_gaps`.
+    Gaps,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Record<'a> {
+    kind: RecordKind,
     max_sequence: u64,
     message_count: u64,
     sequence: u64,
     sender: NodeId,
     data: &'a [u8],
+    /// Kept in the frame rather than the record, see `COMPRESSED_FLAG`.
+    #[serde(skip)]
+    compressed: bool,
 }
 
-/*
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DbInfo {
-    root1_offset: usize,
-    root2_offset: usize,
-    wal_offset: usize,
-    wall_length: usize,
-}
+impl<'a> Record<'a> {
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
 
+    pub fn max_sequence(&self) -> u64 {
+        self.max_sequence
+    }
 
-const CHECKSUM_SIZE: usize = 4;
+    pub fn message_count(&self) -> u64 {
+        self.message_count
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DbRoot {
-    generation: u64,
-    data_start: usize,
-    data_end: usize,
-    check_sum: [u8 ; CHECKSUM_SIZE],
-}
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
 
-impl DbRoot {
-    pub fn new(generation: u64, data_start: usize, data_end: usize) -> Self {
-        let mut check_sum =
-            DbRoot::compute_checksum(generation, data_start, data_end);
+    pub fn sender(&self) -> NodeId {
+        self.sender
+    }
 
-        Self {
-            generation,
-            data_start,
-            data_end,
-            check_sum,
+    /// The data as stored, compressed if `is_compressed`.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// The data as written, expanded in to `target` if it was stored
+    /// compressed. `COMPRESS_MAX` bytes is always enough room.
+    pub fn decompress<'b>(&self, target: &'b mut [u8]) -> Result<&'b [u8], StorageError>
+    where
+        'a: 'b,
+    {
+        if !self.compressed {
+            return Ok(self.data);
         }
+        decompress(self.data, target)
     }
+}
+
+/// What `Storage` does once every slab is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    /// Refuse new slabs with `StorageError::DbFull`.
+    KeepAll,
+    /// Drop the oldest slab to make room for the new one.
+    DropOldest,
+}
 
-    pub fn validate(&self) -> Result<(), StorageError> {
-        let computed = DbRoot::compute_checksum(
-            self.generation,
-            self.data_start,
-            self.data_end);
+/// Range of records dropped from the head of the log.
+///
+/// The sequences are the `max_sequence` of the first and last
+/// record dropped, the messages their `message_count`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Evicted {
+    pub min_sequence: u64,
+    pub max_sequence: u64,
+    pub min_message: u64,
+    pub max_message: u64,
+    pub record_count: u64,
+}
 
-        if computed != self.check_sum {
-            return Err(StorageError::CorruptDB);
+impl Evicted {
+    fn new(record: &Record) -> Self {
+        Self {
+            min_sequence: record.max_sequence,
+            max_sequence: record.max_sequence,
+            min_message: record.message_count,
+            max_message: record.message_count,
+            record_count: 1,
         }
-        Ok(())
     }
 
-    fn compute_checksum(generation: u64, data_start: usize, data_end: usize) -> [u8; CHECKSUM_SIZE] {
-        let mut xof = AsconXof::default();
-        xof.update(&generation.to_be_bytes());
-        xof.update(&data_start.to_be_bytes());
-        xof.update(&data_end.to_be_bytes());
-        let mut reader = xof.finalize_xof();
-        let mut check_sum = [0u8; CHECKSUM_SIZE];
-        reader.read(&mut check_sum);
-        check_sum
+    fn extend(&mut self, later: &Evicted) {
+        self.max_sequence = later.max_sequence;
+        self.max_message = later.max_message;
+        self.record_count = self.record_count.saturating_add(later.record_count);
     }
 }
 
-/// Layout.
-/// [DbInfo, Padded to whole page]
-/// [Root 1, Padded to whole page]
-/// [WAL, Padded to whole page]
-/// [Root 2, Padded to whole page]
-/// [Records]
-*/
+/// Most damaged records a `VerifyReport` lists individually.
+pub const MAX_REPORTED_DAMAGE: usize = 8;
+
+/// A record that could not be read back.
+///
+/// `record` counts the good records before it in the slab and
+/// `offset` is where its frame starts from the start of the slab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    pub slab: usize,
+    pub offset: usize,
+    pub record: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub slabs: usize,
+    pub records: u64,
+    pub damaged_count: usize,
+    /// The first `MAX_REPORTED_DAMAGE` damaged records in log order.
+    pub damaged: Vec<Damage, MAX_REPORTED_DAMAGE>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.damaged_count == 0
+    }
+
+    fn add_damage(&mut self, damage: Damage) {
+        self.damaged_count = self.damaged_count.saturating_add(1);
+        // Past the limit we only keep count.
+        let _ = self.damaged.push(damage);
+    }
+}
 
 pub struct Storage<I>
 where
     I: IO,
 {
     io: I,
+    retention: Retention,
+    evicted: Option<Evicted>,
+    /// Where the membership snapshot is, `None` until the log has
+    /// been scanned for it.
+    membership: Option<Option<MembershipRun>>,
+    /// Set while the membership snapshot is being written so it is
+    /// not carried forward half way through.
+    writing_membership: bool,
+    /// Compress the data of new `Data` records.
+    compression: bool,
 }
 
 impl<I> Storage<I>
@@ -145,104 +345,366 @@ where
     //const SEQUENCE_LENGTH: usize = 8;
 
     pub fn new<'a>(io: I) -> Self {
-        Storage { io }
+        Self::with_retention(io, Retention::KeepAll)
     }
 
-    pub fn get_cursor_from_sequence(&self, sequence: u64) -> Result<Option<Cursor>, StorageError> {
-        let mut index = self.io.get_head()?;
+    pub fn with_retention(io: I, retention: Retention) -> Self {
+        Storage {
+            io,
+            retention,
+            evicted: None,
+            membership: None,
+            writing_membership: false,
+            compression: false,
+        }
+    }
 
-        loop {
-            let slab = match self.io.get_slab(index) {
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Compress the data of message records written from now on when
+    /// it saves space. `read_into` expands them again.
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.compression = enabled;
+    }
+
+    pub fn compression(&self) -> bool {
+        self.compression
+    }
+
+    /// Bytes `data` takes stored as a message record, less than its
+    /// length when compression makes it smaller.
+    pub(crate) fn stored_len(&self, data: &[u8]) -> usize {
+        let mut packed = [0u8; COMPRESS_MAX];
+        pack(RecordKind::Data, self.compression, data, &mut packed).0.len()
+    }
+
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    pub fn into_io(self) -> I {
+        self.io
+    }
+
+    /// Everything evicted since this `Storage` was created.
+    pub fn evicted(&self) -> Option<Evicted> {
+        self.evicted
+    }
+
+    /// Drop the oldest slab returning the range of records it held.
+    ///
+    /// The membership snapshot is carried forward first when there is
+    /// room, with no room it is dropped with the slab.
+    pub fn evict_oldest(&mut self) -> Result<Option<Evicted>, StorageError> {
+        self.keep_membership()?;
+
+        let head = self.io.get_head()?;
+        let mut buffer = I::Buffer::default();
+
+        let dropped = {
+            let slab = match self.io.get_slab(head) {
                 Ok(slab) => slab,
-                Err(e) => match e {
-                    StorageError::OutOfBounds => {
-                        return Ok(None);
-                    }
-                    _ => {
-                        return Err(e);
-                    }
-                },
+                Err(StorageError::OutOfBounds) => return Ok(None),
+                Err(e) => return Err(e),
             };
 
+            let mut dropped: Option<Evicted> = None;
             let mut cursor = slab.get_head();
-            let mut curosr_copy = cursor.clone();
-            while let Some((record, next)) = slab.read(cursor)? {
-                if record.max_sequence >= sequence {
-                    return Ok(Some(curosr_copy));
+            while let Some((record, next)) = slab.read(cursor, buffer.as_mut())? {
+                let current = Evicted::new(&record);
+                match dropped.as_mut() {
+                    Some(range) => range.extend(&current),
+                    None => dropped = Some(current),
                 }
-                curosr_copy = next.clone();
                 cursor = next;
             }
+            dropped
+        };
 
-            match index.checked_add(1) {
-                Some(v) => index = v,
-                None => return Ok(None),
+        self.io.truncate()?;
+
+        if let Some(range) = &dropped {
+            match self.evicted.as_mut() {
+                Some(total) => total.extend(range),
+                None => self.evicted = Some(*range),
             }
         }
+
+        Ok(dropped)
     }
 
-    pub fn get_cursor_from_index(
-        &self,
-        message_index: u64,
-    ) -> Result<Option<Cursor>, StorageError> {
-        let mut index = self.io.get_head()?;
+    /// Read back every record reporting any that are damaged.
+    ///
+    /// Reading a slab stops at its first damaged record as the
+    /// frames after it can not be trusted.
+    pub fn verify(&self) -> Result<VerifyReport, StorageError> {
+        let mut report = VerifyReport::default();
+        let mut buffer = I::Buffer::default();
+        let head = self.io.get_head()?;
+        let end = head
+            .checked_add(self.io.slab_count()?)
+            .ok_or(StorageError::Unreachable)?;
+
+        for index in head..end {
+            report.slabs += 1;
 
-        loop {
             let slab = match self.io.get_slab(index) {
                 Ok(slab) => slab,
-                Err(e) => match e {
-                    StorageError::OutOfBounds => {
-                        return Ok(None);
-                    }
-                    _ => {
-                        return Err(e);
-                    }
-                },
+                Err(_) => {
+                    report.add_damage(Damage {
+                        slab: index,
+                        offset: 0,
+                        record: 0,
+                    });
+                    continue;
+                }
             };
 
             let mut cursor = slab.get_head();
-            let mut curosr_copy = cursor.clone();
-            while let Some((record, next)) = slab.read(cursor)? {
-                if record.message_count >= message_index {
-                    return Ok(Some(curosr_copy));
+            loop {
+                let offset = cursor.offset;
+                let record = cursor.read_count;
+                match slab.read(cursor, buffer.as_mut()) {
+                    Ok(Some((_, next))) => {
+                        report.records = report.records.saturating_add(1);
+                        cursor = next;
+                    }
+                    Ok(None) => break,
+                    Err(_) => {
+                        report.add_damage(Damage {
+                            slab: index,
+                            offset,
+                            record,
+                        });
+                        break;
+                    }
                 }
-                curosr_copy = next.clone();
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Truncate the log back to the last good record before the
+    /// first damaged one, returning the damage that was cut at.
+    pub fn repair(&mut self) -> Result<Option<Damage>, StorageError> {
+        let report = self.verify()?;
+        let Some(damage) = report.damaged.first().cloned() else {
+            return Ok(None);
+        };
+
+        let mut max_sequence = 0;
+        let mut max_message = 0;
+        if damage.record > 0 {
+            let mut buffer = I::Buffer::default();
+            let slab = self.io.get_slab(damage.slab)?;
+            let mut cursor = slab.get_head();
+            for _ in 0..damage.record {
+                let (record, next) = slab
+                    .read(cursor, buffer.as_mut())?
+                    .ok_or(StorageError::Unreachable)?;
+                max_sequence = record.max_sequence;
+                max_message = record.message_count;
                 cursor = next;
             }
+        }
+
+        self.io
+            .rewind(damage.slab, damage.record, max_sequence, max_message)?;
+        // The snapshot may have been cut.
+        self.membership = None;
+
+        Ok(Some(damage))
+    }
+
+    /// Where the last complete checkpoint starts, `None` if there is
+    /// none or it has been evicted.
+    pub fn checkpoint(&self) -> Result<Option<Cursor>, StorageError> {
+        self.io.checkpoint()
+    }
+
+    /// Note the checkpoint starting at `cursor` as the one to restore
+    /// from, once all of its parts are stored.
+    pub fn set_checkpoint(&mut self, cursor: Cursor) -> Result<(), StorageError> {
+        self.io.set_checkpoint(cursor)
+    }
+
+    /// Cursor at the last record written.
+    pub fn last_cursor(&self) -> Result<Option<Cursor>, StorageError> {
+        let count = self.io.slab_count()?;
+        if count == 0 {
+            return Ok(None);
+        }
+        let index = self
+            .io
+            .get_head()?
+            .checked_add(count - 1)
+            .ok_or(StorageError::Unreachable)?;
+
+        let mut buffer = I::Buffer::default();
+        let slab = self.io.get_slab(index)?;
+        let mut cursor = slab.get_head();
+        let mut last = None;
+        while let Some((_, next)) = slab.read(cursor.clone(), buffer.as_mut())? {
+            last = Some(cursor);
+            cursor = next;
+        }
+        Ok(last)
+    }
+
+    /// Cursor at the first record with a `max_sequence` of at least
+    /// `sequence`.
+    pub fn get_cursor_from_sequence(&self, sequence: u64) -> Result<Option<Cursor>, StorageError> {
+        let Some(index) = self.find_slab(|slab| slab.max_sequence() >= sequence)? else {
+            return Ok(None);
+        };
+
+        let mut buffer = I::Buffer::default();
+        let slab = self.io.get_slab(index)?;
+        let mut cursor = slab.get_head();
+        while let Some((record, next)) = slab.read(cursor.clone(), buffer.as_mut())? {
+            if record.max_sequence >= sequence {
+                return Ok(Some(cursor));
+            }
+            cursor = next;
+        }
 
-            match index.checked_add(1) {
-                Some(v) => index = v,
-                None => return Ok(None),
+        // The slab header promised a match.
+        Err(StorageError::CorruptDB)
+    }
+
+    /// Cursor at the record whose `message_count` is `message_index`,
+    /// `None` if it has not been written yet or was evicted.
+    pub fn get_cursor_from_index(
+        &self,
+        message_index: u64,
+    ) -> Result<Option<Cursor>, StorageError> {
+        let Some(index) = self.find_slab(|slab| slab.max_message() >= message_index)? else {
+            return Ok(None);
+        };
+
+        let mut buffer = I::Buffer::default();
+        let slab = self.io.get_slab(index)?;
+        let mut cursor = slab.get_head();
+        while let Some((record, next)) = slab.read(cursor.clone(), buffer.as_mut())? {
+            if record.message_count > message_index {
+                // The message has been evicted.
+                return Ok(None);
             }
+            if record.message_count == message_index {
+                return Ok(Some(cursor));
+            }
+            cursor = next;
         }
+
+        // The slab header promised a match.
+        Err(StorageError::CorruptDB)
     }
 
-    pub fn read<'a>(
-        &'a self,
+    /// Binary search the committed slabs for the first one matching
+    /// `predicate`, which must be false for a prefix of the slabs and
+    /// true for the rest. Only slab headers are read.
+    fn find_slab<F>(&self, predicate: F) -> Result<Option<usize>, StorageError>
+    where
+        F: Fn(&Slab<'_>) -> bool,
+    {
+        let mut low = self.io.get_head()?;
+        let mut high = low
+            .checked_add(self.io.slab_count()?)
+            .ok_or(StorageError::Unreachable)?;
+        let end = high;
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if predicate(&self.io.get_slab(middle)?) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+
+        if low == end {
+            return Ok(None);
+        }
+        Ok(Some(low))
+    }
+
+    /// Read the next message record at or after `cursor` in to
+    /// `target`, expanding it if it is stored compressed. `COMPRESS_MAX`
+    /// bytes is always enough room.
+    pub fn read_into<'b>(
+        &self,
         cursor: Cursor,
-    ) -> Result<Option<(&'a [u8], Cursor)>, StorageError> {
+        target: &'b mut [u8],
+    ) -> Result<Option<(&'b [u8], Cursor)>, StorageError> {
+        let mut buffer = I::Buffer::default();
         let mut cursor = cursor;
+        while let Some((record, next)) = self.read_record(cursor, buffer.as_mut())? {
+            if record.kind == RecordKind::Data {
+                if record.compressed {
+                    return Ok(Some((decompress(record.data, target)?, next)));
+                }
+                let length = record.data.len();
+                let copy = target
+                    .get_mut(..length)
+                    .ok_or(StorageError::RecordTooLarge(length))?;
+                copy.copy_from_slice(record.data);
+                return Ok(Some((copy, next)));
+            }
+            cursor = next;
+        }
+
+        Ok(None)
+    }
+
+    /// Like `read_into` but with room of its own, so it can be kept.
+    pub fn read(&self, cursor: Cursor) -> Result<Option<(Vec<u8, COMPRESS_MAX>, Cursor)>, StorageError> {
+        let mut data = Vec::new();
+        data.resize_default(COMPRESS_MAX).or(Err(StorageError::Unreachable))?;
+        let Some((found, next)) = self.read_into(cursor, &mut data)? else {
+            return Ok(None);
+        };
+        let length = found.len();
+        data.truncate(length);
+        Ok(Some((data, next)))
+    }
+
+    /// Like `read_into` but returns records of every kind, as stored,
+    /// read in to `buffer`. An `IO::Buffer` always has room.
+    pub fn read_record<'b>(
+        &self,
+        cursor: Cursor,
+        buffer: &'b mut [u8],
+    ) -> Result<Option<(Record<'b>, Cursor)>, StorageError> {
         let slab_index = cursor.slab;
         let slab = self.io.get_slab(slab_index)?;
 
-        if let Some((record, next)) = slab.read(cursor)? {
-            return Ok(Some((record.data, next)));
+        if cursor.read_count < slab.record_count() {
+            return slab.read(cursor, buffer);
         }
 
         let Some(next_index) = slab_index.checked_add(1) else {
             return Ok(None);
         };
 
-        if next_index >= self.io.slab_count()? {
-            return Ok(None);
-        }
-
-        let slab = self.io.get_slab(next_index)?;
-
-        cursor = slab.get_head();
+        let slab = match self.io.get_slab(next_index) {
+            Ok(slab) => slab,
+            Err(StorageError::OutOfBounds) => return Ok(None),
+            Err(e) => return Err(e),
+        };
 
-        if let Some((record, next)) = slab.read(cursor)? {
-            return Ok(Some((record.data, next)));
+        if let Some(found) = slab.read(slab.get_head(), buffer)? {
+            return Ok(Some(found));
         }
 
         // This could only happen if there was an empty
@@ -250,8 +712,70 @@ where
         Err(StorageError::Unreachable)
     }
 
+    /// Write and commit a single record, packing it in to the last
+    /// slab if it still has room and starting a new slab if not.
+    pub fn append(
+        &mut self,
+        max_sequence: u64,
+        message_count: u64,
+        sequence: u64,
+        sender: NodeId,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.append_kind(RecordKind::Data, max_sequence, message_count, sequence, sender, data)
+    }
+
+    /// Append one part of a checkpoint. Checkpoint records are skipped
+    /// by `read_into` so sync and message lookups never see them.
+    pub fn append_checkpoint(
+        &mut self,
+        max_sequence: u64,
+        message_count: u64,
+        sender: NodeId,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        self.append_kind(RecordKind::Checkpoint, max_sequence, message_count, 0, sender, data)
+    }
+
+    pub(crate) fn append_kind(
+        &mut self,
+        kind: RecordKind,
+        max_sequence: u64,
+        message_count: u64,
+        sequence: u64,
+        sender: NodeId,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        let compression = self.compression;
+        if let Some(mut writer) = self.io.append_writer()? {
+            writer.set_compression(compression);
+            match writer.write_kind(kind, max_sequence, message_count, sequence, sender, data) {
+                Ok(()) => return writer.commit(),
+                Err(StorageError::SlabFull) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut writer = self.get_writer()?;
+        writer.write_kind(kind, max_sequence, message_count, sequence, sender, data)?;
+        writer.commit()
+    }
+
+    /// Writer for a new slab, evicting the oldest slab to make room
+    /// when retention allows it.
     pub fn get_writer<'a>(&'a mut self) -> Result<SlabWriter<'a, I>, StorageError> {
-        self.io.new_writer()
+        if self.retention == Retention::DropOldest {
+            // Make sure eviction will not reach the snapshot first.
+            self.keep_membership()?;
+        }
+
+        if self.retention == Retention::DropOldest && self.io.free_slabs()? == 0 {
+            self.evict_oldest()?;
+        }
+
+        let mut writer = self.io.new_writer()?;
+        writer.set_compression(self.compression);
+        Ok(writer)
     }
 }
 
@@ -0,0 +1,30 @@
+use super::*;
+
+use lzss::{Lzss, SliceReader, SliceWriter};
+
+/// Largest record data `SlabWriter` will compress, so expanding a
+/// record never takes more room than this.
+pub const COMPRESS_MAX: usize = 4096;
+
+/// Set in the length of a frame whose record data is compressed.
+/// Stores written before compression never set it.
+pub(crate) const COMPRESSED_FLAG: u32 = 1 << 31;
+
+/// A 1KB window keeps the stack used by both directions small.
+type RecordLzss = Lzss<10, 4, 0x20, { 1 << 10 }, { 2 << 10 }>;
+
+/// Compress `data` in to `target`, `None` if that would not make it
+/// any smaller.
+pub(crate) fn compress<'b>(data: &[u8], target: &'b mut [u8]) -> Option<&'b [u8]> {
+    let room = data.len().checked_sub(1)?.min(target.len());
+    let out = target.get_mut(..room)?;
+    let len = RecordLzss::compress_stack(SliceReader::new(data), SliceWriter::new(out)).ok()?;
+    target.get(..len)
+}
+
+/// Expand compressed record data in to `target`.
+pub(crate) fn decompress<'b>(data: &[u8], target: &'b mut [u8]) -> Result<&'b [u8], StorageError> {
+    let len = RecordLzss::decompress_stack(SliceReader::new(data), SliceWriter::new(target))
+        .or(Err(StorageError::OutOfBounds))?;
+    target.get(..len).ok_or(StorageError::Unreachable)
+}
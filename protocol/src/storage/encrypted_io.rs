@@ -0,0 +1,359 @@
+use super::*;
+
+use aes_gcm_siv::aead::{AeadInPlace, KeyInit};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, Tag};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+const TAG_SIZE: usize = 16;
+
+/// Bytes before the data of each sealed record and in each counter
+/// reservation, [counter: u64][tag: [u8; 16]].
+const SEAL_HEADER_SIZE: usize = size_of::<u64>() + TAG_SIZE;
+
+/// Room for the fields of a record other than its data, as postcard
+/// writes them.
+const ASSOCIATED_MAX: usize = 96;
+
+/// Counters reserved by each write to the counter media.
+const COUNTER_STEP: u64 = 256;
+
+/// HKDF info for the storage key, so the device secret can be used
+/// for other things too.
+const KEY_INFO: &[u8] = b"finder storage at rest";
+
+/// `IO` keeping the data of every record encrypted, layered over any
+/// other `IO`.
+///
+/// Each record is one write to its slab and its data is sealed as a
+/// whole with AES-GCM-SIV under a key derived from a device secret,
+/// so a seal costs `SEAL_HEADER_SIZE` bytes a record. The rest of the
+/// record stays in the clear for the inner `IO` to order and recover
+/// slabs by, and is bound to the data as associated data. A record
+/// that does not open reads as `BadChecksum`.
+///
+/// Nonces come from a counter that never goes back: counters are
+/// reserved `COUNTER_STEP` at a time in the two halves of the counter
+/// media, and the reservation is synced before any record uses it, so
+/// a restart picks up past every counter that may have been used.
+/// Each half is erased on its own, so should be whole erase blocks.
+pub struct EncryptedIO<I, M> {
+    inner: I,
+    counters: M,
+    cipher: Aes256GcmSiv,
+    /// Counter media bytes per reservation.
+    counter_stride: usize,
+    /// Counter the next record is sealed with.
+    next: u64,
+    /// Counters below this may have been used, as the counter media
+    /// records.
+    reserved: u64,
+    counter_page: usize,
+    counter_slot: usize,
+}
+
+impl<I: IO, M: Media> EncryptedIO<I, M> {
+    /// Open the records of `inner` under a key derived from `secret`,
+    /// keeping the nonce counter in `counters`. Counters holding
+    /// something but no reservation that opens mean the wrong secret
+    /// or a damaged store, and fail with `BadChecksum` rather than
+    /// start the counter over.
+    pub fn open(inner: I, counters: M, secret: &[u8]) -> Result<Self, StorageError> {
+        let counter_stride = align_up(SEAL_HEADER_SIZE, counters.write_size())?;
+
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, secret)
+            .expand(KEY_INFO, &mut key)
+            .or(Err(StorageError::Unreachable))?;
+        let cipher = Aes256GcmSiv::new(&key.into());
+
+        let mut io = Self {
+            inner,
+            counters,
+            cipher,
+            counter_stride,
+            next: 0,
+            reserved: 0,
+            counter_page: 0,
+            counter_slot: 0,
+        };
+        if io.counter_slots() == 0 {
+            return Err(StorageError::OutOfBounds);
+        }
+        io.open_counter()?;
+
+        Ok(io)
+    }
+
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    /// The inner `IO` and the counter media.
+    pub fn into_parts(self) -> (I, M) {
+        (self.inner, self.counters)
+    }
+
+    /// Carry on from the newest reservation in the counter media.
+    fn open_counter(&mut self) -> Result<(), StorageError> {
+        let slots = self.counter_slots();
+        let mut best: Option<(u64, usize)> = None;
+        let mut used = [0usize; 2];
+        let mut blank = true;
+
+        for (page, used) in used.iter_mut().enumerate() {
+            for slot in 0..slots {
+                let mut bytes = [0u8; SEAL_HEADER_SIZE];
+                self.counters.read(self.counter_offset(page, slot)?, &mut bytes)?;
+                if bytes.iter().all(|b| *b == 0xFF) {
+                    continue;
+                }
+                *used = slot + 1;
+                blank &= bytes.iter().all(|b| *b == 0);
+
+                let Some(value) = self.open_reservation(&bytes)? else {
+                    continue;
+                };
+                if best.is_none_or(|(newest, _)| value > newest) {
+                    best = Some((value, page));
+                }
+            }
+        }
+
+        match best {
+            Some((value, page)) => {
+                self.next = value;
+                self.reserved = value;
+                self.counter_page = page;
+                self.counter_slot = used[page];
+            }
+            None if blank => {
+                let half = self.counter_half();
+                self.counters.erase(0, half)?;
+                self.counters.erase(half, half)?;
+            }
+            None => return Err(StorageError::BadChecksum),
+        }
+
+        Ok(())
+    }
+
+    fn open_reservation(&self, bytes: &[u8; SEAL_HEADER_SIZE]) -> Result<Option<u64>, StorageError> {
+        let (value, offset) = read_u64(bytes, 0)?;
+        let tag = bytes.get(offset..).ok_or(StorageError::Unreachable)?;
+        let opened = self
+            .cipher
+            .decrypt_in_place_detached(&nonce(COUNTER_NONCE, value), &[], &mut [], Tag::from_slice(tag))
+            .is_ok();
+        Ok(opened.then_some(value))
+    }
+
+    /// Record that counters below `value` may be used, once it is
+    /// durable.
+    fn reserve(&mut self, value: u64) -> Result<(), StorageError> {
+        if self.counter_slot >= self.counter_slots() {
+            // The half left behind still holds the newest reservation
+            // until the first one in this half lands.
+            self.counter_page = (self.counter_page + 1) % 2;
+            self.counter_slot = 0;
+            let half = self.counter_half();
+            self.counters.erase(self.counter_page * half, half)?;
+        }
+
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(COUNTER_NONCE, value), &[], &mut [])
+            .or(Err(StorageError::Unreachable))?;
+        let mut bytes = [0u8; SEAL_HEADER_SIZE];
+        let offset = write_u64(value, &mut bytes, 0)?;
+        bytes
+            .get_mut(offset..)
+            .ok_or(StorageError::Unreachable)?
+            .copy_from_slice(&tag);
+
+        self.counters
+            .write(self.counter_offset(self.counter_page, self.counter_slot)?, &bytes)?;
+        self.counter_slot += 1;
+        self.counters.sync()?;
+        self.reserved = value;
+        Ok(())
+    }
+
+    fn next_counter(&mut self) -> Result<u64, StorageError> {
+        if self.next >= self.reserved {
+            let value = self
+                .next
+                .checked_add(COUNTER_STEP)
+                .ok_or(StorageError::Unreachable)?;
+            self.reserve(value)?;
+        }
+        let counter = self.next;
+        self.next += 1;
+        Ok(counter)
+    }
+
+    fn counter_half(&self) -> usize {
+        self.counters.size() / 2
+    }
+
+    fn counter_slots(&self) -> usize {
+        self.counter_half() / self.counter_stride
+    }
+
+    fn counter_offset(&self, page: usize, slot: usize) -> Result<usize, StorageError> {
+        slot.checked_mul(self.counter_stride)
+            .and_then(|offset| offset.checked_add(page * self.counter_half()))
+            .ok_or(StorageError::Unreachable)
+    }
+}
+
+impl<I: IO, M: Media> RecordOpener for EncryptedIO<I, M> {
+    fn open<'b>(&self, body: &'b mut [u8]) -> Result<Record<'b>, StorageError> {
+        let (associated, len, at) = {
+            let record: Record = from_bytes(body)?;
+            let (associated, len) = associated(&record)?;
+            // The data is the last thing postcard writes.
+            (associated, len, body.len() - record.data.len())
+        };
+
+        let sealed = body.get_mut(at..).ok_or(StorageError::Unreachable)?;
+        if sealed.len() < SEAL_HEADER_SIZE {
+            return Err(StorageError::CorruptDB);
+        }
+        let (header, text) = sealed.split_at_mut(SEAL_HEADER_SIZE);
+        let (counter, offset) = read_u64(header, 0)?;
+        let tag = *Tag::from_slice(header.get(offset..).ok_or(StorageError::Unreachable)?);
+        self.cipher
+            .decrypt_in_place_detached(&nonce(RECORD_NONCE, counter), &associated[..len], text, &tag)
+            .or(Err(StorageError::BadChecksum))?;
+
+        let body: &'b [u8] = body;
+        let mut record: Record<'b> = from_bytes(body)?;
+        record.data = record
+            .data
+            .get(SEAL_HEADER_SIZE..)
+            .ok_or(StorageError::Unreachable)?;
+        Ok(record)
+    }
+}
+
+impl<I: IO, M: Media> IO for EncryptedIO<I, M> {
+    type Buffer = I::Buffer;
+
+    fn truncate(&mut self) -> Result<(), StorageError> {
+        self.inner.truncate()
+    }
+
+    fn slab_size(&self) -> usize {
+        self.inner.slab_size()
+    }
+
+    fn write_size(&self) -> usize {
+        self.inner.write_size()
+    }
+
+    fn free_slabs(&self) -> Result<usize, StorageError> {
+        self.inner.free_slabs()
+    }
+
+    fn slab_count(&self) -> Result<usize, StorageError> {
+        self.inner.slab_count()
+    }
+
+    fn get_slab(&self, index: usize) -> Result<Slab<'_>, StorageError> {
+        Ok(self.inner.get_slab(index)?.with_opener(self))
+    }
+
+    fn append_writer(&mut self) -> Result<Option<SlabWriter<'_, Self>>, StorageError> {
+        let Some(writer) = self.inner.append_writer()? else {
+            return Ok(None);
+        };
+        let state = writer.into_state();
+        Ok(Some(SlabWriter::from_state(self, state)))
+    }
+
+    fn new_writer(&mut self) -> Result<SlabWriter<'_, Self>, StorageError> {
+        let state = self.inner.new_writer()?.into_state();
+        Ok(SlabWriter::from_state(self, state))
+    }
+
+    fn write_record(&mut self, offset: usize, end: usize, record: &Record) -> Result<usize, StorageError> {
+        let mut buffer = I::Buffer::default();
+        // Too big for the buffer is too big for any slab.
+        let sealed = SEAL_HEADER_SIZE
+            .checked_add(record.data.len())
+            .and_then(|len| buffer.as_mut().get_mut(..len))
+            .ok_or(StorageError::SlabFull)?;
+        let (header, text) = sealed.split_at_mut(SEAL_HEADER_SIZE);
+        text.copy_from_slice(record.data);
+
+        let (associated, len) = associated(record)?;
+        let counter = self.next_counter()?;
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(RECORD_NONCE, counter), &associated[..len], text)
+            .or(Err(StorageError::Unreachable))?;
+        let at = write_u64(counter, header, 0)?;
+        write_arr(tag.into(), header, at)?;
+
+        let sealed = Record {
+            data: sealed,
+            ..*record
+        };
+        self.inner.write_record(offset, end, &sealed)
+    }
+
+    fn commit(
+        &mut self,
+        record_count: u32,
+        max_sequence: u64,
+        max_message: u64,
+        slab: usize,
+    ) -> Result<(), StorageError> {
+        self.inner.commit(record_count, max_sequence, max_message, slab)
+    }
+
+    fn rewind(
+        &mut self,
+        slab: usize,
+        record_count: u32,
+        max_sequence: u64,
+        max_message: u64,
+    ) -> Result<(), StorageError> {
+        self.inner.rewind(slab, record_count, max_sequence, max_message)
+    }
+
+    fn get_head(&self) -> Result<usize, StorageError> {
+        self.inner.get_head()
+    }
+
+    fn checkpoint(&self) -> Result<Option<Cursor>, StorageError> {
+        self.inner.checkpoint()
+    }
+
+    fn set_checkpoint(&mut self, cursor: Cursor) -> Result<(), StorageError> {
+        self.inner.set_checkpoint(cursor)
+    }
+}
+
+/// The fields of `record` other than its data, which its seal covers.
+fn associated(record: &Record) -> Result<([u8; ASSOCIATED_MAX], usize), StorageError> {
+    let mut bytes = [0u8; ASSOCIATED_MAX];
+    let fields = Record {
+        data: &[],
+        ..*record
+    };
+    let len = to_slice(&fields, &mut bytes)?.len();
+    Ok((bytes, len))
+}
+
+/// Nonce prefixes, so a record and a reservation never share a nonce.
+const RECORD_NONCE: u32 = 0;
+const COUNTER_NONCE: u32 = 1;
+
+fn nonce(kind: u32, counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&kind.to_be_bytes());
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    nonce.into()
+}
@@ -0,0 +1,164 @@
+extern crate std;
+use std::vec;
+use std::vec::Vec;
+
+use super::*;
+
+/// What happens to the operation a `FaultMedia` is told to break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The operation returns `StorageError::IoError` and changes
+    /// nothing, later operations work.
+    Fail,
+    /// Only the first bytes of the write land before the power goes.
+    Tear(usize),
+    /// The power goes just before the operation, it and everything
+    /// after it are lost.
+    Drop,
+}
+
+/// Media for power loss testing, it passes writes through to `inner`
+/// until a chosen operation breaks.
+///
+/// Writes and erases count as one operation each. After a `Tear` or
+/// `Drop` the device is off: later operations still succeed and are
+/// read back, as the running code would have seen, but never reach
+/// `inner`. Rebooting is building a new `SlabIO` from `into_inner`.
+pub struct FaultMedia<M> {
+    inner: M,
+    /// What the running code sees, `inner` plus writes lost to the
+    /// power cut.
+    view: Vec<u8>,
+    operations: usize,
+    fault: Option<(usize, Fault)>,
+    powered: bool,
+}
+
+impl<M: Media> FaultMedia<M> {
+    /// Media breaking the operation numbered `at`, counted from zero,
+    /// with `fault`. `None` never breaks and can count the operations
+    /// a run takes.
+    pub fn new(inner: M, fault: Option<(usize, Fault)>) -> Result<Self, StorageError> {
+        let mut view = vec![0u8; inner.size()];
+        inner.read(0, &mut view)?;
+        Ok(Self {
+            view,
+            inner,
+            operations: 0,
+            fault,
+            powered: true,
+        })
+    }
+
+    /// Operations seen so far.
+    pub fn operations(&self) -> usize {
+        self.operations
+    }
+
+    /// Whether the power is still on.
+    pub fn powered(&self) -> bool {
+        self.powered
+    }
+
+    /// What survived, for rebooting.
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Count an operation returning what should happen to it.
+    fn next_fault(&mut self) -> Option<Fault> {
+        let at = self.operations;
+        self.operations += 1;
+
+        match self.fault {
+            Some((fault_at, fault)) if self.powered && fault_at == at => Some(fault),
+            _ => None,
+        }
+    }
+
+    fn view_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8], StorageError> {
+        let end = offset.checked_add(len).ok_or(StorageError::OutOfBounds)?;
+        self.view
+            .get_mut(offset..end)
+            .ok_or(StorageError::OutOfBounds)
+    }
+}
+
+impl<M: Media> Media for FaultMedia<M> {
+    fn size(&self) -> usize {
+        self.view.len()
+    }
+
+    fn read(&self, offset: usize, target: &mut [u8]) -> Result<(), StorageError> {
+        let end = offset
+            .checked_add(target.len())
+            .ok_or(StorageError::OutOfBounds)?;
+        let source = self.view.get(offset..end).ok_or(StorageError::OutOfBounds)?;
+        target.copy_from_slice(source);
+        Ok(())
+    }
+
+    fn write_size(&self) -> usize {
+        self.inner.write_size()
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), StorageError> {
+        let fault = self.next_fault();
+        if fault == Some(Fault::Fail) {
+            return Err(StorageError::IoError);
+        }
+
+        self.view_mut(offset, data.len())?.copy_from_slice(data);
+
+        match fault {
+            Some(Fault::Tear(len)) => {
+                self.powered = false;
+                let torn = data.get(..len).unwrap_or(data);
+                self.inner.write(offset, torn)
+            }
+            Some(Fault::Drop) => {
+                self.powered = false;
+                Ok(())
+            }
+            _ if self.powered => self.inner.write(offset, data),
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        if !self.powered {
+            return Ok(());
+        }
+        self.inner.sync()
+    }
+
+    fn erase(&mut self, offset: usize, len: usize) -> Result<(), StorageError> {
+        let fault = self.next_fault();
+        if fault == Some(Fault::Fail) {
+            return Err(StorageError::IoError);
+        }
+
+        self.view_mut(offset, len)?.fill(0xFF);
+
+        match fault {
+            Some(Fault::Tear(torn)) => {
+                self.powered = false;
+                self.inner.erase(offset, torn.min(len))
+            }
+            Some(Fault::Drop) => {
+                self.powered = false;
+                Ok(())
+            }
+            _ if self.powered => self.inner.erase(offset, len),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub type FaultIO<M, const SLAB_SIZE: usize> = SlabIO<FaultMedia<M>, SLAB_SIZE>;
+
+impl<M: Media, const SLAB_SIZE: usize> SlabIO<FaultMedia<M>, SLAB_SIZE> {
+    pub fn with_fault(inner: M, fault: Option<(usize, Fault)>) -> Result<Self, StorageError> {
+        SlabIO::from_media(FaultMedia::new(inner, fault)?)
+    }
+}
@@ -0,0 +1,95 @@
+use super::*;
+
+extern crate std;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// A regular file, read and written in place.
+///
+/// `sync` waits on `fsync`.
+pub struct FileMedia {
+    file: File,
+    len: usize,
+}
+
+impl FileMedia {
+    /// Open or create the file at `path`, growing it to `len` bytes
+    /// if needed.
+    pub fn open<P: AsRef<Path>>(path: P, len: usize) -> Result<Self, StorageError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_error)?;
+
+        let current = file.metadata().map_err(io_error)?.len();
+        let wanted = u64::try_from(len).map_err(|_| StorageError::OutOfBounds)?;
+
+        // Shrinking would silently drop committed slabs.
+        if current > wanted {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        if current < wanted {
+            file.set_len(wanted).map_err(io_error)?;
+            file.sync_all().map_err(io_error)?;
+        }
+
+        Ok(Self { file, len })
+    }
+
+    /// Seek to `offset` checking `len` bytes from there are in the file.
+    fn seek(&self, offset: usize, len: usize) -> Result<(), StorageError> {
+        let end = offset.checked_add(len).ok_or(StorageError::OutOfBounds)?;
+        if end > self.len {
+            return Err(StorageError::OutOfBounds);
+        }
+        let position = u64::try_from(offset).map_err(|_| StorageError::OutOfBounds)?;
+        (&self.file)
+            .seek(SeekFrom::Start(position))
+            .map_err(io_error)?;
+        Ok(())
+    }
+}
+
+impl Media for FileMedia {
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    fn read(&self, offset: usize, target: &mut [u8]) -> Result<(), StorageError> {
+        self.seek(offset, target.len())?;
+        (&self.file).read_exact(target).map_err(io_error)
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), StorageError> {
+        self.seek(offset, data.len())?;
+        self.file.write_all(data).map_err(io_error)
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        self.file.sync_data().map_err(io_error)
+    }
+}
+
+fn io_error(err: std::io::Error) -> StorageError {
+    log::error!("file io failed: {}", err);
+    StorageError::IoError
+}
+
+pub type FileIO<const SLAB_SIZE: usize> = SlabIO<FileMedia, SLAB_SIZE>;
+
+impl<const SLAB_SIZE: usize> SlabIO<FileMedia, SLAB_SIZE> {
+    /// Open the store at `path` with room for `slab_capacity` slabs,
+    /// recovering any slabs committed by a previous process.
+    pub fn open<P: AsRef<Path>>(path: P, slab_capacity: usize) -> Result<Self, StorageError> {
+        let len = slab_capacity
+            .checked_add(DbInfo::reserved_pages())
+            .and_then(|pages| pages.checked_mul(SLAB_SIZE))
+            .ok_or(StorageError::OutOfBounds)?;
+        SlabIO::from_media(FileMedia::open(path, len)?)
+    }
+}
@@ -0,0 +1,67 @@
+use super::*;
+
+/// Most gaps a channel keeps track of, the oldest is forgotten first.
+pub const MAX_GAPS: usize = 8;
+
+/// Largest serialized `Gap`, each counter is a varint of up to ten
+/// bytes.
+const GAP_MAX: usize = size_of::<NodeId>() + 3 * 10;
+
+/// Envelopes from `node` that were never received, `after + 1..=until`
+/// holds the sequence of at least one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Gap {
+    pub node: NodeId,
+    /// Last sequence received from `node` before the gap.
+    pub after: u64,
+    /// Sequence of the newest missing envelope.
+    pub until: u64,
+    /// Messages stored when the gap was found, the missing ones come
+    /// after message `index`.
+    pub index: u64,
+}
+
+pub type Gaps = Vec<Gap, MAX_GAPS>;
+
+impl Gap {
+    /// Whether the two gaps are part of the same run of missing
+    /// envelopes.
+    pub fn overlaps(&self, other: &Gap) -> bool {
+        self.node == other.node && self.after <= other.until && other.after <= self.until
+    }
+}
+
+impl<I> Storage<I>
+where
+    I: IO,
+{
+    /// Replace the channel's gaps with `gaps`.
+    ///
+    /// Only the latest list is read back, so it is lost once evicted.
+    /// `Client` writes it again with each checkpoint.
+    pub fn write_gaps(&mut self, sender: NodeId, gaps: &Gaps) -> Result<(), StorageError> {
+        let mut target = [0u8; MAX_GAPS * GAP_MAX + 1];
+        let bytes = to_slice(gaps, &mut target)?;
+        let (max_sequence, max_message) = self.tail_counters()?;
+        self.append_kind(RecordKind::Gaps, max_sequence, max_message, 0, sender, bytes)
+    }
+
+    /// The latest list written by `write_gaps`, empty if none is
+    /// stored.
+    pub fn read_gaps(&self) -> Result<Gaps, StorageError> {
+        let mut found = Gaps::new();
+        let Some(mut cursor) = self.get_cursor_from_sequence(0)? else {
+            return Ok(found);
+        };
+
+        let mut buffer = I::Buffer::default();
+        while let Some((record, next)) = self.read_record(cursor, buffer.as_mut())? {
+            if record.kind == RecordKind::Gaps {
+                found = from_bytes(record.data)?;
+            }
+            cursor = next;
+        }
+
+        Ok(found)
+    }
+}
@@ -0,0 +1,243 @@
+use super::*;
+
+/// A message record found by `iter_range` or `iter_rev`, read its
+/// data with `Storage::read_into` at `cursor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMessage {
+    pub cursor: Cursor,
+    pub message_count: u64,
+    pub sequence: u64,
+    pub sender: NodeId,
+}
+
+impl StoredMessage {
+    fn new(record: &Record<'_>, cursor: Cursor) -> Self {
+        Self {
+            cursor,
+            message_count: record.message_count(),
+            sequence: record.sequence(),
+            sender: record.sender(),
+        }
+    }
+}
+
+/// Records `RevIter` reads from a slab in one pass.
+const REV_BATCH: usize = 16;
+
+/// Whether `record` holds a message. Envelopes changing the channel,
+/// adding a member say, are stored as `Data` too but keep the
+/// `message_count` of the message before them, `before` is the largest
+/// count stored ahead of `record`. `None` where that is not known,
+/// at the oldest slab, where only the first count of zero is ruled
+/// out.
+pub(crate) fn is_message(record: &Record<'_>, before: Option<u64>) -> bool {
+    record.kind() == RecordKind::Data && record.message_count() > before.unwrap_or(0)
+}
+
+/// Whether one of `deleted` names `record`.
+fn is_deleted(deleted: &[Tombstone], record: &Record<'_>) -> bool {
+    deleted.iter().any(|tombstone| tombstone.names(record))
+}
+
+/// Message records oldest first, see `Storage::iter_range`.
+pub struct RangeIter<'a, I: IO> {
+    storage: &'a Storage<I>,
+    deleted: &'a [Tombstone],
+    cursor: Option<Cursor>,
+    before: Option<u64>,
+    from_index: u64,
+    to_index: u64,
+    buffer: I::Buffer,
+}
+
+impl<'a, I: IO> RangeIter<'a, I> {
+    /// Skip the envelopes `deleted` names as well, see
+    /// `Storage::tombstones`.
+    pub fn skip_deleted(self, deleted: &'a [Tombstone]) -> Self {
+        Self { deleted, ..self }
+    }
+}
+
+impl<'a, I: IO> Iterator for RangeIter<'a, I> {
+    type Item = Result<StoredMessage, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(cursor) = self.cursor.take() {
+            let (record, next) = match self.storage.read_record(cursor.clone(), self.buffer.as_mut()) {
+                Ok(Some(found)) => found,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let message = is_message(&record, self.before) && !is_deleted(self.deleted, &record);
+            self.before = self.before.max(Some(record.message_count()));
+            if !message || record.message_count() < self.from_index {
+                self.cursor = Some(next);
+                continue;
+            }
+            if record.message_count() >= self.to_index {
+                return None;
+            }
+
+            let found = StoredMessage::new(&record, cursor);
+            self.cursor = Some(next);
+            return Some(Ok(found));
+        }
+
+        None
+    }
+}
+
+/// Message records newest first, see `Storage::iter_rev`.
+///
+/// Records only link forward, so the messages of a slab are read in
+/// one pass and handed out from the end. A slab with more than
+/// `REV_BATCH` messages takes a pass per batch.
+pub struct RevIter<'a, I: IO> {
+    storage: &'a Storage<I>,
+    deleted: &'a [Tombstone],
+    head: usize,
+    /// Slab the next records come from, `None` once past the head.
+    slab: Option<usize>,
+    /// Records at the start of `slab` not yet read.
+    left: u32,
+    batch: Vec<StoredMessage, REV_BATCH>,
+    buffer: I::Buffer,
+}
+
+impl<'a, I: IO> RevIter<'a, I> {
+    /// Skip the envelopes `deleted` names as well, see
+    /// `Storage::tombstones`.
+    pub fn skip_deleted(self, deleted: &'a [Tombstone]) -> Self {
+        Self { deleted, ..self }
+    }
+
+    fn step(&mut self) -> Result<Option<StoredMessage>, StorageError> {
+        loop {
+            if let Some(found) = self.batch.pop() {
+                return Ok(Some(found));
+            }
+            let Some(index) = self.slab else {
+                return Ok(None);
+            };
+
+            if self.left == 0 {
+                if index <= self.head {
+                    self.slab = None;
+                    return Ok(None);
+                }
+                let index = index - 1;
+                self.left = self.storage.io.get_slab(index)?.record_count();
+                self.slab = Some(index);
+                continue;
+            }
+
+            self.read_batch(index)?;
+        }
+    }
+
+    /// Read the messages among the last `REV_BATCH` records of the
+    /// `left` still to go in slab `index`.
+    fn read_batch(&mut self, index: usize) -> Result<(), StorageError> {
+        let start = self.left.saturating_sub(REV_BATCH as u32);
+        let mut before = self.storage.count_before(index)?;
+        let slab = self.storage.io.get_slab(index)?;
+        let mut cursor = slab.get_head();
+        for position in 0..self.left {
+            let (record, next) = slab
+                .read(cursor.clone(), self.buffer.as_mut())?
+                .ok_or(StorageError::CorruptDB)?;
+            if position >= start
+                && is_message(&record, before)
+                && !is_deleted(self.deleted, &record)
+            {
+                self.batch
+                    .push(StoredMessage::new(&record, cursor))
+                    .or(Err(StorageError::Unreachable))?;
+            }
+            before = before.max(Some(record.message_count()));
+            cursor = next;
+        }
+
+        self.left = start;
+        Ok(())
+    }
+}
+
+impl<'a, I: IO> Iterator for RevIter<'a, I> {
+    type Item = Result<StoredMessage, StorageError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.step() {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                self.slab = None;
+                self.batch.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<I> Storage<I>
+where
+    I: IO,
+{
+    /// Message records whose `message_count` is in
+    /// `from_index..to_index`, oldest first. Records evicted from the
+    /// start of the range are skipped, as are the envelopes that
+    /// change the channel rather than add a message. Envelopes with a
+    /// tombstone are only left out once compacted, or with
+    /// `RangeIter::skip_deleted`.
+    pub fn iter_range(
+        &self,
+        from_index: u64,
+        to_index: u64,
+    ) -> Result<RangeIter<'_, I>, StorageError> {
+        let (cursor, before) = match self.find_slab(|slab| slab.max_message() >= from_index)? {
+            Some(index) if from_index < to_index => {
+                (Some(self.io.get_slab(index)?.get_head()), self.count_before(index)?)
+            }
+            _ => (None, None),
+        };
+
+        Ok(RangeIter {
+            storage: self,
+            deleted: &[],
+            cursor,
+            before,
+            from_index,
+            to_index,
+            buffer: I::Buffer::default(),
+        })
+    }
+
+    /// Every message record still stored, newest first, so the last
+    /// few can be shown without reading the whole log. Deleted
+    /// envelopes are left out as in `iter_range`.
+    pub fn iter_rev(&self) -> Result<RevIter<'_, I>, StorageError> {
+        let head = self.io.get_head()?;
+        let end = head
+            .checked_add(self.io.slab_count()?)
+            .ok_or(StorageError::Unreachable)?;
+
+        Ok(RevIter {
+            storage: self,
+            deleted: &[],
+            head,
+            slab: Some(end),
+            left: 0,
+            batch: Vec::new(),
+            buffer: I::Buffer::default(),
+        })
+    }
+
+    /// The largest `message_count` stored ahead of slab `index`, `None`
+    /// for the oldest slab.
+    pub(crate) fn count_before(&self, index: usize) -> Result<Option<u64>, StorageError> {
+        if index <= self.io.get_head()? {
+            return Ok(None);
+        }
+        Ok(Some(self.io.get_slab(index - 1)?.max_message()))
+    }
+}
@@ -0,0 +1,188 @@
+use super::*;
+
+use ascon_hash::{AsconXof, ExtendableOutput, Update, XofReader};
+
+pub const CHECKSUM_SIZE: usize = 4;
+
+const MAGIC: u16 = 0xA9F4;
+const VERSION: u8 = 3;
+
+pub(crate) fn compute_checksum(parts: &[&[u8]]) -> [u8; CHECKSUM_SIZE] {
+    let mut xof = AsconXof::default();
+    for part in parts {
+        xof.update(part);
+    }
+    let mut reader = xof.finalize_xof();
+    let mut check_sum = [0u8; CHECKSUM_SIZE];
+    reader.read(&mut check_sum);
+    check_sum
+}
+
+/// Roots are logged one after another in fixed size slots within
+/// the two root pages.
+pub const ROOT_SLOT_SIZE: usize = 64;
+
+/// Describes where everything else lives, written once when the
+/// media is formatted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbInfo {
+    magic: u16,
+    version: u8,
+    pub slab_size: u32,
+    pub root1_offset: u64,
+    pub root2_offset: u64,
+    pub data_offset: u64,
+    pub slab_capacity: u64,
+    /// The media's `write_size`, frames start on a multiple of it.
+    pub write_size: u32,
+    check_sum: [u8; CHECKSUM_SIZE],
+}
+
+impl DbInfo {
+    /// Layout.
+    /// [DbInfo, Padded to whole page]
+    /// [Root 1, Page of root slots]
+    /// [Root 2, Page of root slots]
+    /// [Records]
+    ///
+    /// Pages are one slab in size.
+    pub fn new(
+        slab_size: usize,
+        slab_capacity: usize,
+        write_size: usize,
+    ) -> Result<Self, StorageError> {
+        let page = slab_size as u64;
+        let mut info = Self {
+            magic: MAGIC,
+            version: VERSION,
+            slab_size: u32::try_from(slab_size).map_err(|_| StorageError::OutOfBounds)?,
+            root1_offset: page,
+            root2_offset: 2 * page,
+            data_offset: 3 * page,
+            slab_capacity: slab_capacity as u64,
+            write_size: u32::try_from(write_size).map_err(|_| StorageError::OutOfBounds)?,
+            check_sum: [0; CHECKSUM_SIZE],
+        };
+        info.check_sum = info.compute_checksum();
+        Ok(info)
+    }
+
+    /// Number of pages used before the first slab.
+    pub const fn reserved_pages() -> usize {
+        3
+    }
+
+    pub fn validate(&self, slab_size: usize, write_size: usize) -> Result<(), StorageError> {
+        if self.magic != MAGIC
+            || self.version != VERSION
+            || self.compute_checksum() != self.check_sum
+        {
+            return Err(StorageError::CorruptDB);
+        }
+
+        if self.slab_size as usize != slab_size || self.write_size as usize != write_size {
+            return Err(StorageError::CorruptDB);
+        }
+
+        Ok(())
+    }
+
+    fn compute_checksum(&self) -> [u8; CHECKSUM_SIZE] {
+        compute_checksum(&[
+            &self.magic.to_be_bytes(),
+            &[self.version],
+            &self.slab_size.to_be_bytes(),
+            &self.root1_offset.to_be_bytes(),
+            &self.root2_offset.to_be_bytes(),
+            &self.data_offset.to_be_bytes(),
+            &self.slab_capacity.to_be_bytes(),
+            &self.write_size.to_be_bytes(),
+        ])
+    }
+}
+
+/// What a slab holds, the `max_sequence` and `message_count` are
+/// those of its last record.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlabHeader {
+    pub count: u32,
+    pub max_sequence: u64,
+    pub max_message: u64,
+}
+
+/// The committed slabs are the logical indices `data_start..data_end`.
+///
+/// The last slab is still being appended to so its header lives here
+/// rather than on the media, a slab's header is only written once the
+/// next slab is started.
+///
+/// Appends to the last slab commit without a new root, so `tail` may
+/// be behind what the slab holds, see `Slab::recover`. A rewind sets
+/// `tail_closed` to stop the slab taking any more.
+///
+/// `checkpoint` is where the last complete checkpoint starts, so
+/// opening a channel does not have to search the log for it.
+///
+/// Each root goes in the next free slot of the current root page.
+/// When that fills the other page is erased and used, so the newest
+/// root survives a torn write of the next one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DbRoot {
+    pub generation: u64,
+    pub data_start: u64,
+    pub data_end: u64,
+    pub tail: SlabHeader,
+    pub tail_closed: bool,
+    pub checkpoint: Option<Cursor>,
+    check_sum: [u8; CHECKSUM_SIZE],
+}
+
+impl DbRoot {
+    pub fn new(
+        generation: u64,
+        data_start: u64,
+        data_end: u64,
+        tail: SlabHeader,
+        tail_closed: bool,
+        checkpoint: Option<Cursor>,
+    ) -> Self {
+        let mut root = Self {
+            generation,
+            data_start,
+            data_end,
+            tail,
+            tail_closed,
+            checkpoint,
+            check_sum: [0; CHECKSUM_SIZE],
+        };
+        root.check_sum = root.compute_checksum();
+        root
+    }
+
+    pub fn validate(&self) -> Result<(), StorageError> {
+        if self.compute_checksum() != self.check_sum || self.data_start > self.data_end {
+            return Err(StorageError::CorruptDB);
+        }
+        Ok(())
+    }
+
+    fn compute_checksum(&self) -> [u8; CHECKSUM_SIZE] {
+        let (marker, slab, offset, read_count) = match &self.checkpoint {
+            Some(cursor) => (1u8, cursor.slab as u64, cursor.offset as u64, cursor.read_count),
+            None => (0, 0, 0, 0),
+        };
+        compute_checksum(&[
+            &self.generation.to_be_bytes(),
+            &self.data_start.to_be_bytes(),
+            &self.data_end.to_be_bytes(),
+            &self.tail.count.to_be_bytes(),
+            &self.tail.max_sequence.to_be_bytes(),
+            &self.tail.max_message.to_be_bytes(),
+            &[u8::from(self.tail_closed)],
+            &[marker],
+            &slab.to_be_bytes(),
+            &offset.to_be_bytes(),
+            &read_count.to_be_bytes(),
+        ])
+    }
+}
@@ -1,106 +1,71 @@
 use super::*;
-pub struct MemIO<'a, const SLAB_SIZE: usize>
-where
-    Self: 'a,
-{
-    slab_count: usize,
-    max_index: usize,
-    start_offset: usize,
+
+pub struct MemMedia<'a> {
     data: &'a mut [u8],
+    write_size: usize,
 }
 
-impl<'a, const SLAB_SIZE: usize> MemIO<'a, SLAB_SIZE> {
-    pub fn new(data: &'a mut [u8]) -> Result<Self, StorageError> {
-        let max_index = data
-            .len()
-            .checked_div(SLAB_SIZE)
-            .ok_or(StorageError::Unreachable)?;
-
-        let result = Self {
-            slab_count: 0,
-            max_index,
-            start_offset: 0,
-            data,
-        };
-        Ok(result)
+impl<'a> MemMedia<'a> {
+    pub fn new(data: &'a mut [u8]) -> Self {
+        Self::with_write_size(data, 1)
     }
-}
 
-impl<'a, const SLAB_SIZE: usize> IO for MemIO<'a, SLAB_SIZE> {
-    fn truncate(&mut self) -> Result<(), StorageError> {
-        Err(StorageError::Unimplemented)
+    /// Lay frames out as media writing `write_size` bytes at a time
+    /// would, for looking at images taken from such media.
+    pub fn with_write_size(data: &'a mut [u8], write_size: usize) -> Self {
+        Self { data, write_size }
     }
+}
 
-    fn slab_size(&self) -> usize {
-        SLAB_SIZE
+impl<'a> Media for MemMedia<'a> {
+    fn size(&self) -> usize {
+        self.data.len()
     }
 
-    fn free_slabs(&self) -> Result<usize, StorageError> {
-        Ok((self.data.len() - (self.slab_count * SLAB_SIZE)) / SLAB_SIZE)
+    fn read(&self, offset: usize, target: &mut [u8]) -> Result<(), StorageError> {
+        let end = offset
+            .checked_add(target.len())
+            .ok_or(StorageError::OutOfBounds)?;
+        let source = self.data.get(offset..end).ok_or(StorageError::OutOfBounds)?;
+        target.copy_from_slice(source);
+        Ok(())
     }
 
-    fn slab_count(&self) -> Result<usize, StorageError> {
-        Ok(self.slab_count)
+    fn write_size(&self) -> usize {
+        self.write_size
     }
 
-    fn new_writer<'b>(&'b mut self) -> Result<SlabWriter<'b, Self>, StorageError> {
-        // BUG: used checked math
-        let start = self.start_offset + ((self.slab_count) * SLAB_SIZE);
-        let end = start + SLAB_SIZE;
-
-        let writer = SlabWriter::new(self, start, end);
-
-        Ok(writer)
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), StorageError> {
+        let end = offset
+            .checked_add(data.len())
+            .ok_or(StorageError::OutOfBounds)?;
+        let target = self
+            .data
+            .get_mut(offset..end)
+            .ok_or(StorageError::OutOfBounds)?;
+        target.copy_from_slice(data);
+        Ok(())
     }
 
-    fn get_slab<'b>(&'b self, index: usize) -> Result<Slab<'b>, StorageError> {
-        if index >= self.max_index {
-            return Err(StorageError::OutOfBounds);
-        }
-
-        // BUG: Used checked math
-        let slab_start = (self.start_offset + (index * SLAB_SIZE)) % self.data.len();
-        let slab_slice: &'b [u8] = &self.data[slab_start..(slab_start + SLAB_SIZE)];
-
-        let records: Slab<'b> = Slab::new(slab_slice, index)?;
-        Ok(records)
+    fn sync(&mut self) -> Result<(), StorageError> {
+        Ok(())
     }
+}
 
-    fn write_record(&mut self, offset: usize, end: usize, record: &Record) -> Result<usize, StorageError> {
-        let len_offset = offset;
-        // BUG: what if usize is u64
-        let offset = len_offset
-            .checked_add(size_of::<u32>())
-            .ok_or(StorageError::OutOfBounds)?;
-
-        let target = &mut self.data[offset..end];
-        let wrote = to_slice(record, target)?;
-        let wrote_len = wrote.len();
-        write_u32(wrote_len as u32, self.data, len_offset)?;
+pub type MemIO<'a, const SLAB_SIZE: usize> = SlabIO<MemMedia<'a>, SLAB_SIZE>;
 
-        let end = offset
-            .checked_add(wrote_len)
-            .ok_or(StorageError::OutOfBounds)?;
-
-        Ok(end)
-    }
+/// Bytes a `MemIO` needs for `slabs` slabs on top of the pages the
+/// layout reserves, for sizing static buffers.
+pub const fn buffer_size<const SLAB_SIZE: usize>(slabs: usize) -> usize {
+    (DbInfo::reserved_pages() + slabs) * SLAB_SIZE
+}
 
-    fn commit(
-        &mut self,
-        record_count: u32,
-        max_sequence: u64,
-        offset: usize,
-    ) -> Result<(), StorageError> {
-        let offset = write_u32(record_count, self.data, offset)?;
-        write_u64(max_sequence, self.data, offset)?;
-        self.slab_count = self
-            .slab_count
-            .checked_add(1)
-            .ok_or(StorageError::Unreachable)?;
-        Ok(())
+impl<'a, const SLAB_SIZE: usize> SlabIO<MemMedia<'a>, SLAB_SIZE> {
+    pub fn new(data: &'a mut [u8]) -> Result<Self, StorageError> {
+        SlabIO::from_media(MemMedia::new(data))
     }
 
-    fn get_head(&self) -> Result<usize, StorageError> {
-        Ok(self.start_offset)
+    pub fn with_write_size(data: &'a mut [u8], write_size: usize) -> Result<Self, StorageError> {
+        SlabIO::from_media(MemMedia::with_write_size(data, write_size))
     }
 }
@@ -0,0 +1,272 @@
+use super::*;
+
+/// Most snapshot bytes held by a single record.
+pub const MEMBERSHIP_CHUNK: usize = 256;
+
+/// Room for a chunk's record data, the bytes plus their framing.
+const CHUNK_BUFFER: usize = MEMBERSHIP_CHUNK + 16;
+
+/// One record of a membership snapshot. The snapshot is split in to
+/// `parts` chunks written back to back, a run missing any is ignored.
+#[derive(Serialize, Deserialize)]
+struct Chunk<'a> {
+    part: u16,
+    parts: u16,
+    bytes: &'a [u8],
+}
+
+/// The records of the latest complete membership snapshot.
+#[derive(Debug, Clone)]
+pub(crate) struct MembershipRun {
+    start: Cursor,
+    last_slab: usize,
+    parts: u16,
+    len: usize,
+}
+
+impl MembershipRun {
+    /// Whether the run's first record is the one at `cursor`.
+    pub(crate) fn starts_at(&self, cursor: &Cursor) -> bool {
+        self.start.slab == cursor.slab && self.start.offset == cursor.offset
+    }
+
+    pub(crate) fn parts(&self) -> u16 {
+        self.parts
+    }
+}
+
+impl<I> Storage<I>
+where
+    I: IO,
+{
+    /// Replace the membership snapshot with `data`.
+    ///
+    /// Unlike the rest of the log the snapshot is never evicted, each
+    /// time the oldest slabs are about to reach it it is written again
+    /// at the tail.
+    pub fn write_membership(&mut self, sender: NodeId, data: &[u8]) -> Result<(), StorageError> {
+        let parts = data.len().div_ceil(MEMBERSHIP_CHUNK).max(1);
+        let parts = u16::try_from(parts).or(Err(StorageError::RecordTooLarge(data.len())))?;
+
+        self.writing_membership = true;
+        let result = self.write_chunks(sender, parts, data);
+        self.writing_membership = false;
+        // Found again by the next scan.
+        self.membership = None;
+
+        result
+    }
+
+    /// Copy the latest membership snapshot in to `target`, `None` if
+    /// none has been written.
+    pub fn read_membership<'a>(
+        &self,
+        target: &'a mut [u8],
+    ) -> Result<Option<&'a [u8]>, StorageError> {
+        let run = match &self.membership {
+            Some(run) => run.clone(),
+            None => self.find_membership()?,
+        };
+        let Some(run) = run else {
+            return Ok(None);
+        };
+
+        let out = target
+            .get_mut(..run.len)
+            .ok_or(StorageError::RecordTooLarge(run.len))?;
+
+        let mut buffer = I::Buffer::default();
+        let mut offset = 0;
+        let mut cursor = run.start;
+        for _ in 0..run.parts {
+            let (record, next) = self
+                .read_record(cursor, buffer.as_mut())?
+                .ok_or(StorageError::CorruptDB)?;
+            let chunk: Chunk<'_> = from_bytes(record.data)?;
+            let end = offset + chunk.bytes.len();
+            out.get_mut(offset..end)
+                .ok_or(StorageError::CorruptDB)?
+                .copy_from_slice(chunk.bytes);
+            offset = end;
+            cursor = next;
+        }
+
+        Ok(Some(out))
+    }
+
+    /// Sender and length of the latest membership snapshot.
+    pub(crate) fn membership_info(&self) -> Result<Option<(NodeId, usize)>, StorageError> {
+        let run = match &self.membership {
+            Some(run) => run.clone(),
+            None => self.find_membership()?,
+        };
+        let Some(run) = run else {
+            return Ok(None);
+        };
+
+        let mut buffer = I::Buffer::default();
+        let (record, _) = self
+            .read_record(run.start, buffer.as_mut())?
+            .ok_or(StorageError::CorruptDB)?;
+        Ok(Some((record.sender, run.len)))
+    }
+
+    /// Write the snapshot again at the tail if the slabs left before
+    /// eviction reaches it are about to run out.
+    pub(crate) fn keep_membership(&mut self) -> Result<(), StorageError> {
+        if self.writing_membership {
+            return Ok(());
+        }
+
+        let run = match &self.membership {
+            Some(run) => run.clone(),
+            None => {
+                let run = self.find_membership()?;
+                self.membership = Some(run.clone());
+                run
+            }
+        };
+        let Some(run) = run else {
+            return Ok(());
+        };
+
+        let head = self.io.get_head()?;
+        let first_slab = run.start.slab;
+        let span = run.last_slab.saturating_sub(first_slab).saturating_add(1);
+        // New slabs that can be started before the first slab of the
+        // snapshot is evicted. Writing the copy takes at most `span`
+        // of them so it must start while one more is left.
+        let room = self
+            .io
+            .free_slabs()?
+            .saturating_add(first_slab.saturating_sub(head));
+        if room > span.saturating_add(1) {
+            return Ok(());
+        }
+
+        self.writing_membership = true;
+        let result = self.copy_membership(&run);
+        self.writing_membership = false;
+        self.membership = None;
+
+        match result {
+            // No room for the copy, the snapshot goes with its slab if
+            // that is evicted.
+            Err(StorageError::DbFull) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn copy_membership(&mut self, run: &MembershipRun) -> Result<(), StorageError> {
+        let mut buffer = I::Buffer::default();
+        let mut cursor = run.start.clone();
+
+        for _ in 0..run.parts {
+            let (record, next) = self
+                .read_record(cursor, buffer.as_mut())?
+                .ok_or(StorageError::CorruptDB)?;
+
+            let (max_sequence, max_message) = self.tail_counters()?;
+            self.append_kind(
+                RecordKind::Membership,
+                max_sequence,
+                max_message,
+                0,
+                record.sender,
+                record.data,
+            )?;
+            cursor = next;
+        }
+
+        Ok(())
+    }
+
+    fn write_chunks(&mut self, sender: NodeId, parts: u16, data: &[u8]) -> Result<(), StorageError> {
+        let mut buffer = [0u8; CHUNK_BUFFER];
+
+        for part in 0..parts {
+            let start = usize::from(part) * MEMBERSHIP_CHUNK;
+            let end = data.len().min(start + MEMBERSHIP_CHUNK);
+            let chunk = Chunk {
+                part,
+                parts,
+                bytes: data.get(start..end).ok_or(StorageError::Unreachable)?,
+            };
+            let bytes = to_slice(&chunk, buffer.as_mut_slice())?;
+
+            let (max_sequence, max_message) = self.tail_counters()?;
+            self.append_kind(
+                RecordKind::Membership,
+                max_sequence,
+                max_message,
+                0,
+                sender,
+                bytes,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Scan the log for the last complete membership snapshot.
+    pub(crate) fn find_membership(&self) -> Result<Option<MembershipRun>, StorageError> {
+        let Some(mut cursor) = self.get_cursor_from_sequence(0)? else {
+            return Ok(None);
+        };
+
+        let mut buffer = I::Buffer::default();
+        let mut found = None;
+        // The run being read, its next part and bytes so far.
+        let mut pending: Option<(Cursor, u16, usize)> = None;
+
+        while let Some((record, next)) = self.read_record(cursor.clone(), buffer.as_mut())? {
+            if record.kind == RecordKind::Membership {
+                let chunk: Chunk<'_> = from_bytes(record.data)?;
+                if chunk.part == 0 {
+                    pending = Some((cursor.clone(), 0, 0));
+                }
+
+                match pending.take() {
+                    Some((start, part, len)) if part == chunk.part => {
+                        let len = len + chunk.bytes.len();
+                        let part = part + 1;
+                        if part == chunk.parts {
+                            found = Some(MembershipRun {
+                                start,
+                                last_slab: next.slab,
+                                parts: chunk.parts,
+                                len,
+                            });
+                        } else {
+                            pending = Some((start, part, len));
+                        }
+                    }
+                    _ => {}
+                }
+            } else {
+                // The chunks of a snapshot are written back to back.
+                pending = None;
+            }
+            cursor = next;
+        }
+
+        Ok(found)
+    }
+
+    /// Counters of the last record so records that are not messages
+    /// keep both in order.
+    pub(crate) fn tail_counters(&self) -> Result<(u64, u64), StorageError> {
+        let count = self.io.slab_count()?;
+        let Some(last) = count.checked_sub(1) else {
+            return Ok((0, 0));
+        };
+        let index = self
+            .io
+            .get_head()?
+            .checked_add(last)
+            .ok_or(StorageError::Unreachable)?;
+        let slab = self.io.get_slab(index)?;
+
+        Ok((slab.max_sequence(), slab.max_message()))
+    }
+}
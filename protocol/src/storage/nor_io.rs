@@ -0,0 +1,188 @@
+use super::*;
+
+use core::cell::{Ref, RefCell};
+use embedded_storage::nor_flash::{NorFlash, NorFlashError};
+
+/// Largest `READ_SIZE` or `WRITE_SIZE` a `NorMedia` can work with.
+const MAX_WORD: usize = 64;
+
+/// A region of NOR flash, read on demand.
+///
+/// `SlabIO` only writes erased space, on whole words, so any flash
+/// that can program a word once between erases will do. The tail of
+/// a write is padded to a whole word with `0xFF`.
+pub struct NorMedia<F> {
+    // `ReadNorFlash::read` takes `&mut self` but `Media::read` does not.
+    flash: RefCell<F>,
+    base: usize,
+    len: usize,
+}
+
+impl<F: NorFlash> NorMedia<F> {
+    /// Use the `len` bytes of `flash` starting at `base`, which must
+    /// both be whole erase blocks.
+    pub fn new(flash: F, base: usize, len: usize) -> Result<Self, StorageError> {
+        let end = base.checked_add(len).ok_or(StorageError::OutOfBounds)?;
+
+        if !base.is_multiple_of(F::ERASE_SIZE)
+            || !len.is_multiple_of(F::ERASE_SIZE)
+            || end > flash.capacity()
+            || F::READ_SIZE > MAX_WORD
+            || F::WRITE_SIZE > MAX_WORD
+        {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        Ok(Self {
+            flash: RefCell::new(flash),
+            base,
+            len,
+        })
+    }
+
+    pub fn flash(&self) -> Ref<'_, F> {
+        self.flash.borrow()
+    }
+
+    pub fn into_flash(self) -> F {
+        self.flash.into_inner()
+    }
+
+    /// Offset on the flash of `offset..(offset + len)` in the region.
+    fn locate(&self, offset: usize, len: usize) -> Result<usize, StorageError> {
+        let end = offset.checked_add(len).ok_or(StorageError::OutOfBounds)?;
+        if end > self.len {
+            return Err(StorageError::OutOfBounds);
+        }
+        self.base
+            .checked_add(offset)
+            .ok_or(StorageError::OutOfBounds)
+    }
+}
+
+impl<F: NorFlash> Media for NorMedia<F> {
+    fn size(&self) -> usize {
+        self.len
+    }
+
+    fn read(&self, offset: usize, target: &mut [u8]) -> Result<(), StorageError> {
+        let at = self.locate(offset, target.len())?;
+        let mut flash = self
+            .flash
+            .try_borrow_mut()
+            .or(Err(StorageError::Unreachable))?;
+
+        // Whole words straight in to `target`, the ragged ends through
+        // a word sized buffer.
+        let mut done = 0;
+        while done < target.len() {
+            let position = at + done;
+            let left = target.len() - done;
+            let skip = position % F::READ_SIZE;
+
+            if skip == 0 && left >= F::READ_SIZE {
+                let whole = left - left % F::READ_SIZE;
+                flash
+                    .read(to_u32(position)?, &mut target[done..(done + whole)])
+                    .map_err(flash_error)?;
+                done += whole;
+                continue;
+            }
+
+            let mut word = [0u8; MAX_WORD];
+            let word = &mut word[..F::READ_SIZE];
+            flash
+                .read(to_u32(position - skip)?, word)
+                .map_err(flash_error)?;
+            let step = (F::READ_SIZE - skip).min(left);
+            target[done..(done + step)].copy_from_slice(&word[skip..(skip + step)]);
+            done += step;
+        }
+
+        Ok(())
+    }
+
+    fn write_size(&self) -> usize {
+        F::WRITE_SIZE
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), StorageError> {
+        let at = self.locate(offset, data.len())?;
+        if !at.is_multiple_of(F::WRITE_SIZE) {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let flash = self.flash.get_mut();
+        let whole = data.len() - data.len() % F::WRITE_SIZE;
+        if whole > 0 {
+            flash
+                .write(to_u32(at)?, &data[..whole])
+                .map_err(flash_error)?;
+        }
+
+        let rest = &data[whole..];
+        if !rest.is_empty() {
+            let mut word = [0xFFu8; MAX_WORD];
+            let word = &mut word[..F::WRITE_SIZE];
+            word[..rest.len()].copy_from_slice(rest);
+            flash
+                .write(to_u32(at + whole)?, word)
+                .map_err(flash_error)?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        // Flash writes are done once they return.
+        Ok(())
+    }
+
+    /// Blocks that already read as erased are left alone, sparing them
+    /// the wear.
+    fn erase(&mut self, offset: usize, len: usize) -> Result<(), StorageError> {
+        let at = self.locate(offset, len)?;
+
+        let mut chunk = [0u8; MAX_WORD];
+        let mut checked = 0;
+        while checked < len {
+            let step = chunk.len().min(len - checked);
+            self.read(offset + checked, &mut chunk[..step])?;
+            if chunk[..step].iter().any(|b| *b != 0xFF) {
+                break;
+            }
+            checked += step;
+        }
+        if checked == len {
+            return Ok(());
+        }
+
+        self.flash
+            .get_mut()
+            .erase(to_u32(at)?, to_u32(at + len)?)
+            .map_err(flash_error)
+    }
+}
+
+fn flash_error<E: NorFlashError>(err: E) -> StorageError {
+    log::error!("nor flash failed: {:?}", err.kind());
+    StorageError::IoError
+}
+
+fn to_u32(offset: usize) -> Result<u32, StorageError> {
+    u32::try_from(offset).map_err(|_| StorageError::OutOfBounds)
+}
+
+pub type NorIO<F, const SLAB_SIZE: usize> = SlabIO<NorMedia<F>, SLAB_SIZE>;
+
+impl<F: NorFlash, const SLAB_SIZE: usize> SlabIO<NorMedia<F>, SLAB_SIZE> {
+    /// Open the store in the `len` bytes of `flash` starting at
+    /// `base`. Slabs must be whole erase blocks so erasing one never
+    /// touches its neighbours.
+    pub fn open(flash: F, base: usize, len: usize) -> Result<Self, StorageError> {
+        if !SLAB_SIZE.is_multiple_of(F::ERASE_SIZE) {
+            return Err(StorageError::OutOfBounds);
+        }
+        SlabIO::from_media(NorMedia::new(flash, base, len)?)
+    }
+}
@@ -0,0 +1,402 @@
+use super::*;
+
+use core::cell::RefCell;
+use critical_section::Mutex;
+
+/// Pages ahead of the ranges, holding the `PoolTable`.
+const TABLE_PAGES: usize = 2;
+
+/// A run of pages of a `StoragePool` given to one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolRange {
+    /// Chosen by whoever opened the range, to find it again after a
+    /// restart.
+    pub key: u32,
+    /// First page, counted from the end of the table pages.
+    pub start: u32,
+    pub len: u32,
+}
+
+impl PoolRange {
+    fn end(&self) -> u32 {
+        self.start.saturating_add(self.len)
+    }
+
+    fn overlaps(&self, start: u32, end: u32) -> bool {
+        self.start < end && start < self.end()
+    }
+}
+
+/// Which ranges of a pool are taken.
+///
+/// Written to the table pages in turn, newest generation wins:
+/// [length: u32][check_sum: [u8; 4]][PoolTable]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PoolTable<const MAX_RANGES: usize> {
+    generation: u64,
+    ranges: Vec<PoolRange, MAX_RANGES>,
+    /// Where a range is being moved to, see `StoragePool::move_to`.
+    /// Forgotten on open if the move never finished.
+    moving: Option<PoolRange>,
+}
+
+struct PoolState<M, const MAX_RANGES: usize> {
+    media: M,
+    table: PoolTable<MAX_RANGES>,
+    /// Table page the next table goes in.
+    page: usize,
+}
+
+/// Channels carved out of one `Media`.
+///
+/// Each channel opens a range of whole pages under a key of its own
+/// choosing and keeps a `SlabIO` in it, so the pool only tracks which
+/// ranges are taken. The table of ranges is logged to two pages ahead
+/// of them like `SlabIO` roots, so the ranges and everything in them
+/// are found again after a restart. A range holds `slabs` slabs, its
+/// quota, and `DbInfo::reserved_pages` more for the `SlabIO`.
+pub struct StoragePool<M, const SLAB_SIZE: usize, const MAX_RANGES: usize> {
+    state: Mutex<RefCell<PoolState<M, MAX_RANGES>>>,
+    capacity: usize,
+    write_size: usize,
+}
+
+impl<M: Media, const SLAB_SIZE: usize, const MAX_RANGES: usize> StoragePool<M, SLAB_SIZE, MAX_RANGES> {
+    /// Recover the ranges already on `media`, or start an empty pool
+    /// if it is blank.
+    pub fn new(media: M) -> Result<Self, StorageError> {
+        let capacity = (media.size() / SLAB_SIZE)
+            .checked_sub(TABLE_PAGES)
+            .filter(|pages| *pages > 0)
+            .ok_or(StorageError::DbFull)?;
+        if u32::try_from(capacity).is_err() {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let mut best: Option<(PoolTable<MAX_RANGES>, usize)> = None;
+        let mut blank = true;
+        let mut page = [0u8; SLAB_SIZE];
+        for index in 0..TABLE_PAGES {
+            media.read(index * SLAB_SIZE, &mut page)?;
+            blank &= page.iter().all(|b| *b == 0) || page.iter().all(|b| *b == 0xFF);
+
+            let Some(table) = read_table::<MAX_RANGES>(&page) else {
+                continue;
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(newest, _)| newest.generation < table.generation)
+            {
+                best = Some((table, index));
+            }
+        }
+
+        let (mut table, page) = match best {
+            Some((table, index)) => (table, (index + 1) % TABLE_PAGES),
+            None if blank => (
+                PoolTable {
+                    generation: 0,
+                    ranges: Vec::new(),
+                    moving: None,
+                },
+                0,
+            ),
+            None => return Err(StorageError::CorruptDB),
+        };
+        if table
+            .ranges
+            .iter()
+            .chain(table.moving.iter())
+            .any(|range| range.end() as usize > capacity)
+        {
+            return Err(StorageError::CorruptDB);
+        }
+        // Whatever was written to it is dropped with it.
+        table.moving = None;
+
+        Ok(Self {
+            write_size: media.write_size(),
+            state: Mutex::new(RefCell::new(PoolState { media, table, page })),
+            capacity,
+        })
+    }
+
+    /// Total number of pages for ranges.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of pages in no range.
+    pub fn free_slabs(&self) -> usize {
+        self.with_state(|state| {
+            let taken: usize = state
+                .table
+                .ranges
+                .iter()
+                .chain(state.table.moving.iter())
+                .map(|range| range.len as usize)
+                .sum();
+            self.capacity.saturating_sub(taken)
+        })
+    }
+
+    /// The ranges taken, to find every channel after a restart.
+    pub fn ranges(&self) -> Vec<PoolRange, MAX_RANGES> {
+        self.with_state(|state| state.table.ranges.clone())
+    }
+
+    /// Storage in the range opened under `key`, taking a new range
+    /// for `slabs` slabs if there is none.
+    pub fn open(&self, key: u32, slabs: usize) -> Result<PooledIO<'_, M, SLAB_SIZE, MAX_RANGES>, StorageError> {
+        let range = self.with_state(|state| {
+            if let Some(range) = state.table.ranges.iter().find(|range| range.key == key) {
+                return Ok(*range);
+            }
+
+            let range = self.allocate(state, key, slabs)?;
+            state.table.ranges.push(range).or(Err(StorageError::DbFull))?;
+            if let Err(e) = self.write_table(state) {
+                state.table.ranges.pop();
+                return Err(e);
+            }
+            Ok(range)
+        })?;
+
+        SlabIO::from_media(PoolMedia { pool: self, range })
+    }
+
+    /// Storage in a new range for `slabs` slabs to move the channel
+    /// at `key` in to, say with `Client::compact`. Until `finish_move`
+    /// the old range is the one opened under `key`, even after a
+    /// restart. One move at a time, starting another for the same key
+    /// gives up on the last.
+    pub fn move_to(&self, key: u32, slabs: usize) -> Result<PooledIO<'_, M, SLAB_SIZE, MAX_RANGES>, StorageError> {
+        let range = self.with_state(|state| {
+            if !state.table.ranges.iter().any(|range| range.key == key) {
+                return Err(StorageError::OutOfBounds);
+            }
+            match state.table.moving {
+                Some(moving) if moving.key != key => return Err(StorageError::OutOfBounds),
+                _ => state.table.moving = None,
+            }
+
+            let range = self.allocate(state, key, slabs)?;
+            state.table.moving = Some(range);
+            if let Err(e) = self.write_table(state) {
+                state.table.moving = None;
+                return Err(e);
+            }
+            Ok(range)
+        })?;
+
+        SlabIO::from_media(PoolMedia { pool: self, range })
+    }
+
+    /// Make the range `move_to` gave out the one opened under the key
+    /// of `old`, freeing the range `old` was using.
+    pub fn finish_move(&self, old: PooledIO<'_, M, SLAB_SIZE, MAX_RANGES>) -> Result<(), StorageError> {
+        let old = old.range();
+        self.with_state(|state| {
+            let moving = state
+                .table
+                .moving
+                .filter(|range| range.key == old.key)
+                .ok_or(StorageError::OutOfBounds)?;
+            let slot = state
+                .table
+                .ranges
+                .iter_mut()
+                .find(|range| **range == old)
+                .ok_or(StorageError::OutOfBounds)?;
+            *slot = moving;
+            state.table.moving = None;
+
+            if let Err(e) = self.write_table(state) {
+                state.table.moving = Some(moving);
+                if let Some(slot) = state.table.ranges.iter_mut().find(|range| **range == moving) {
+                    *slot = old;
+                }
+                return Err(e);
+            }
+            Ok(())
+        })
+    }
+
+    /// Hand the range of `io` back to the pool, dropping what it holds.
+    pub fn release(&self, io: PooledIO<'_, M, SLAB_SIZE, MAX_RANGES>) -> Result<(), StorageError> {
+        let range = io.range();
+        self.with_state(|state| {
+            let position = state
+                .table
+                .ranges
+                .iter()
+                .position(|taken| *taken == range)
+                .ok_or(StorageError::OutOfBounds)?;
+            state.table.ranges.remove(position);
+
+            if let Err(e) = self.write_table(state) {
+                let _ = state.table.ranges.insert(position, range);
+                return Err(e);
+            }
+            Ok(())
+        })
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut PoolState<M, MAX_RANGES>) -> R) -> R {
+        critical_section::with(|cs| f(&mut self.state.borrow_ref_mut(cs)))
+    }
+
+    /// The first free run of pages for `slabs` slabs, with its first
+    /// page erased so the `SlabIO` in it starts over.
+    fn allocate(
+        &self,
+        state: &mut PoolState<M, MAX_RANGES>,
+        key: u32,
+        slabs: usize,
+    ) -> Result<PoolRange, StorageError> {
+        if slabs == 0 {
+            return Err(StorageError::OutOfBounds);
+        }
+        let len = slabs
+            .checked_add(DbInfo::reserved_pages())
+            .and_then(|len| u32::try_from(len).ok())
+            .ok_or(StorageError::DbFull)?;
+
+        let mut start = 0u32;
+        loop {
+            let end = start.checked_add(len).ok_or(StorageError::DbFull)?;
+            if end as usize > self.capacity {
+                return Err(StorageError::DbFull);
+            }
+            let taken = state
+                .table
+                .ranges
+                .iter()
+                .chain(state.table.moving.iter())
+                .find(|range| range.overlaps(start, end));
+            match taken {
+                Some(range) => start = range.end(),
+                None => break,
+            }
+        }
+
+        let range = PoolRange { key, start, len };
+        state.media.erase(page_offset::<SLAB_SIZE>(&range)?, SLAB_SIZE)?;
+        state.media.sync()?;
+        Ok(range)
+    }
+
+    /// Log the table to the next table page.
+    fn write_table(&self, state: &mut PoolState<M, MAX_RANGES>) -> Result<(), StorageError> {
+        let mut table = state.table.clone();
+        table.generation = table
+            .generation
+            .checked_add(1)
+            .ok_or(StorageError::Unreachable)?;
+
+        let mut page = [0u8; SLAB_SIZE];
+        let body = page.get_mut(FRAME_SIZE..).ok_or(StorageError::OutOfBounds)?;
+        let wrote = to_slice(&table, body).or(Err(StorageError::DbFull))?;
+        let check_sum = compute_checksum(&[wrote]);
+        let length = u32::try_from(wrote.len()).or(Err(StorageError::Unreachable))?;
+        let total = FRAME_SIZE + wrote.len();
+        let offset = write_u32(length, &mut page, 0)?;
+        write_arr(check_sum, &mut page, offset)?;
+
+        let at = state.page * SLAB_SIZE;
+        state.media.erase(at, SLAB_SIZE)?;
+        state.media.write(at, &page[..total])?;
+        state.media.sync()?;
+
+        state.table.generation = table.generation;
+        state.page = (state.page + 1) % TABLE_PAGES;
+        Ok(())
+    }
+}
+
+fn read_table<const MAX_RANGES: usize>(page: &[u8]) -> Option<PoolTable<MAX_RANGES>> {
+    let (length, offset) = read_u32(page, 0).ok()?;
+    let check_sum = page.get(offset..FRAME_SIZE)?;
+    let body = page.get(FRAME_SIZE..FRAME_SIZE.checked_add(length as usize)?)?;
+    if compute_checksum(&[body]) != check_sum {
+        return None;
+    }
+    from_bytes(body).ok()
+}
+
+/// Offset on the pool's media of the first page of `range`.
+fn page_offset<const SLAB_SIZE: usize>(range: &PoolRange) -> Result<usize, StorageError> {
+    (range.start as usize)
+        .checked_add(TABLE_PAGES)
+        .and_then(|page| page.checked_mul(SLAB_SIZE))
+        .ok_or(StorageError::OutOfBounds)
+}
+
+/// One range of a `StoragePool` seen as media of its own.
+pub struct PoolMedia<'a, M, const SLAB_SIZE: usize, const MAX_RANGES: usize> {
+    pool: &'a StoragePool<M, SLAB_SIZE, MAX_RANGES>,
+    range: PoolRange,
+}
+
+impl<'a, M: Media, const SLAB_SIZE: usize, const MAX_RANGES: usize> PoolMedia<'a, M, SLAB_SIZE, MAX_RANGES> {
+    /// Offset on the pool's media of `offset..(offset + len)` in the range.
+    fn locate(&self, offset: usize, len: usize) -> Result<usize, StorageError> {
+        let end = offset.checked_add(len).ok_or(StorageError::OutOfBounds)?;
+        if end > self.size() {
+            return Err(StorageError::OutOfBounds);
+        }
+        page_offset::<SLAB_SIZE>(&self.range)?
+            .checked_add(offset)
+            .ok_or(StorageError::OutOfBounds)
+    }
+}
+
+impl<'a, M: Media, const SLAB_SIZE: usize, const MAX_RANGES: usize> Media
+    for PoolMedia<'a, M, SLAB_SIZE, MAX_RANGES>
+{
+    fn size(&self) -> usize {
+        self.range.len as usize * SLAB_SIZE
+    }
+
+    fn read(&self, offset: usize, target: &mut [u8]) -> Result<(), StorageError> {
+        let at = self.locate(offset, target.len())?;
+        self.pool.with_state(|state| state.media.read(at, target))
+    }
+
+    fn write_size(&self) -> usize {
+        self.pool.write_size
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), StorageError> {
+        let at = self.locate(offset, data.len())?;
+        self.pool.with_state(|state| state.media.write(at, data))
+    }
+
+    fn sync(&mut self) -> Result<(), StorageError> {
+        self.pool.with_state(|state| state.media.sync())
+    }
+
+    fn erase(&mut self, offset: usize, len: usize) -> Result<(), StorageError> {
+        let at = self.locate(offset, len)?;
+        self.pool.with_state(|state| state.media.erase(at, len))
+    }
+}
+
+/// A channel's range of a `StoragePool`.
+pub type PooledIO<'a, M, const SLAB_SIZE: usize, const MAX_RANGES: usize> =
+    SlabIO<PoolMedia<'a, M, SLAB_SIZE, MAX_RANGES>, SLAB_SIZE>;
+
+impl<'a, M: Media, const SLAB_SIZE: usize, const MAX_RANGES: usize> PooledIO<'a, M, SLAB_SIZE, MAX_RANGES> {
+    pub fn pool(&self) -> &'a StoragePool<M, SLAB_SIZE, MAX_RANGES> {
+        self.media().pool
+    }
+
+    pub fn range(&self) -> PoolRange {
+        self.media().range
+    }
+
+    /// Slabs the range holds.
+    pub fn quota(&self) -> usize {
+        (self.range().len as usize).saturating_sub(DbInfo::reserved_pages())
+    }
+}
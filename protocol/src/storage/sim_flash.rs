@@ -0,0 +1,125 @@
+extern crate std;
+use std::vec;
+use std::vec::Vec;
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError, NorFlashErrorKind,
+    ReadNorFlash,
+};
+
+/// In memory NOR flash for exercising `NorMedia` on a host.
+///
+/// Like the real thing it starts erased and erases whole blocks to
+/// `0xFF`. Flash without `MultiwriteNorFlash` may not program a word
+/// twice between erases, so writing one again is refused, as is a
+/// write that would need a bit set, so callers that forget to erase
+/// are caught.
+pub struct SimFlash<const ERASE_SIZE: usize> {
+    data: Vec<u8>,
+    /// Words written since their block was last erased.
+    programmed: Vec<bool>,
+    erase_counts: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimFlashError {
+    NotAligned,
+    OutOfBounds,
+    /// A write needed a bit that was already cleared to be set, or
+    /// went to a word already written since the last erase.
+    NotErased,
+}
+
+impl NorFlashError for SimFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            SimFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            SimFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            SimFlashError::NotErased => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for SimFlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => SimFlashError::NotAligned,
+            _ => SimFlashError::OutOfBounds,
+        }
+    }
+}
+
+impl<const ERASE_SIZE: usize> SimFlash<ERASE_SIZE> {
+    /// An erased flash of `blocks` erase blocks.
+    pub fn new(blocks: usize) -> Self {
+        Self {
+            data: vec![0xFF; blocks * ERASE_SIZE],
+            programmed: vec![false; blocks * ERASE_SIZE / Self::WRITE_SIZE],
+            erase_counts: vec![0; blocks],
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Bytes of the flash as is, for simulating damage.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// How many times each erase block has been erased.
+    pub fn erase_counts(&self) -> &[u32] {
+        &self.erase_counts
+    }
+}
+
+impl<const ERASE_SIZE: usize> ErrorType for SimFlash<ERASE_SIZE> {
+    type Error = SimFlashError;
+}
+
+impl<const ERASE_SIZE: usize> ReadNorFlash for SimFlash<ERASE_SIZE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.data[start..(start + bytes.len())]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl<const ERASE_SIZE: usize> NorFlash for SimFlash<ERASE_SIZE> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let (from, to) = (from as usize, to as usize);
+        self.data[from..to].fill(0xFF);
+        self.programmed[(from / Self::WRITE_SIZE)..(to / Self::WRITE_SIZE)].fill(false);
+        for count in &mut self.erase_counts[(from / ERASE_SIZE)..(to / ERASE_SIZE)] {
+            *count += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let start = offset as usize;
+        let words = (start / Self::WRITE_SIZE)..((start + bytes.len()) / Self::WRITE_SIZE);
+        let target = &mut self.data[start..(start + bytes.len())];
+        if self.programmed[words.clone()].iter().any(|written| *written)
+            || target.iter().zip(bytes).any(|(old, new)| old & new != *new)
+        {
+            return Err(SimFlashError::NotErased);
+        }
+        target.copy_from_slice(bytes);
+        self.programmed[words].fill(true);
+        Ok(())
+    }
+}
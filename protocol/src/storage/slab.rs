@@ -1,34 +1,249 @@
 use super::*;
 
+pub(crate) const SLAB_HEADER_SIZE: usize =
+    size_of::<u32>() + size_of::<u64>() + size_of::<u64>() + size_of::<u64>();
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Bytes before the record in each frame, [length: u32][check_sum: [u8; 4]].
+pub(crate) const FRAME_SIZE: usize = LEN_SIZE + CHECKSUM_SIZE;
+
+/// Length of the frame `SlabIO` writes after the records of an
+/// append to commit them. It holds no record.
+pub(crate) const MARK_LENGTH: u32 = 1 << 30;
+
+/// Frame `record` at the start of `target` returning the bytes used.
+pub(crate) fn write_frame(record: &Record, target: &mut [u8]) -> Result<usize, StorageError> {
+    let body = target.get_mut(FRAME_SIZE..).ok_or(StorageError::SlabFull)?;
+    let wrote = to_slice(record, body)?;
+    let wrote_len = wrote.len();
+    let check_sum = compute_checksum(&[wrote]);
+
+    let mut length = u32::try_from(wrote_len)
+        .ok()
+        .filter(|length| length & (COMPRESSED_FLAG | MARK_LENGTH) == 0)
+        .ok_or(StorageError::RecordTooLarge(wrote_len))?;
+    if record.compressed {
+        length |= COMPRESSED_FLAG;
+    }
+    let offset = write_u32(length, target, 0)?;
+    write_arr(check_sum, target, offset)?;
+
+    FRAME_SIZE
+        .checked_add(wrote_len)
+        .ok_or(StorageError::Unreachable)
+}
+
+/// The commit mark for slab `index` once it holds `count` records.
+/// Its check sum ties it to both so nothing else reads as one.
+pub(crate) fn mark_frame(index: usize, count: u32) -> Result<[u8; FRAME_SIZE], StorageError> {
+    let mut frame = [0u8; FRAME_SIZE];
+    let offset = write_u32(MARK_LENGTH, &mut frame, 0)?;
+    write_arr(mark_checksum(index, count), &mut frame, offset)?;
+    Ok(frame)
+}
+
+fn mark_checksum(index: usize, count: u32) -> [u8; CHECKSUM_SIZE] {
+    compute_checksum(&[&(index as u64).to_be_bytes(), &count.to_be_bytes()])
+}
+
+pub(crate) fn encode_header(
+    header: &SlabHeader,
+    index: u64,
+) -> Result<[u8; SLAB_HEADER_SIZE], StorageError> {
+    let mut bytes = [0u8; SLAB_HEADER_SIZE];
+    let offset = write_u32(header.count, &mut bytes, 0)?;
+    let offset = write_u64(header.max_sequence, &mut bytes, offset)?;
+    let offset = write_u64(header.max_message, &mut bytes, offset)?;
+    write_u64(index, &mut bytes, offset)?;
+    Ok(bytes)
+}
+
+/// The header of slab `index` from the bytes `encode_header` wrote.
+pub(crate) fn decode_header(bytes: &[u8], index: usize) -> Result<SlabHeader, StorageError> {
+    let (count, offset) = read_u32(bytes, 0)?;
+    let (max_sequence, offset) = read_u64(bytes, offset)?;
+    let (max_message, offset) = read_u64(bytes, offset)?;
+    let (slab_index, _) = read_u64(bytes, offset)?;
+
+    // A committed slab must be the one we asked for, anything
+    // else is a stale slab from before the ring wrapped.
+    if slab_index != index as u64 {
+        return Err(StorageError::CorruptDB);
+    }
+
+    Ok(SlabHeader {
+        count,
+        max_sequence,
+        max_message,
+    })
+}
+
+/// The data of a `kind` record as `SlabWriter` stores it, compressed
+/// in to `packed` when `compression` is on and that makes it smaller.
+pub(crate) fn pack<'b>(
+    kind: RecordKind,
+    compression: bool,
+    data: &'b [u8],
+    packed: &'b mut [u8],
+) -> (&'b [u8], bool) {
+    let compressed = match kind {
+        RecordKind::Data if compression && data.len() <= COMPRESS_MAX => compress(data, packed),
+        _ => None,
+    };
+    match compressed {
+        Some(compressed) => (compressed, true),
+        None => (data, false),
+    }
+}
+
+/// Offset in to each slab of its first record.
+pub(crate) fn first_offset(write_size: usize) -> Result<usize, StorageError> {
+    align_up(SLAB_HEADER_SIZE, write_size)
+}
+
+/// Room an append leaves after its records for the mark that commits
+/// it.
+pub(crate) fn mark_reserve(write_size: usize) -> Result<usize, StorageError> {
+    align_up(FRAME_SIZE, write_size)
+}
+
+pub(crate) fn align_up(offset: usize, align: usize) -> Result<usize, StorageError> {
+    match offset % align {
+        0 => Ok(offset),
+        rem => offset
+            .checked_add(align - rem)
+            .ok_or(StorageError::OutOfBounds),
+    }
+}
+
+/// Where a `Slab` reads its frames from.
+pub trait SlabSource {
+    /// Fill `target` with the bytes `offset` in to slab `index`.
+    fn read_slab(&self, index: usize, offset: usize, target: &mut [u8]) -> Result<(), StorageError>;
+}
+
+/// Room for any record a slab of `SLAB_SIZE` bytes holds, the
+/// `IO::Buffer` of the slab backed stores.
+pub struct SlabBuffer<const SLAB_SIZE: usize>([u8; SLAB_SIZE]);
+
+impl<const SLAB_SIZE: usize> Default for SlabBuffer<SLAB_SIZE> {
+    fn default() -> Self {
+        Self([0; SLAB_SIZE])
+    }
+}
+
+impl<const SLAB_SIZE: usize> AsMut<[u8]> for SlabBuffer<SLAB_SIZE> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// Turns the body of a frame back in to the record it holds, for an
+/// `IO` that stores records other than as written, see `EncryptedIO`.
+pub(crate) trait RecordOpener {
+    fn open<'b>(&self, body: &'b mut [u8]) -> Result<Record<'b>, StorageError>;
+}
+
+/// A committed slab, its records are read from the `SlabSource` one
+/// at a time.
+///
+/// Frames start on a multiple of `align` so flash that programs
+/// whole words never programs one twice.
+#[derive(Clone)]
 pub struct Slab<'a> {
+    source: &'a dyn SlabSource,
     slab: usize,
     offset: usize,
+    size: usize,
+    align: usize,
     count: u32,
     slab_max_sequence: u64,
-    // [count: u32][slab_max_sequence: u64][length:u32][data: [u8]]
-    records: &'a [u8], // Record Data
+    slab_max_message: u64,
+    opener: Option<&'a dyn RecordOpener>,
 }
 
 impl<'a> Slab<'a> {
-    pub fn new(data: &'a [u8], index: usize) -> Result<Self, StorageError> {
-        let (count, offset) = read_u32(data, 0)?;
-        let (slab_max_sequence, offset) = read_u64(data, offset)?;
+    /// Slab `index` of `source` holding what `header` says. Its frames
+    /// start `offset` bytes in and end by `size`.
+    pub(crate) fn new(
+        source: &'a dyn SlabSource,
+        index: usize,
+        header: &SlabHeader,
+        offset: usize,
+        size: usize,
+        align: usize,
+    ) -> Result<Self, StorageError> {
+        if offset > size || align == 0 {
+            return Err(StorageError::OutOfBounds);
+        }
 
         Ok(Slab {
+            source,
             slab: index,
             offset,
-            count,
-            slab_max_sequence,
-            records: data,
+            size,
+            align,
+            count: header.count,
+            slab_max_sequence: header.max_sequence,
+            slab_max_message: header.max_message,
+            opener: None,
         })
     }
 
+    /// The same slab with its records read through `opener`.
+    pub(crate) fn with_opener(mut self, opener: &'a dyn RecordOpener) -> Self {
+        self.opener = Some(opener);
+        self
+    }
+
+    pub fn header(&self) -> SlabHeader {
+        SlabHeader {
+            count: self.count,
+            max_sequence: self.slab_max_sequence,
+            max_message: self.slab_max_message,
+        }
+    }
+
     pub fn record_count(&self) -> u32 {
         self.count
     }
 
+    /// The `max_sequence` of the last record in the slab.
+    pub fn max_sequence(&self) -> u64 {
+        self.slab_max_sequence
+    }
+
+    /// The `message_count` of the last record in the slab.
+    pub fn max_message(&self) -> u64 {
+        self.slab_max_message
+    }
+
+    /// Offset from the start of the slab just past its last record
+    /// and the commit mark after it, if any.
+    pub fn end_offset(&self) -> Result<usize, StorageError> {
+        let mut offset = self.offset;
+        let mut seen = 0;
+        while offset.saturating_add(FRAME_SIZE) <= self.size {
+            let (length, check_sum) = self.frame(offset)?;
+            if length == MARK_LENGTH {
+                if seen == self.count && check_sum != mark_checksum(self.slab, seen) {
+                    break;
+                }
+                offset = self.next_frame(offset, 0)?;
+                continue;
+            }
+            if seen == self.count {
+                break;
+            }
+            offset = self.next_frame(offset, length & !COMPRESSED_FLAG)?;
+            seen += 1;
+        }
+
+        if seen < self.count || offset > self.size {
+            return Err(StorageError::CorruptDB);
+        }
+        Ok(offset)
+    }
+
     pub fn get_head(&self) -> Cursor {
         Cursor {
             slab: self.slab,
@@ -37,65 +252,230 @@ impl<'a> Slab<'a> {
         }
     }
 
-    pub fn read(&self, mut cursor: Cursor) -> Result<Option<(Record<'a>, Cursor)>, StorageError> {
+    /// Read the record at `cursor` in to `buffer`.
+    pub fn read<'b>(
+        &self,
+        mut cursor: Cursor,
+        buffer: &'b mut [u8],
+    ) -> Result<Option<(Record<'b>, Cursor)>, StorageError> {
         if cursor.read_count >= self.record_count() {
             return Ok(None);
         }
 
-        let at = cursor.offset;
-        let (length, offset) = read_u32(self.records, at)?;
+        let (record, offset) = self.read_frame(cursor.offset, buffer)?;
+        cursor.offset = offset;
+        cursor.read_count = cursor
+            .read_count
+            .checked_add(1)
+            .ok_or(StorageError::Unreachable)?;
 
-        // We don't really track the end of what is used
-        // so if we read a zero length we assume we are
-        // passed the end. This is kinda janky.
-        if length == 0 {
-            return Ok(None);
+        Ok(Some((record, cursor)))
+    }
+
+    /// The header including appends committed after the one this slab
+    /// was opened with, records followed by a commit mark for them.
+    /// Reading stops at anything else, such as a torn write.
+    pub(crate) fn recover(&self, buffer: &mut [u8]) -> Result<SlabHeader, StorageError> {
+        let mut committed = self.header();
+        let mut pending = committed;
+        let mut offset = self.end_offset()?;
+
+        while offset.saturating_add(FRAME_SIZE) <= self.size {
+            let (length, check_sum) = self.frame(offset)?;
+            if length == MARK_LENGTH {
+                if pending.count == committed.count
+                    || check_sum != mark_checksum(self.slab, pending.count)
+                {
+                    break;
+                }
+                committed = pending;
+                offset = self.next_frame(offset, 0)?;
+                continue;
+            }
+
+            let Ok((record, next)) = self.read_frame(offset, buffer) else {
+                break;
+            };
+            if record.max_sequence < pending.max_sequence
+                || record.message_count < pending.max_message
+            {
+                break;
+            }
+            pending = SlabHeader {
+                count: pending.count.checked_add(1).ok_or(StorageError::Unreachable)?,
+                max_sequence: record.max_sequence,
+                max_message: record.message_count,
+            };
+            offset = next;
         }
 
+        Ok(committed)
+    }
+
+    /// The length and check sum of the frame at `offset`.
+    fn frame(&self, offset: usize) -> Result<(u32, [u8; CHECKSUM_SIZE]), StorageError> {
+        let mut bytes = [0u8; FRAME_SIZE];
+        self.source.read_slab(self.slab, offset, &mut bytes)?;
+        let (length, offset) = read_u32(&bytes, 0)?;
+        let (check_sum, _) = read_arr::<CHECKSUM_SIZE>(&bytes, offset)?;
+        Ok((length, check_sum))
+    }
+
+    /// Where the frame after one at `offset` with `length` bytes of
+    /// record starts.
+    fn next_frame(&self, offset: usize, length: u32) -> Result<usize, StorageError> {
         // BUG: what happens if usize is smaller then u32?
-        let end_offset = offset
-            .checked_add(length as usize)
+        let end = offset
+            .checked_add(FRAME_SIZE)
+            .and_then(|end| end.checked_add(length as usize))
             .ok_or(StorageError::CorruptDB)?;
+        align_up(end, self.align)
+    }
 
-        let Some(slice) = self.records.get(offset..end_offset) else {
-            return Err(StorageError::CorruptDB);
+    /// The record in the frame at `offset`, skipping commit marks, and
+    /// where the next frame starts.
+    fn read_frame<'b>(
+        &self,
+        mut offset: usize,
+        buffer: &'b mut [u8],
+    ) -> Result<(Record<'b>, usize), StorageError> {
+        let (length, check_sum) = loop {
+            let (length, check_sum) = self.frame(offset)?;
+            if length != MARK_LENGTH {
+                break (length, check_sum);
+            }
+            offset = self.next_frame(offset, 0)?;
         };
+        let compressed = length & COMPRESSED_FLAG != 0;
+        let length = length & !COMPRESSED_FLAG;
 
-        let record: Record<'_> = from_bytes(slice)?;
-        cursor.offset = end_offset;
-        cursor.read_count = cursor
-            .read_count
-            .checked_add(1)
-            .ok_or(StorageError::Unreachable)?;
+        // The header says there is a record here so
+        // an empty one means the slab is damaged.
+        if length == 0 {
+            return Err(StorageError::CorruptDB);
+        }
 
-        Ok(Some((record, cursor)))
+        let next = self.next_frame(offset, length)?;
+        let start = offset + FRAME_SIZE;
+        let end = start + length as usize;
+        if end > self.size {
+            return Err(StorageError::CorruptDB);
+        }
+
+        let body = buffer
+            .get_mut(..length as usize)
+            .ok_or(StorageError::RecordTooLarge(length as usize))?;
+        self.source.read_slab(self.slab, start, body)?;
+
+        if compute_checksum(&[&*body]) != check_sum {
+            return Err(StorageError::BadChecksum);
+        }
+
+        let mut record: Record<'b> = match self.opener {
+            Some(opener) => opener.open(body)?,
+            None => from_bytes(body)?,
+        };
+        record.compressed = compressed;
+
+        Ok((record, next))
     }
 }
 
+/// Where a `SlabWriter` is up to, for handing it to an `IO` layered
+/// over the one that made it.
+pub(crate) struct WriterState {
+    count: u32,
+    slab_max_sequence: u64,
+    slab_max_message: u64,
+    slab: usize,
+    offset: usize,
+    end: usize,
+}
+
 pub struct SlabWriter<'a, I: IO> {
     count: u32,
     slab_max_sequence: u64,
-    slab_offset: usize,
+    slab_max_message: u64,
+    slab: usize,
     offset: usize,
     end: usize,
-    // [count: u32][slab_max_sequence: u64][length:u32][max_sequence: u64][data: [u8]]
+    compression: bool,
+    // [count: u32][slab_max_sequence: u64][slab_max_message: u64][index: u64]
+    // [length:u32][check_sum: [u8; 4]][Record]
     io: &'a mut I, // Record Data
 }
 
 impl<'a, I: IO> SlabWriter<'a, I> {
-    pub fn new(io: &'a mut I, offset: usize, end: usize) -> SlabWriter<'a, I> {
-        const INITIAL_OFFSET: usize = size_of::<u32>() + size_of::<u64>();
-        debug_assert!(INITIAL_OFFSET < (end - offset));
+    /// Writer for the empty slab `slab` whose records go in
+    /// `offset..end`.
+    pub fn new(io: &'a mut I, slab: usize, offset: usize, end: usize) -> SlabWriter<'a, I> {
+        debug_assert!(offset < end);
         Self {
             count: 0,
             slab_max_sequence: 0,
-            slab_offset: offset,
-            offset: offset + INITIAL_OFFSET,
+            slab_max_message: 0,
+            slab,
+            offset,
+            end,
+            compression: false,
+            io,
+        }
+    }
+
+    /// Writer adding records to the committed slab `slab` after the
+    /// `count` records already ending at `offset`.
+    pub fn resume(
+        io: &'a mut I,
+        slab: usize,
+        offset: usize,
+        end: usize,
+        count: u32,
+        slab_max_sequence: u64,
+        slab_max_message: u64,
+    ) -> SlabWriter<'a, I> {
+        debug_assert!(offset <= end);
+        Self {
+            count,
+            slab_max_sequence,
+            slab_max_message,
+            slab,
+            offset,
             end,
+            compression: false,
             io,
         }
     }
 
+    pub(crate) fn into_state(self) -> WriterState {
+        WriterState {
+            count: self.count,
+            slab_max_sequence: self.slab_max_sequence,
+            slab_max_message: self.slab_max_message,
+            slab: self.slab,
+            offset: self.offset,
+            end: self.end,
+        }
+    }
+
+    /// The writer `into_state` left off, now writing through `io`.
+    pub(crate) fn from_state(io: &'a mut I, state: WriterState) -> SlabWriter<'a, I> {
+        Self::resume(
+            io,
+            state.slab,
+            state.offset,
+            state.end,
+            state.count,
+            state.slab_max_sequence,
+            state.slab_max_message,
+        )
+    }
+
+    /// Compress the data of `Data` records from here on, each one is
+    /// only stored compressed if that makes it smaller.
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.compression = enabled;
+    }
+
     pub fn write_record(
         &mut self,
         max_sequence: u64,
@@ -104,15 +484,35 @@ impl<'a, I: IO> SlabWriter<'a, I> {
         sender: NodeId,
         data: &[u8],
     ) -> Result<(), StorageError> {
+        self.write_kind(RecordKind::Data, max_sequence, message_count, sequence, sender, data)
+    }
+
+    pub fn write_kind(
+        &mut self,
+        kind: RecordKind,
+        max_sequence: u64,
+        message_count: u64,
+        sequence: u64,
+        sender: NodeId,
+        data: &[u8],
+    ) -> Result<(), StorageError> {
+        let mut packed = [0u8; COMPRESS_MAX];
+        let (data, compressed) = pack(kind, self.compression, data, &mut packed);
+
         let record = Record {
+            kind,
             max_sequence,
             message_count,
             sequence,
             sender,
             data,
+            compressed,
         };
 
-        if self.slab_max_sequence > record.max_sequence {
+        // Lookups binary search the slabs on both of these.
+        if self.slab_max_sequence > record.max_sequence
+            || self.slab_max_message > record.message_count
+        {
             return Err(StorageError::OutOfOrder);
         }
 
@@ -122,6 +522,7 @@ impl<'a, I: IO> SlabWriter<'a, I> {
         self.count = self.count.checked_add(1).ok_or(StorageError::Unreachable)?;
 
         self.slab_max_sequence = record.max_sequence;
+        self.slab_max_message = record.message_count;
 
         self.offset = end;
         Ok(())
@@ -129,7 +530,12 @@ impl<'a, I: IO> SlabWriter<'a, I> {
 
     pub fn commit(self) -> Result<(), StorageError> {
         self.io
-            .commit(self.count, self.slab_max_sequence, self.slab_offset)?;
+            .commit(
+                self.count,
+                self.slab_max_sequence,
+                self.slab_max_message,
+                self.slab,
+            )?;
         Ok(())
     }
 }
@@ -0,0 +1,597 @@
+use super::*;
+
+/// Lays slabs out on a `Media` as a ring behind a log of roots.
+///
+/// See `DbInfo` for the page layout. Slab layout:
+/// [count: u32][max_sequence: u64][max_message: u64][index: u64] of the slab before
+/// [length: u32][check_sum: [u8; 4]][Record]...
+///
+/// Slabs are addressed by a logical index which only ever grows, the
+/// committed slabs are `head..(head + slab_count)` and logical index
+/// `i` lives in physical slot `i % max_index`. Walking the ring means
+/// every slot is erased and reused equally often.
+///
+/// The last slab stays open for appends and its header lives in the
+/// root. Records are only ever added past the end of the committed
+/// ones, into space erased when the slab was started, and a slab's
+/// header is written once, in to the start of the slab after it when
+/// that is started. So nothing is programmed twice between erases,
+/// which flash needs. A slab with anything but erased space past its
+/// records, left by a write that was never committed, takes no more
+/// and the next record starts a new slab.
+///
+/// Starting a slab, evicting one or moving the checkpoint commits a
+/// new root. A commit makes its records durable then writes the next
+/// root, so a power cut at any step leaves the previous root and
+/// everything it points at untouched. Appends to the last slab are
+/// committed by a mark after their records instead, see
+/// `Slab::recover`, so roots are written about as often as slabs are
+/// started and the root pages wear no faster than the slabs.
+pub struct SlabIO<M, const SLAB_SIZE: usize> {
+    head: usize,
+    slab_count: usize,
+    tail_header: SlabHeader,
+    tail_closed: bool,
+    checkpoint: Option<Cursor>,
+    max_index: usize,
+    generation: u64,
+    root_offsets: [usize; 2],
+    root_page: usize,
+    root_slot: usize,
+    data_offset: usize,
+    write_size: usize,
+    /// End of the last record written, where an append's mark goes.
+    written: usize,
+    /// The last slab, once it is known to be erased past its records.
+    clean_tail: Option<usize>,
+    media: M,
+}
+
+impl<M: Media, const SLAB_SIZE: usize> SlabIO<M, SLAB_SIZE> {
+    const ROOT_SLOTS: usize = SLAB_SIZE / ROOT_SLOT_SIZE;
+
+    /// Recover the committed slabs already present on `media`,
+    /// formatting it first if it is blank.
+    pub fn from_media(media: M) -> Result<Self, StorageError> {
+        let write_size = media.write_size();
+        if Self::ROOT_SLOTS == 0
+            || write_size == 0
+            || !ROOT_SLOT_SIZE.is_multiple_of(write_size)
+            || !SLAB_SIZE.is_multiple_of(write_size)
+        {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        if media.size() < SLAB_SIZE {
+            return Err(StorageError::DbFull);
+        }
+        let mut info_page = [0u8; SLAB_SIZE];
+        media.read(0, &mut info_page)?;
+
+        if is_blank(&info_page) {
+            return Self::format(media);
+        }
+
+        let info: DbInfo = from_bytes(&info_page).map_err(|_| StorageError::CorruptDB)?;
+        info.validate(SLAB_SIZE, write_size)?;
+
+        let mut io = Self::with_info(&info, media)?;
+
+        let end = io
+            .max_index
+            .checked_mul(SLAB_SIZE)
+            .and_then(|len| len.checked_add(io.data_offset))
+            .ok_or(StorageError::CorruptDB)?;
+        if end > io.media.size() {
+            return Err(StorageError::CorruptDB);
+        }
+
+        let mut best: Option<(DbRoot, usize)> = None;
+        for page in 0..io.root_offsets.len() {
+            for slot in 0..Self::ROOT_SLOTS {
+                let Ok(root) = io.read_root(page, slot) else {
+                    continue;
+                };
+                if root.validate().is_err() {
+                    continue;
+                }
+                let newer = match &best {
+                    Some((current, _)) => current.generation < root.generation,
+                    None => true,
+                };
+                if newer {
+                    best = Some((root, page));
+                }
+            }
+        }
+
+        let (root, page) = best.ok_or(StorageError::CorruptDB)?;
+        let slab_count = root
+            .data_end
+            .checked_sub(root.data_start)
+            .ok_or(StorageError::CorruptDB)?;
+
+        io.head = to_usize(root.data_start)?;
+        io.slab_count = to_usize(slab_count)?;
+        io.tail_header = root.tail;
+        io.tail_closed = root.tail_closed;
+        io.checkpoint = root.checkpoint;
+        io.generation = root.generation;
+
+        // The slots after the newest root may hold a torn write, so
+        // the next root starts the other page.
+        io.root_page = page;
+        io.root_slot = Self::ROOT_SLOTS;
+
+        if io.slab_count > io.max_index {
+            return Err(StorageError::CorruptDB);
+        }
+
+        // Pick up the appends committed since the root was written.
+        if let Some(last) = io.tail()?.checked_sub(1).filter(|_| io.slab_count > 0) {
+            if !io.tail_closed {
+                let tail_header = io.get_slab(last)?.recover(&mut info_page)?;
+                io.tail_header = tail_header;
+            }
+        }
+
+        Ok(io)
+    }
+
+    pub fn media(&self) -> &M {
+        &self.media
+    }
+
+    pub fn into_media(self) -> M {
+        self.media
+    }
+
+    fn with_info(info: &DbInfo, media: M) -> Result<Self, StorageError> {
+        Ok(Self {
+            head: 0,
+            slab_count: 0,
+            tail_header: SlabHeader::default(),
+            tail_closed: false,
+            checkpoint: None,
+            max_index: to_usize(info.slab_capacity)?,
+            generation: 0,
+            root_offsets: [to_usize(info.root1_offset)?, to_usize(info.root2_offset)?],
+            root_page: 0,
+            root_slot: 0,
+            data_offset: to_usize(info.data_offset)?,
+            write_size: info.write_size as usize,
+            written: 0,
+            clean_tail: None,
+            media,
+        })
+    }
+
+    fn format(media: M) -> Result<Self, StorageError> {
+        let pages = media.size() / SLAB_SIZE;
+        let max_index = pages
+            .checked_sub(DbInfo::reserved_pages())
+            .filter(|count| *count > 0)
+            .ok_or(StorageError::DbFull)?;
+
+        let info = DbInfo::new(SLAB_SIZE, max_index, media.write_size())?;
+        let mut io = Self::with_info(&info, media)?;
+
+        // Clear anything left over from a previous life so it
+        // can not out rank the fresh root.
+        io.media.erase(io.root_offsets[0], SLAB_SIZE)?;
+        io.media.erase(io.root_offsets[1], SLAB_SIZE)?;
+        io.write_root_slot(&DbRoot::new(0, 0, 0, SlabHeader::default(), false, None))?;
+        io.media.sync()?;
+
+        // The info page goes last so a cut off format is retried.
+        io.media.erase(0, SLAB_SIZE)?;
+        let mut buffer = [0u8; SLAB_SIZE];
+        let wrote = to_slice(&info, &mut buffer)?;
+        io.media.write(0, wrote)?;
+        io.media.sync()?;
+
+        Ok(io)
+    }
+
+    fn root_slot_offset(&self, page: usize, slot: usize) -> Result<usize, StorageError> {
+        slot.checked_mul(ROOT_SLOT_SIZE)
+            .and_then(|offset| offset.checked_add(self.root_offsets[page]))
+            .ok_or(StorageError::Unreachable)
+    }
+
+    fn read_root(&self, page: usize, slot: usize) -> Result<DbRoot, StorageError> {
+        let offset = self.root_slot_offset(page, slot)?;
+        let mut bytes = [0u8; ROOT_SLOT_SIZE];
+        self.media.read(offset, &mut bytes)?;
+        Ok(from_bytes(&bytes)?)
+    }
+
+    /// Write `root` in to the next free slot, moving to the other
+    /// root page when this one is full.
+    fn write_root_slot(&mut self, root: &DbRoot) -> Result<(), StorageError> {
+        if self.root_slot >= Self::ROOT_SLOTS {
+            self.root_page = (self.root_page + 1) % self.root_offsets.len();
+            self.root_slot = 0;
+            self.media.erase(self.root_offsets[self.root_page], SLAB_SIZE)?;
+        }
+
+        let mut buffer = [0u8; ROOT_SLOT_SIZE];
+        let wrote = to_slice(root, &mut buffer)?;
+        let offset = self.root_slot_offset(self.root_page, self.root_slot)?;
+        self.media.write(offset, wrote)?;
+        self.root_slot += 1;
+        Ok(())
+    }
+
+    /// Commit a new root covering `head..(head + slab_count)` where the
+    /// last slab holds what `tail_header` says, and takes no more
+    /// records if `tail_closed`. The checkpoint is kept while the
+    /// records it starts at are.
+    fn write_root(
+        &mut self,
+        head: usize,
+        slab_count: usize,
+        tail_header: SlabHeader,
+        tail_closed: bool,
+    ) -> Result<(), StorageError> {
+        let generation = self
+            .generation
+            .checked_add(1)
+            .ok_or(StorageError::Unreachable)?;
+        let end = head
+            .checked_add(slab_count)
+            .ok_or(StorageError::Unreachable)?;
+
+        let checkpoint = self.checkpoint.clone().filter(|cursor| {
+            cursor.slab >= head
+                && cursor.slab < end
+                && (cursor.slab + 1 < end || cursor.read_count < tail_header.count)
+        });
+
+        let root = DbRoot::new(
+            generation,
+            head as u64,
+            end as u64,
+            tail_header,
+            tail_closed,
+            checkpoint.clone(),
+        );
+        self.write_root_slot(&root)?;
+        self.media.sync()?;
+
+        self.generation = generation;
+        self.head = head;
+        self.slab_count = slab_count;
+        self.tail_header = tail_header;
+        self.tail_closed = tail_closed;
+        self.checkpoint = checkpoint;
+        Ok(())
+    }
+
+    /// Write the header of the last slab, `index`, at the start of the
+    /// slab after it, which `new_writer` has just erased.
+    ///
+    /// Until the root moves on the header is ignored, so a torn write
+    /// here is erased and written again by the next attempt.
+    fn seal(&mut self, index: usize) -> Result<(), StorageError> {
+        let next = index.checked_add(1).ok_or(StorageError::Unreachable)?;
+        let offset = self.slab_offset(next)?;
+        let header = encode_header(&self.tail_header, index as u64)?;
+        self.media.write(offset, &header)
+    }
+
+    fn slab_offset(&self, index: usize) -> Result<usize, StorageError> {
+        let slot = index
+            .checked_rem(self.max_index)
+            .ok_or(StorageError::DbFull)?;
+        slot.checked_mul(SLAB_SIZE)
+            .and_then(|offset| offset.checked_add(self.data_offset))
+            .ok_or(StorageError::Unreachable)
+    }
+
+    /// Offset in to each slab of its first record.
+    fn first_offset(&self) -> Result<usize, StorageError> {
+        first_offset(self.write_size)
+    }
+
+    fn tail(&self) -> Result<usize, StorageError> {
+        self.head
+            .checked_add(self.slab_count)
+            .ok_or(StorageError::Unreachable)
+    }
+
+    /// Whether `offset..end` of the media reads as erased.
+    fn is_erased(&self, offset: usize, end: usize) -> Result<bool, StorageError> {
+        let mut chunk = [0u8; 64];
+        let mut at = offset;
+        while at < end {
+            let step = chunk.len().min(end - at);
+            let bytes = &mut chunk[..step];
+            self.media.read(at, bytes)?;
+            if bytes.iter().any(|b| *b != 0xFF) {
+                return Ok(false);
+            }
+            at += step;
+        }
+        Ok(true)
+    }
+}
+
+impl<M: Media, const SLAB_SIZE: usize> SlabSource for SlabIO<M, SLAB_SIZE> {
+    fn read_slab(&self, index: usize, offset: usize, target: &mut [u8]) -> Result<(), StorageError> {
+        let end = offset
+            .checked_add(target.len())
+            .ok_or(StorageError::OutOfBounds)?;
+        if end > SLAB_SIZE {
+            return Err(StorageError::OutOfBounds);
+        }
+        let start = self
+            .slab_offset(index)?
+            .checked_add(offset)
+            .ok_or(StorageError::Unreachable)?;
+        self.media.read(start, target)
+    }
+}
+
+impl<M: Media, const SLAB_SIZE: usize> IO for SlabIO<M, SLAB_SIZE> {
+    type Buffer = SlabBuffer<SLAB_SIZE>;
+
+    fn truncate(&mut self) -> Result<(), StorageError> {
+        if self.slab_count == 0 {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let (tail_header, tail_closed) = match self.slab_count {
+            1 => (SlabHeader::default(), false),
+            _ => (self.tail_header, self.tail_closed),
+        };
+
+        // Once the root moves past it the slab is free.
+        let head = self.head.checked_add(1).ok_or(StorageError::Unreachable)?;
+        self.write_root(head, self.slab_count - 1, tail_header, tail_closed)
+    }
+
+    fn slab_size(&self) -> usize {
+        SLAB_SIZE
+    }
+
+    fn write_size(&self) -> usize {
+        self.write_size
+    }
+
+    fn free_slabs(&self) -> Result<usize, StorageError> {
+        Ok(self.max_index - self.slab_count)
+    }
+
+    fn slab_count(&self) -> Result<usize, StorageError> {
+        Ok(self.slab_count)
+    }
+
+    fn new_writer(&mut self) -> Result<SlabWriter<'_, Self>, StorageError> {
+        if self.slab_count >= self.max_index {
+            return Err(StorageError::DbFull);
+        }
+
+        let start = self.slab_offset(self.tail()?)?;
+        let end = start
+            .checked_add(SLAB_SIZE)
+            .ok_or(StorageError::Unreachable)?;
+        let first = start
+            .checked_add(self.first_offset()?)
+            .ok_or(StorageError::Unreachable)?;
+
+        // The slot still holds whatever was evicted from it.
+        self.media.erase(start, SLAB_SIZE)?;
+
+        let index = self.tail()?;
+        let writer = SlabWriter::new(self, index, first, end);
+
+        Ok(writer)
+    }
+
+    fn append_writer(&mut self) -> Result<Option<SlabWriter<'_, Self>>, StorageError> {
+        let Some(index) = self.tail()?.checked_sub(1).filter(|_| self.slab_count > 0) else {
+            return Ok(None);
+        };
+        if self.tail_closed {
+            return Ok(None);
+        }
+
+        let used = self.get_slab(index)?.end_offset()?;
+        let header = self.tail_header;
+
+        let start = self.slab_offset(index)?;
+        let end = start
+            .checked_add(SLAB_SIZE)
+            .ok_or(StorageError::Unreachable)?;
+        let offset = start.checked_add(used).ok_or(StorageError::Unreachable)?;
+
+        // Past the records is either erased or a write that was never
+        // committed, which can not be programmed over.
+        if self.clean_tail != Some(index) {
+            if !self.is_erased(offset, end)? {
+                return Ok(None);
+            }
+            self.clean_tail = Some(index);
+        }
+
+        Ok(Some(SlabWriter::resume(
+            self,
+            index,
+            offset,
+            end,
+            header.count,
+            header.max_sequence,
+            header.max_message,
+        )))
+    }
+
+    fn get_slab(&self, index: usize) -> Result<Slab<'_>, StorageError> {
+        let tail = self.tail()?;
+        if index < self.head || index >= tail {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let header = if index + 1 == tail {
+            self.tail_header
+        } else {
+            let mut bytes = [0u8; SLAB_HEADER_SIZE];
+            self.media.read(self.slab_offset(index + 1)?, &mut bytes)?;
+            decode_header(&bytes, index)?
+        };
+
+        Slab::new(
+            self,
+            index,
+            &header,
+            self.first_offset()?,
+            SLAB_SIZE,
+            self.write_size,
+        )
+    }
+
+    fn write_record(
+        &mut self,
+        offset: usize,
+        end: usize,
+        record: &Record,
+    ) -> Result<usize, StorageError> {
+        // Until the next commit the space past the records may not be
+        // erased any more.
+        self.clean_tail = None;
+
+        // Leave room for the mark that commits an append, a new slab is
+        // committed by its root.
+        let start = self.slab_offset(self.tail()?)?;
+        let mark = if (start..start.saturating_add(SLAB_SIZE)).contains(&offset) {
+            0
+        } else {
+            mark_reserve(self.write_size)?
+        };
+        let available = end
+            .checked_sub(offset)
+            .ok_or(StorageError::OutOfBounds)?
+            .checked_sub(mark)
+            .ok_or(StorageError::SlabFull)?;
+        if available > SLAB_SIZE {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let mut buffer = [0u8; SLAB_SIZE];
+        let total = write_frame(record, &mut buffer[..available])?;
+        self.media.write(offset, &buffer[..total])?;
+
+        let next = offset
+            .checked_add(total)
+            .ok_or(StorageError::OutOfBounds)?;
+        self.written = align_up(next, self.write_size)?;
+        Ok(self.written)
+    }
+
+    fn commit(
+        &mut self,
+        record_count: u32,
+        max_sequence: u64,
+        max_message: u64,
+        slab: usize,
+    ) -> Result<(), StorageError> {
+        let tail = self.tail()?;
+        let tail_header = SlabHeader {
+            count: record_count,
+            max_sequence,
+            max_message,
+        };
+
+        // Either a new slab at the tail or an append to the last one.
+        if slab == tail {
+            if let Some(last) = tail.checked_sub(1).filter(|_| self.slab_count > 0) {
+                self.seal(last)?;
+            }
+            let slab_count = self
+                .slab_count
+                .checked_add(1)
+                .ok_or(StorageError::Unreachable)?;
+
+            // The records must be durable before anything points at them.
+            self.media.sync()?;
+            self.write_root(self.head, slab_count, tail_header, false)?;
+        } else if self.slab_count > 0 && slab.checked_add(1) == Some(tail) && !self.tail_closed {
+            if record_count == self.tail_header.count {
+                return Ok(());
+            }
+
+            // Every record has its own check sum, so a mark that lands
+            // ahead of a torn record is never reached.
+            let mark = mark_frame(slab, record_count)?;
+            self.media.write(self.written, &mark)?;
+            self.media.sync()?;
+            self.tail_header = tail_header;
+        } else {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        self.clean_tail = Some(slab);
+        Ok(())
+    }
+
+    fn rewind(
+        &mut self,
+        slab: usize,
+        record_count: u32,
+        max_sequence: u64,
+        max_message: u64,
+    ) -> Result<(), StorageError> {
+        if slab < self.head || slab >= self.tail()? {
+            return Err(StorageError::OutOfBounds);
+        }
+
+        let kept = slab - self.head;
+        if record_count == 0 {
+            let tail_header = match slab.checked_sub(1).filter(|_| kept > 0) {
+                Some(last) => self.get_slab(last)?.header(),
+                None => SlabHeader::default(),
+            };
+            return self.write_root(self.head, kept, tail_header, false);
+        }
+
+        // The records cut off are still there, so the slab is closed
+        // rather than have later appends follow them.
+        let tail_header = SlabHeader {
+            count: record_count,
+            max_sequence,
+            max_message,
+        };
+        self.write_root(self.head, kept + 1, tail_header, true)
+    }
+
+    fn get_head(&self) -> Result<usize, StorageError> {
+        Ok(self.head)
+    }
+
+    fn checkpoint(&self) -> Result<Option<Cursor>, StorageError> {
+        Ok(self.checkpoint.clone())
+    }
+
+    fn set_checkpoint(&mut self, cursor: Cursor) -> Result<(), StorageError> {
+        let previous = self.checkpoint.replace(cursor);
+        let written = self.write_root(
+            self.head,
+            self.slab_count,
+            self.tail_header,
+            self.tail_closed,
+        );
+        if written.is_err() {
+            self.checkpoint = previous;
+        }
+        written
+    }
+}
+
+/// Both zeroed RAM and erased flash count as never written.
+fn is_blank(data: &[u8]) -> bool {
+    data.iter().all(|b| *b == 0) || data.iter().all(|b| *b == 0xFF)
+}
+
+fn to_usize(value: u64) -> Result<usize, StorageError> {
+    usize::try_from(value).map_err(|_| StorageError::CorruptDB)
+}
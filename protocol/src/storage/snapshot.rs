@@ -0,0 +1,220 @@
+use super::*;
+
+/// First bytes of every snapshot, "FNDS".
+pub const SNAPSHOT_MAGIC: u32 = 0x464E_4453;
+
+/// Bumped whenever the layout of a snapshot changes.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Start of a snapshot.
+///
+/// A snapshot is a run of frames, each laid out like a stored record
+/// as [length: u32][check_sum: [u8; 4]][postcard value]. The first
+/// frame holds this header, then come the membership snapshot if
+/// `membership` is set and finally `records` message records, oldest
+/// first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    pub magic: u32,
+    pub version: u8,
+    pub channel_id: ChannelId,
+    pub records: u64,
+    pub membership: bool,
+}
+
+/// Everything after the header of a snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SnapshotEntry<'a> {
+    /// The signed membership snapshot, written before any record as
+    /// the records after an eviction can only be checked with it.
+    Membership { sender: NodeId, data: &'a [u8] },
+    Record {
+        max_sequence: u64,
+        message_count: u64,
+        sequence: u64,
+        sender: NodeId,
+        data: &'a [u8],
+    },
+}
+
+impl<I> Storage<I>
+where
+    I: IO,
+{
+    /// Write the channel's log in to `target` as a snapshot another
+    /// device can `import`. Only message records and the membership
+    /// snapshot are exported, checkpoints are rebuilt on import.
+    /// Deleted messages are left out.
+    pub fn export<'b>(
+        &self,
+        channel_id: ChannelId,
+        target: &'b mut [u8],
+    ) -> Result<&'b [u8], StorageError> {
+        let tombstones = self.tombstones()?;
+        let exported = |record: &Record<'_>| {
+            record.kind == RecordKind::Data && !tombstones.iter().any(|t| t.names(record))
+        };
+
+        let mut buffer = I::Buffer::default();
+        let mut records = 0u64;
+        if let Some(mut cursor) = self.get_cursor_from_sequence(0)? {
+            while let Some((record, next)) = self.read_record(cursor, buffer.as_mut())? {
+                if exported(&record) {
+                    records = records.checked_add(1).ok_or(StorageError::Unreachable)?;
+                }
+                cursor = next;
+            }
+        }
+        let membership = self.membership_info()?;
+
+        let header = SnapshotHeader {
+            magic: SNAPSHOT_MAGIC,
+            version: SNAPSHOT_VERSION,
+            channel_id,
+            records,
+            membership: membership.is_some(),
+        };
+        let mut offset = write_entry(&header, target)?;
+
+        if let Some((sender, len)) = membership {
+            // The snapshot is read in to the end of `target` and framed
+            // in front of it.
+            let split = target.len().checked_sub(len).ok_or(StorageError::OutOfBounds)?;
+            let (out, scratch) = target.split_at_mut(split);
+            let data = self
+                .read_membership(scratch)?
+                .ok_or(StorageError::Unreachable)?;
+            let entry = SnapshotEntry::Membership { sender, data };
+            let out = out.get_mut(offset..).ok_or(StorageError::OutOfBounds)?;
+            offset += write_entry(&entry, out)?;
+        }
+
+        // Records are exported as written so snapshots do not depend
+        // on how either side stores them.
+        let mut expanded = [0u8; COMPRESS_MAX];
+        if let Some(mut cursor) = self.get_cursor_from_sequence(0)? {
+            while let Some((record, next)) = self.read_record(cursor, buffer.as_mut())? {
+                if exported(&record) {
+                    let entry = SnapshotEntry::Record {
+                        max_sequence: record.max_sequence,
+                        message_count: record.message_count,
+                        sequence: record.sequence,
+                        sender: record.sender,
+                        data: record.decompress(&mut expanded)?,
+                    };
+                    let out = target.get_mut(offset..).ok_or(StorageError::OutOfBounds)?;
+                    offset += write_entry(&entry, out)?;
+                }
+                cursor = next;
+            }
+        }
+
+        target.get(..offset).ok_or(StorageError::Unreachable)
+    }
+
+    /// Load a snapshot written by `export` in to this storage, which
+    /// must be empty, returning the channel it belongs to.
+    ///
+    /// The whole snapshot is checked before anything is written.
+    /// `verify` is handed every entry in order and should check its
+    /// signature, an error from it stops the import.
+    pub fn import<'b, E, F>(&mut self, bytes: &'b [u8], mut verify: F) -> Result<ChannelId, E>
+    where
+        E: From<StorageError>,
+        F: FnMut(&SnapshotEntry<'b>) -> Result<(), E>,
+    {
+        if self.io.slab_count()? != 0 {
+            return Err(StorageError::OutOfOrder.into());
+        }
+
+        let (header, start) = read_header(bytes)?;
+
+        let mut offset = start;
+        for _ in 0..header.entries() {
+            let (entry, next) = read_entry::<SnapshotEntry<'b>>(bytes, offset)?;
+            verify(&entry)?;
+            offset = next;
+        }
+        if offset != bytes.len() {
+            return Err(StorageError::CorruptDB.into());
+        }
+
+        let mut offset = start;
+        for _ in 0..header.entries() {
+            let (entry, next) = read_entry::<SnapshotEntry<'b>>(bytes, offset)?;
+            match entry {
+                SnapshotEntry::Membership { sender, data } => {
+                    self.write_membership(sender, data)?;
+                }
+                SnapshotEntry::Record {
+                    max_sequence,
+                    message_count,
+                    sequence,
+                    sender,
+                    data,
+                } => {
+                    self.append(max_sequence, message_count, sequence, sender, data)?;
+                }
+            }
+            offset = next;
+        }
+
+        Ok(header.channel_id)
+    }
+}
+
+impl SnapshotHeader {
+    /// Frames following the header.
+    fn entries(&self) -> u64 {
+        self.records.saturating_add(u64::from(self.membership))
+    }
+}
+
+/// Read and check the header at the start of a snapshot, returning
+/// it with the offset of the first entry.
+pub fn read_header(bytes: &[u8]) -> Result<(SnapshotHeader, usize), StorageError> {
+    let (header, offset) = read_entry::<SnapshotHeader>(bytes, 0)?;
+    if header.magic != SNAPSHOT_MAGIC || header.version != SNAPSHOT_VERSION {
+        return Err(StorageError::CorruptDB);
+    }
+
+    Ok((header, offset))
+}
+
+fn write_entry<T: Serialize>(value: &T, target: &mut [u8]) -> Result<usize, StorageError> {
+    let body = target.get_mut(FRAME_SIZE..).ok_or(StorageError::OutOfBounds)?;
+    let wrote = match to_slice(value, body) {
+        Ok(wrote) => wrote,
+        Err(postcard::Error::SerializeBufferFull) => return Err(StorageError::OutOfBounds),
+        Err(e) => return Err(e.into()),
+    };
+    let wrote_len = wrote.len();
+    let check_sum = compute_checksum(&[wrote]);
+
+    let len = u32::try_from(wrote_len).or(Err(StorageError::RecordTooLarge(wrote_len)))?;
+    let offset = write_u32(len, target, 0)?;
+    write_arr(check_sum, target, offset)?;
+
+    FRAME_SIZE
+        .checked_add(wrote_len)
+        .ok_or(StorageError::Unreachable)
+}
+
+fn read_entry<'a, T: Deserialize<'a>>(
+    bytes: &'a [u8],
+    offset: usize,
+) -> Result<(T, usize), StorageError> {
+    let (length, offset) = read_u32(bytes, offset).or(Err(StorageError::CorruptDB))?;
+    let (check_sum, offset) =
+        read_arr::<CHECKSUM_SIZE>(bytes, offset).or(Err(StorageError::CorruptDB))?;
+    let end = offset
+        .checked_add(length as usize)
+        .ok_or(StorageError::CorruptDB)?;
+    let slice = bytes.get(offset..end).ok_or(StorageError::CorruptDB)?;
+
+    if compute_checksum(&[slice]) != check_sum {
+        return Err(StorageError::BadChecksum);
+    }
+
+    Ok((from_bytes(slice)?, end))
+}
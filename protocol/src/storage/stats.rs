@@ -0,0 +1,84 @@
+use super::*;
+
+/// How full a `Storage` is, see `Storage::stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    pub slab_size: usize,
+    pub slabs_used: usize,
+    /// Slabs that can still be written before the oldest has to be
+    /// evicted.
+    pub slabs_free: usize,
+    /// Bytes of stored record frames and commit marks, slab headers
+    /// are not counted.
+    pub bytes_used: usize,
+    /// Bytes left for records in the last slab and the free slabs,
+    /// less the room each keeps for the mark committing an append.
+    pub bytes_free: usize,
+    pub records: u64,
+    /// The `Data` records, what `read_into` hands out.
+    pub messages: u64,
+    /// Channel wide `max_sequence` of the oldest stored message, `None`
+    /// when there are no messages.
+    pub oldest_sequence: Option<u64>,
+    /// Channel wide `max_sequence` of the newest stored message.
+    pub newest_sequence: Option<u64>,
+    /// Messages of the average size stored so far that fit in
+    /// `bytes_free`, `None` until a message is stored. Each carries its
+    /// share of checkpoints and the like.
+    pub remaining_messages: Option<u64>,
+}
+
+impl<I> Storage<I>
+where
+    I: IO,
+{
+    /// Report how much of the storage is used and how many more
+    /// messages fit before eviction starts. Reads the whole log.
+    pub fn stats(&self) -> Result<StorageStats, StorageError> {
+        let slab_size = self.io.slab_size();
+        let slabs_used = self.io.slab_count()?;
+        let slabs_free = self.io.free_slabs()?;
+        let first = first_offset(self.io.write_size())?;
+        let mark = mark_reserve(self.io.write_size())?;
+        let room = slab_size.saturating_sub(first).saturating_sub(mark);
+        let mut stats = StorageStats {
+            slab_size,
+            slabs_used,
+            slabs_free,
+            bytes_free: slabs_free.saturating_mul(room),
+            ..StorageStats::default()
+        };
+
+        let mut buffer = I::Buffer::default();
+        let head = self.io.get_head()?;
+        for index in head..head.saturating_add(slabs_used) {
+            let slab = self.io.get_slab(index)?;
+            let end = slab.end_offset()?;
+            stats.bytes_used = stats.bytes_used.saturating_add(end.saturating_sub(first));
+            stats.records = stats.records.saturating_add(u64::from(slab.record_count()));
+            if index + 1 == head + slabs_used {
+                // New records carry on in the last slab.
+                stats.bytes_free = stats
+                    .bytes_free
+                    .saturating_add(slab_size.saturating_sub(end).saturating_sub(mark));
+            }
+
+            let mut cursor = slab.get_head();
+            while let Some((record, next)) = slab.read(cursor, buffer.as_mut())? {
+                if record.kind == RecordKind::Data {
+                    stats.messages = stats.messages.saturating_add(1);
+                    stats.oldest_sequence.get_or_insert(record.max_sequence);
+                    stats.newest_sequence = Some(record.max_sequence);
+                }
+                cursor = next;
+            }
+        }
+
+        if let Some(average) = stats.bytes_used.checked_div(stats.messages as usize) {
+            let fit = stats.bytes_free.checked_div(average.max(1)).unwrap_or(0);
+            stats.remaining_messages = Some(fit as u64);
+        }
+
+        Ok(stats)
+    }
+}
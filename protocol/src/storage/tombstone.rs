@@ -0,0 +1,154 @@
+use super::*;
+
+/// Most tombstones a log can hold before it has to be compacted.
+pub const MAX_TOMBSTONES: usize = 16;
+
+/// Names a deleted envelope by its sender and sequence, which unlike
+/// the message index is never shared with another record.
+///
+/// The data of both `Tombstone` records and the `Deleted` records
+/// `compact_into` leaves in place of the envelopes they name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub sender: NodeId,
+    pub sequence: u64,
+    /// Kept so the sender's state can be rebuilt without the envelope.
+    pub id: EnvelopeId,
+}
+
+pub type Tombstones = Vec<Tombstone, MAX_TOMBSTONES>;
+
+impl Tombstone {
+    /// Whether `record` is the envelope this names.
+    pub fn names(&self, record: &Record<'_>) -> bool {
+        record.kind == RecordKind::Data
+            && record.sender == self.sender
+            && record.sequence == self.sequence
+    }
+}
+
+impl<I> Storage<I>
+where
+    I: IO,
+{
+    /// Record that the envelope named by `tombstone` is deleted. It
+    /// stays stored, and is still handed out by `read_into`, until the
+    /// log is compacted.
+    pub fn append_tombstone(
+        &mut self,
+        sender: NodeId,
+        tombstone: &Tombstone,
+    ) -> Result<(), StorageError> {
+        let mut target = [0u8; size_of::<Tombstone>() + 16];
+        let bytes = to_slice(tombstone, &mut target)?;
+        let (max_sequence, max_message) = self.tail_counters()?;
+        self.append_kind(RecordKind::Tombstone, max_sequence, max_message, 0, sender, bytes)
+    }
+
+    /// Every tombstone in the log not yet compacted, oldest first.
+    pub fn tombstones(&self) -> Result<Tombstones, StorageError> {
+        let mut found = Tombstones::new();
+        let Some(mut cursor) = self.get_cursor_from_sequence(0)? else {
+            return Ok(found);
+        };
+
+        let mut buffer = I::Buffer::default();
+        while let Some((record, next)) = self.read_record(cursor, buffer.as_mut())? {
+            if record.kind == RecordKind::Tombstone {
+                found
+                    .push(from_bytes(record.data)?)
+                    .or(Err(StorageError::OutOfBounds))?;
+            }
+            cursor = next;
+        }
+
+        Ok(found)
+    }
+
+    /// Copy the log in to the empty `target` leaving out what
+    /// tombstones have deleted.
+    ///
+    /// Each deleted envelope is replaced by a `Deleted` record with
+    /// the same counters so sequence lookups, and the sender state
+    /// rebuilt from the log, are unchanged. The tombstones themselves,
+    /// checkpoints, gaps and all but the latest membership snapshot
+    /// are dropped, a new checkpoint should be written once done.
+    pub fn compact_into<J: IO>(&self, target: &mut Storage<J>) -> Result<(), StorageError> {
+        if target.io.slab_count()? != 0 {
+            return Err(StorageError::OutOfOrder);
+        }
+
+        let tombstones = self.tombstones()?;
+        let membership = self.find_membership()?;
+        let mut membership_left = 0u16;
+        let mut buffer = I::Buffer::default();
+        let mut expanded = [0u8; COMPRESS_MAX];
+        let mut placeholder = [0u8; size_of::<Tombstone>() + 16];
+
+        let Some(mut cursor) = self.get_cursor_from_sequence(0)? else {
+            return Ok(());
+        };
+
+        loop {
+            if let Some(run) = &membership {
+                if run.starts_at(&cursor) {
+                    membership_left = run.parts();
+                }
+            }
+
+            let Some((record, next)) = self.read_record(cursor, buffer.as_mut())? else {
+                break;
+            };
+
+            match record.kind {
+                RecordKind::Data => {
+                    let (kind, data) = match tombstones.iter().find(|t| t.names(&record)) {
+                        Some(tombstone) => (
+                            RecordKind::Deleted,
+                            &*to_slice(tombstone, &mut placeholder)?,
+                        ),
+                        None => (RecordKind::Data, record.decompress(&mut expanded)?),
+                    };
+                    target.append_kind(
+                        kind,
+                        record.max_sequence,
+                        record.message_count,
+                        record.sequence,
+                        record.sender,
+                        data,
+                    )?;
+                }
+                RecordKind::Deleted => {
+                    target.append_kind(
+                        RecordKind::Deleted,
+                        record.max_sequence,
+                        record.message_count,
+                        record.sequence,
+                        record.sender,
+                        record.data,
+                    )?;
+                }
+                RecordKind::Membership if membership_left > 0 => {
+                    membership_left -= 1;
+                    let (max_sequence, max_message) = target.tail_counters()?;
+                    target.append_kind(
+                        RecordKind::Membership,
+                        max_sequence,
+                        max_message,
+                        0,
+                        record.sender,
+                        record.data,
+                    )?;
+                }
+                RecordKind::Membership
+                | RecordKind::Checkpoint
+                | RecordKind::Tombstone
+                | RecordKind::Gaps => {}
+            }
+
+            cursor = next;
+        }
+
+        Ok(())
+    }
+}
@@ -1,19 +1,97 @@
 use super::*;
 
-mod runner;
+pub(crate) mod runner;
 use runner::*;
 
+use crypto::ed25519::Ed25519Crypto;
 use crypto::rust::{test::get_test_keys, RustCrypto};
-use storage::mem_io::MemIO;
+use storage::fault_io::{Fault, FaultIO};
+use storage::file_io::{FileIO, FileMedia};
+use storage::mem_io::{MemIO, MemMedia};
+use storage::pool_io::{PooledIO, StoragePool};
+
+extern crate std;
+use std::boxed::Box;
 
 const MEGA_BYTE: usize = 1024 * 1024;
 const SLAB_SIZE: usize = 1024;
 const MAX_CHANNELS: usize = 4;
 const MAX_NODES: usize = 128;
 
+/// Channels for a test client. `ClientChannels` is large, building it
+/// in its own frame keeps the stack of tests with several clients
+/// from holding a copy for each one.
+#[inline(never)]
+fn new_channels<I: IO, C: Crypto>() -> Box<ClientChannels<MAX_CHANNELS, MAX_NODES, I, C>> {
+    Box::new(ClientChannels::new())
+}
+
 #[test]
 fn test_runner_simple() -> Result<(), ClientError> {
-    let mut runner = TestRunner::new();
+    let mut runner = TestRunner::<MemIO<'static, SLAB_SIZE>>::new();
+    runner.run("simple.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_simple_ed25519() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<MemIO<'static, SLAB_SIZE>, Ed25519Crypto>::new();
+    runner.run("simple.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_simple_file_io() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<FileIO<SLAB_SIZE>>::new();
+    runner.run("simple.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_simple_file_io_ed25519() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<FileIO<SLAB_SIZE>, Ed25519Crypto>::new();
+    runner.run("simple.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_quota() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<MemIO<'static, SLAB_SIZE>>::new();
+    runner.run("quota.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_quota_ed25519() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<MemIO<'static, SLAB_SIZE>, Ed25519Crypto>::new();
+    runner.run("quota.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_compression() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<MemIO<'static, SLAB_SIZE>>::new();
+    runner.run("compression.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_compression_ed25519() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<MemIO<'static, SLAB_SIZE>, Ed25519Crypto>::new();
+    runner.run("compression.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_simple_pool_io() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<PooledIO<'static, FileMedia, SLAB_SIZE, POOL_RANGES>>::new();
+    runner.run("simple.yaml")?;
+    Ok(())
+}
+
+#[test]
+fn test_runner_simple_pool_io_ed25519() -> Result<(), ClientError> {
+    let mut runner = TestRunner::<PooledIO<'static, FileMedia, SLAB_SIZE, POOL_RANGES>, Ed25519Crypto>::new();
     runner.run("simple.yaml")?;
     Ok(())
 }
@@ -46,6 +124,38 @@ fn test_init_chat() -> Result<(), ClientError> {
     Ok(())
 }
 
+// Same constants and startup sequence as the s3-dongle and t3-v1-6-1
+// firmwares, which must not panic at boot.
+#[test]
+fn test_firmware_boot() -> Result<(), ClientError> {
+    const SLAB_SIZE: usize = 1024;
+    const MEMIO_SLABS: usize = 8;
+    const MEMIO_SIZE: usize = storage::mem_io::buffer_size::<SLAB_SIZE>(MEMIO_SLABS);
+    const MAX_CHANNELS: usize = 2;
+    const MAX_NODES: usize = 2;
+
+    let seed = [0; 128];
+    let mut crypto = RustCrypto::new(&seed)?;
+    let key_pair = get_test_keys();
+    static BUFFER: StaticAllocation<[u8; MEMIO_SIZE]> = StaticAllocation::wrap([0u8; MEMIO_SIZE]);
+    let data = BUFFER.take_mut()?;
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(data)?;
+
+    static CHANNELS_CONST: StaticAllocation<
+        ClientChannels<MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, RustCrypto>,
+    > = StaticAllocation::wrap(ClientChannels::new());
+    let channels = CHANNELS_CONST.take_mut()?;
+
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, RustCrypto> =
+        Client::new(key_pair, &mut crypto, channels);
+
+    let channel_id = client.init_chat("Test Chat", io)?;
+    assert_eq!(client.list_nodes(&channel_id)?.len(), 1);
+    client.storage_stats(&channel_id)?;
+    client.send_message(&channel_id, "hello")?;
+    Ok(())
+}
+
 #[test]
 fn test_open_chat() -> Result<(), ClientError> {
     static SEED: [u8; 128] = [0u8; 128];
@@ -128,3 +238,1587 @@ fn test_send_message() -> Result<(), ClientError> {
 
     Ok(())
 }
+
+#[test]
+fn test_open_chat_file_io() -> Result<(), ClientError> {
+    open_chat_file_io::<RustCrypto>()
+}
+
+#[test]
+fn test_open_chat_file_io_ed25519() -> Result<(), ClientError> {
+    open_chat_file_io::<Ed25519Crypto>()
+}
+
+fn open_chat_file_io<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let path = std::env::temp_dir().join(std::format!(
+        "finder-open-chat-{}-{}.db",
+        C::NAME,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let message = "This one should survive a restart";
+
+    let channel_id = {
+        let io: FileIO<SLAB_SIZE> = FileIO::open(&path, 64)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, FileIO<SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Test Chat", io)?;
+        client.send_message(&channel_id, message)?;
+        channel_id
+    };
+
+    let io: FileIO<SLAB_SIZE> = FileIO::open(&path, 64)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, FileIO<SLAB_SIZE>, C> =
+        Client::new(key_pair, &mut crypto, &mut channels);
+
+    client.open_chat(channel_id, io)?;
+
+    assert_eq!(client.message_count(&channel_id)?, 1);
+    assert_eq!(client.get_message(&channel_id, 1)?.text, message);
+
+    std::fs::remove_file(&path).expect("could not remove test file");
+    Ok(())
+}
+
+#[test]
+fn test_open_chat_checkpoint() -> Result<(), ClientError> {
+    open_chat_checkpoint::<RustCrypto>()
+}
+
+#[test]
+fn test_open_chat_checkpoint_ed25519() -> Result<(), ClientError> {
+    open_chat_checkpoint::<Ed25519Crypto>()
+}
+
+fn open_chat_checkpoint<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let mut data = std::vec![0u8; MEGA_BYTE];
+    let count = CHECKPOINT_INTERVAL as u64 + 3;
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Test Chat", io)?;
+        for i in 0..count {
+            client.send_message(&channel_id, &std::format!("message {}", i))?;
+        }
+        channel_id
+    };
+
+    {
+        // An automatic checkpoint was written, only the records after
+        // it are replayed.
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut storage = Storage::new(io);
+        let mut cursor = storage.checkpoint()?
+            .expect("expected a checkpoint");
+        let mut after = 0;
+        let mut expanded = [0u8; COMPRESS_MAX];
+        while let Some((_, next)) = storage.read_into(cursor, &mut expanded)? {
+            after += 1;
+            cursor = next;
+        }
+        assert!(after < CHECKPOINT_INTERVAL);
+        let found = storage.checkpoint()?;
+
+        // A checkpoint torn off after its start is not used.
+        let start: CheckpointPart<C::PubSigningKey> = CheckpointPart::Start {
+            newest: NodeId::new(0),
+            owner: None,
+            confidential: false,
+            generation: 0,
+            message_count: 0,
+            nodes: 1,
+            users: 1,
+            removed: 0,
+            epoch: 0,
+            secrets: 0,
+        };
+        let mut target = [0u8; 128];
+        let bytes = to_slice(&start, target.as_mut_slice())?;
+        // The channel was created at sequence 1.
+        storage.append_checkpoint(count + 1, count, NodeId::new(0), bytes)?;
+        let torn = storage.checkpoint()?;
+        assert_eq!(torn, found);
+    }
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(key_pair, &mut crypto, &mut channels);
+
+    client.open_chat(channel_id, io)?;
+    assert_eq!(client.message_count(&channel_id)?, count);
+    assert_eq!(client.get_message(&channel_id, 1)?.text, "message 0");
+    assert_eq!(
+        client.get_message(&channel_id, count)?.text.as_str(),
+        std::format!("message {}", count - 1)
+    );
+
+    // The restored state carries on sending.
+    client.checkpoint(&channel_id)?;
+    client.send_message(&channel_id, "after the checkpoint")?;
+    assert_eq!(client.message_count(&channel_id)?, count + 1);
+    assert_eq!(
+        client.get_message(&channel_id, count + 1)?.text,
+        "after the checkpoint"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_pool_rebalance() -> Result<(), ClientError> {
+    pool_rebalance::<RustCrypto>()
+}
+
+#[test]
+fn test_pool_rebalance_ed25519() -> Result<(), ClientError> {
+    pool_rebalance::<Ed25519Crypto>()
+}
+
+fn pool_rebalance<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+
+    // Two table pages and 40 pages for ranges.
+    let mut buffer = std::vec![0u8; 42 * SLAB_SIZE];
+    let pool: StoragePool<MemMedia<'_>, SLAB_SIZE, 8> = StoragePool::new(MemMedia::new(&mut buffer))?;
+
+    let mut channels = new_channels();
+    let mut client: Client<
+        '_,
+        '_,
+        MAX_CHANNELS,
+        MAX_NODES,
+        PooledIO<'_, MemMedia<'_>, SLAB_SIZE, 8>,
+        C,
+    > = Client::new(key_pair, &mut crypto, &mut channels);
+
+    // The first channel takes most of the pool and fills its range,
+    // the second does not fit next to it.
+    let first = client.init_chat("First", pool.open(1, 27)?)?;
+    client.set_retention(&first, Retention::DropOldest)?;
+    let mut sent = 0;
+    while client.storage_stats(&first)?.slabs_free > 0 {
+        client.send_message(&first, &std::format!("message {}", sent))?;
+        sent += 1;
+    }
+    assert!(matches!(pool.open(2, 8), Err(StorageError::DbFull)));
+
+    let free = pool.free_slabs();
+    client.rebalance_storage(7)?;
+    assert!(pool.free_slabs() > free);
+    let second = client.init_chat("Second", pool.open(2, 8)?)?;
+    client.send_message(&second, "Room now")?;
+    assert_eq!(client.get_message(&second, 1)?.text, "Room now");
+
+    // The first channel kept its newest messages in the smaller range.
+    let count = client.message_count(&first)?;
+    assert_eq!(
+        client.get_message(&first, count)?.text.as_str(),
+        std::format!("message {}", sent - 1)
+    );
+    assert!(client.storage_stats(&first)?.slabs_used <= 7);
+    client.send_message(&first, "After the move")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_messages_range() -> Result<(), ClientError> {
+    messages_range::<RustCrypto>()
+}
+
+#[test]
+fn test_messages_range_ed25519() -> Result<(), ClientError> {
+    messages_range::<Ed25519Crypto>()
+}
+
+fn messages_range<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    const CONFIDENTIAL_SLAB: usize = 2 * SLAB_SIZE;
+
+    let mut data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut data)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+        Client::new(key_pair, &mut crypto, &mut channels);
+
+    // Adding a member and a rekey are stored between the messages.
+    let channel_id = client.init_confidential_chat("Test Chat", io)?;
+    for i in 1..=20 {
+        client.send_message(&channel_id, &std::format!("message {}", i))?;
+        if i == 5 {
+            client.add_node(&channel_id, member.public.clone(), "Member")?;
+        }
+        if i == 12 {
+            client.rekey(&channel_id)?;
+        }
+    }
+
+    let all = client
+        .messages(&channel_id, 0..100)?
+        .collect::<Result<std::vec::Vec<_>, _>>()?;
+    assert_eq!(all.len(), 20);
+    for (i, (index, message)) in all.iter().enumerate() {
+        assert_eq!(*index, i as u64 + 1);
+        assert_eq!(message.text.as_str(), std::format!("message {}", i + 1));
+    }
+
+    let page: std::vec::Vec<u64> = client
+        .messages(&channel_id, 5..8)?
+        .map(|found| found.map(|(index, _)| index))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(page, [5, 6, 7]);
+    assert_eq!(client.messages(&channel_id, 13..14)?.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_membership_survives_eviction() -> Result<(), ClientError> {
+    membership_survives_eviction::<RustCrypto>()
+}
+
+#[test]
+fn test_membership_survives_eviction_ed25519() -> Result<(), ClientError> {
+    membership_survives_eviction::<Ed25519Crypto>()
+}
+
+fn membership_survives_eviction<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let joiner = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+
+    let mut data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut snapshot = std::vec![0u8; MEMBERSHIP_MAX + MAX_SIG];
+    let mut exported = std::vec![0u8; 16 * SLAB_SIZE];
+    let count = 60;
+
+    let (channel_id, snapshot) = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Test Chat", io)?;
+        client.set_retention(&channel_id, Retention::DropOldest)?;
+        client.add_node(&channel_id, member.public.clone(), "Member")?;
+        for i in 0..count {
+            client.send_message(&channel_id, &std::format!("message {}", i))?;
+        }
+        // The record adding the member is long gone.
+        assert!(client.get_message(&channel_id, 1).is_err());
+
+        let len = client
+            .membership(&channel_id, &mut snapshot)?
+            .expect("expected a snapshot")
+            .len();
+        snapshot.truncate(len);
+
+        let len = client.export_channel(&channel_id, &mut exported)?.len();
+        exported.truncate(len);
+        (channel_id, snapshot)
+    };
+
+    {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        client.open_chat(channel_id, io)?;
+        assert_eq!(client.list_nodes(&channel_id)?.len(), 2);
+        assert_eq!(
+            client.get_message(&channel_id, count)?.text.as_str(),
+            std::format!("message {}", count - 1)
+        );
+    }
+
+    {
+        // The exported log starts after the evicted records, the
+        // membership snapshot vouches for the member's messages.
+        let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut crypto, &mut channels);
+
+        client.import_channel(key_pair.public.clone(), &exported, io)?;
+        assert_eq!(client.message_count(&channel_id)?, count);
+        client.send_message(&channel_id, "from the backup")?;
+        assert_eq!(
+            client.get_message(&channel_id, count + 1)?.text,
+            "from the backup"
+        );
+    }
+
+    // A node joining late learns the member from the snapshot alone.
+    let mut joiner_data = std::vec![0u8; 8 * SLAB_SIZE];
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut joiner_data)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(joiner, &mut crypto, &mut channels);
+    client.add_channel(key_pair.public.clone(), channel_id, io)?;
+
+    let mut tampered = snapshot.clone();
+    if let Some(byte) = tampered.get_mut(8) {
+        *byte ^= 1;
+    }
+    assert!(client.receive_membership(&channel_id, &tampered).is_err());
+    assert_eq!(client.list_nodes(&channel_id)?.len(), 1);
+
+    client.receive_membership(&channel_id, &snapshot)?;
+    let member_id = C::compute_id(&member.public);
+    assert!(client
+        .list_nodes(&channel_id)?
+        .iter()
+        .any(|node| node.node == member_id));
+
+    Ok(())
+}
+
+#[test]
+fn test_export_import_channel() -> Result<(), ClientError> {
+    export_import_channel::<RustCrypto>()
+}
+
+#[test]
+fn test_export_import_channel_ed25519() -> Result<(), ClientError> {
+    export_import_channel::<Ed25519Crypto>()
+}
+
+fn export_import_channel<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+
+    let mut data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut snapshot = std::vec![0u8; 8 * SLAB_SIZE];
+    let count = 5;
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Test Chat", io)?;
+        client.add_node(&channel_id, member.public.clone(), "Member")?;
+        for i in 0..count {
+            client.send_message(&channel_id, &std::format!("message {}", i))?;
+        }
+
+        let len = client.export_channel(&channel_id, &mut snapshot)?.len();
+        snapshot.truncate(len);
+        channel_id
+    };
+
+    // The same log with one record's signature broken, stored with
+    // good checksums.
+    let mut forged_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut forged = std::vec![0u8; 8 * SLAB_SIZE];
+    {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let source = Storage::new(io);
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut forged_data)?;
+        let mut target = Storage::new(io);
+
+        let mut cursor = source.get_cursor_from_sequence(0)?.expect("expected records");
+        let mut record = [0u8; SLAB_SIZE];
+        let mut buffer = [0u8; SLAB_SIZE];
+        while let Some((found, next)) = source.read_record(cursor, &mut buffer)? {
+            if found.kind() == RecordKind::Data {
+                let record = &mut record[..found.data().len()];
+                record.copy_from_slice(found.data());
+                if found.message_count() == count {
+                    if let Some(byte) = record.last_mut() {
+                        *byte ^= 1;
+                    }
+                }
+                target.append(
+                    found.max_sequence(),
+                    found.message_count(),
+                    found.sequence(),
+                    found.sender(),
+                    record,
+                )?;
+            }
+            cursor = next;
+        }
+        let len = target.export(channel_id, &mut forged)?.len();
+        forged.truncate(len);
+    }
+
+    let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut rejected_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut crypto, &mut channels);
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut rejected_data)?;
+    let result = client.import_channel(key_pair.public.clone(), &forged, io);
+    assert!(matches!(result, Err(ClientError::CryptoError(_))));
+    assert!(client.message_count(&channel_id).is_err());
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let imported = client.import_channel(key_pair.public.clone(), &snapshot, io)?;
+    assert_eq!(imported, channel_id);
+    assert_eq!(client.message_count(&channel_id)?, count);
+    assert_eq!(client.list_nodes(&channel_id)?.len(), 2);
+    assert_eq!(client.get_message(&channel_id, 1)?.text, "message 0");
+
+    // The member carries on from the imported log.
+    client.send_message(&channel_id, "after the import")?;
+    assert_eq!(
+        client.get_message(&channel_id, count + 1)?.text,
+        "after the import"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_open_chat_crash_points() -> Result<(), ClientError> {
+    open_chat_crash_points::<RustCrypto>()
+}
+
+#[test]
+fn test_open_chat_crash_points_ed25519() -> Result<(), ClientError> {
+    open_chat_crash_points::<Ed25519Crypto>()
+}
+
+fn open_chat_crash_points<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let mut base = std::vec![0u8; 16 * SLAB_SIZE];
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut base)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Test Chat", io)?;
+        client.send_message(&channel_id, "before")?;
+        channel_id
+    };
+    let before = 1;
+
+    // Send a message then checkpoint, stopping at the first error.
+    type Faulty<'m> = FaultIO<MemMedia<'m>, SLAB_SIZE>;
+    let run = |crypto: &mut C,
+               data: &mut [u8],
+               fault: Option<(usize, Fault)>|
+     -> Result<usize, ClientError> {
+        let io: Faulty<'_> = FaultIO::with_fault(MemMedia::new(data), fault)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, Faulty<'_>, C> =
+            Client::new(key_pair.clone(), crypto, &mut channels);
+
+        client.open_chat(channel_id, io)?;
+        client.send_message(&channel_id, "after")?;
+        client.checkpoint(&channel_id)?;
+
+        let channel = client.channels.get(&channel_id).ok_or(ClientError::Unreachable)?;
+        Ok(channel.storage.io().media().operations())
+    };
+
+    let mut data = base.clone();
+    let operations = run(&mut crypto, &mut data, None)?;
+    assert!(operations > 2);
+
+    for at in 0..operations {
+        for fault in [Fault::Fail, Fault::Tear(5), Fault::Drop] {
+            let mut data = base.clone();
+            let result = run(&mut crypto, &mut data, Some((at, fault)));
+            if fault == Fault::Fail {
+                assert!(result.is_err(), "{:?} at {}", fault, at);
+            }
+
+            // Reboot, the channel opens with or without the message
+            // and every message it has reads back.
+            let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+            let mut channels = new_channels();
+            let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+                Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+            client.open_chat(channel_id, io)?;
+            let count = client.message_count(&channel_id)?;
+            assert!(count == before || count == before + 1, "{:?} at {}", fault, at);
+            assert_eq!(client.get_message(&channel_id, 1)?.text, "before");
+            if count > before {
+                assert_eq!(client.get_message(&channel_id, count)?.text, "after");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_delete_local() -> Result<(), ClientError> {
+    delete_local::<RustCrypto>()
+}
+
+#[test]
+fn test_delete_local_ed25519() -> Result<(), ClientError> {
+    delete_local::<Ed25519Crypto>()
+}
+
+fn delete_local<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let mut data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut compacted = std::vec![0u8; 16 * SLAB_SIZE];
+    let secret = "this one goes away";
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(key_pair.clone(), &mut crypto, &mut channels);
+
+        let channel_id = client.init_chat("Test Chat", io)?;
+        client.send_message(&channel_id, "first")?;
+        client.send_message(&channel_id, secret)?;
+        client.send_message(&channel_id, "third")?;
+
+        client.delete_local(&channel_id, 2)?;
+        assert!(matches!(
+            client.get_message(&channel_id, 2),
+            Err(ClientError::MessageDeleted)
+        ));
+        // Deleting twice is harmless.
+        client.delete_local(&channel_id, 2)?;
+        assert_eq!(client.get_message(&channel_id, 3)?.text, "third");
+        assert_eq!(client.message_count(&channel_id)?, 3);
+        let left: std::vec::Vec<u64> = client
+            .messages(&channel_id, 1..4)?
+            .map(|found| found.map(|(index, _)| index))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(left, [1, 3]);
+
+        // Peers are no longer sent it.
+        let request = SyncRequest::<MAX_NODES> {
+            session_id: 0,
+            bytes_budget: 4096,
+            vector_clock: heapless::Vec::new(),
+        };
+        let mut state = SyncResponderState::new(&request);
+        client.start_sync_response(&channel_id, &mut state, &request)?;
+        let mut buffer = [0u8; 4096];
+        let (count, len) = client.fill_send_buffer(&channel_id, &mut state, &mut buffer)?;
+        assert_eq!(count, 3);
+        assert!(buffer[..len]
+            .windows(secret.len())
+            .all(|window| window != secret.as_bytes()));
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut compacted)?;
+        client.compact(&channel_id, io)?;
+        assert!(matches!(
+            client.get_message(&channel_id, 2),
+            Err(ClientError::MessageDeleted)
+        ));
+        client.send_message(&channel_id, "fourth")?;
+        channel_id
+    };
+
+    assert!(data.windows(secret.len()).any(|window| window == secret.as_bytes()));
+    assert!(compacted
+        .windows(secret.len())
+        .all(|window| window != secret.as_bytes()));
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut compacted)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(key_pair, &mut crypto, &mut channels);
+
+    client.open_chat(channel_id, io)?;
+    assert_eq!(client.message_count(&channel_id)?, 4);
+    assert_eq!(client.get_message(&channel_id, 1)?.text, "first");
+    assert!(matches!(
+        client.get_message(&channel_id, 2),
+        Err(ClientError::MessageDeleted)
+    ));
+    assert_eq!(client.get_message(&channel_id, 4)?.text, "fourth");
+    assert_eq!(client.messages(&channel_id, 1..5)?.count(), 3);
+
+    client.send_message(&channel_id, "fifth")?;
+    assert_eq!(client.message_count(&channel_id)?, 5);
+
+    Ok(())
+}
+
+#[test]
+fn test_delete_local_limit() -> Result<(), ClientError> {
+    delete_local_limit::<RustCrypto>()
+}
+
+#[test]
+fn test_delete_local_limit_ed25519() -> Result<(), ClientError> {
+    delete_local_limit::<Ed25519Crypto>()
+}
+
+fn delete_local_limit<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut crypto = C::new(&seed)?;
+    let key_pair = C::test_keys();
+    let mut data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut compacted = std::vec![0u8; 16 * SLAB_SIZE];
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut data)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(key_pair, &mut crypto, &mut channels);
+
+    let channel_id = client.init_chat("Test Chat", io)?;
+    let count = MAX_TOMBSTONES as u64 + 2;
+    for i in 1..=count {
+        client.send_message(&channel_id, &std::format!("message {}", i))?;
+    }
+
+    for index in 1..=MAX_TOMBSTONES as u64 {
+        client.delete_local(&channel_id, index)?;
+    }
+    assert!(matches!(
+        client.delete_local(&channel_id, count - 1),
+        Err(ClientError::CompactionNeeded)
+    ));
+    assert_eq!(
+        client.get_message(&channel_id, count - 1)?.text.as_str(),
+        std::format!("message {}", count - 1)
+    );
+    let left: std::vec::Vec<u64> = client
+        .messages(&channel_id, 0..100)?
+        .map(|found| found.map(|(index, _)| index))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(left, [count - 1, count]);
+
+    // Compacting makes room for more.
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut compacted)?;
+    client.compact(&channel_id, io)?;
+    client.delete_local(&channel_id, count - 1)?;
+    let left: std::vec::Vec<u64> = client
+        .messages(&channel_id, 0..100)?
+        .map(|found| found.map(|(index, _)| index))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(left, [count]);
+
+    Ok(())
+}
+
+/// One round of sync from `responder` to `requester`, returning the
+/// envelopes sent.
+fn sync_once<I: IO, C: Crypto>(
+    channel_id: &ChannelId,
+    requester: &mut Client<'_, '_, MAX_CHANNELS, MAX_NODES, I, C>,
+    responder: &Client<'_, '_, MAX_CHANNELS, MAX_NODES, I, C>,
+) -> Result<u32, ClientError> {
+    let mut request = SyncRequest::<MAX_NODES> {
+        session_id: 0,
+        bytes_budget: 4096,
+        vector_clock: heapless::Vec::new(),
+    };
+    requester.finish_sync_request(channel_id, &mut request)?;
+
+    let mut state = SyncResponderState::new(&request);
+    responder.start_sync_response(channel_id, &mut state, &request)?;
+    let mut buffer = std::vec![0u8; 16 * SLAB_SIZE];
+    let (count, len) = responder.fill_send_buffer(channel_id, &mut state, &mut buffer)?;
+
+    requester.receive_buffer(channel_id, &buffer[..len], count)?;
+    Ok(count)
+}
+
+#[test]
+fn test_quota() -> Result<(), ClientError> {
+    quota::<RustCrypto>()
+}
+
+#[test]
+fn test_quota_ed25519() -> Result<(), ClientError> {
+    quota::<Ed25519Crypto>()
+}
+
+fn quota<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let relay = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+    let quota = Quota {
+        window: 8,
+        max_records: 2,
+        max_bytes: 65536,
+    };
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_chat("Test Chat", io)?;
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+        member_client.set_quota(&channel_id, Some(quota))?;
+
+        // Running the channel does not use up the owner's quota.
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.add_node(&channel_id, relay.public.clone(), "Relay")?;
+        owner_client.send_message(&channel_id, "from the owner")?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        assert_eq!(member_client.list_nodes(&channel_id)?.len(), 3);
+        assert_eq!(member_client.message_count(&channel_id)?, 1);
+
+        // What is over quota is left for later without failing the sync.
+        for i in 1..=3 {
+            owner_client.send_message(&channel_id, &std::format!("flood {}", i))?;
+        }
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        assert_eq!(member_client.message_count(&channel_id)?, 2);
+        assert!(member_client.gaps(&channel_id)?.is_empty());
+        // A member's log only replays from a checkpoint.
+        member_client.checkpoint(&channel_id)?;
+        channel_id
+    };
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+    let mut channels = new_channels();
+    let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(owner, &mut owner_crypto, &mut channels);
+    owner_client.open_chat(channel_id, io)?;
+
+    // Opened again, what the owner used is read back from the log.
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let mut channels = new_channels();
+    let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut member_crypto, &mut channels);
+    member_client.open_chat(channel_id, io)?;
+    member_client.set_quota(&channel_id, Some(quota))?;
+    sync_once(&channel_id, &mut member_client, &owner_client)?;
+    assert_eq!(member_client.message_count(&channel_id)?, 2);
+    assert!(member_client.gaps(&channel_id)?.is_empty());
+
+    // Once the window moves on the rest is taken.
+    for i in 0..8 {
+        member_client.send_message(&channel_id, &std::format!("member {}", i))?;
+    }
+    sync_once(&channel_id, &mut member_client, &owner_client)?;
+    assert_eq!(member_client.message_count(&channel_id)?, 12);
+    assert_eq!(member_client.get_message(&channel_id, 12)?.text, "flood 3");
+
+    Ok(())
+}
+
+#[test]
+fn test_quota_compressed() -> Result<(), ClientError> {
+    quota_compressed::<RustCrypto>()
+}
+
+#[test]
+fn test_quota_compressed_ed25519() -> Result<(), ClientError> {
+    quota_compressed::<Ed25519Crypto>()
+}
+
+fn quota_compressed<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let text: std::string::String = core::iter::repeat_n('a', 400).collect();
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+
+    let (channel_id, quota) = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_chat("Test Chat", io)?;
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.send_message(&channel_id, &text)?;
+
+        // The owner stores its envelopes as sent.
+        let storage = &owner_client.channels.get(&channel_id).unwrap().storage;
+        let cursor = storage.last_cursor()?.expect("expected a record");
+        let sent = storage.read(cursor)?.expect("expected a record").0.len();
+        // Two envelopes as sent are over quota, compressed they are not.
+        let quota = Quota {
+            window: 16,
+            max_records: 16,
+            max_bytes: (2 * sent - 1) as u32,
+        };
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+        member_client.set_compression(&channel_id, true)?;
+        member_client.set_quota(&channel_id, Some(quota))?;
+
+        owner_client.send_message(&channel_id, &text)?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        assert_eq!(member_client.message_count(&channel_id)?, 2);
+        // A member's log only replays from a checkpoint.
+        member_client.checkpoint(&channel_id)?;
+
+        (channel_id, quota)
+    };
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+    let mut channels = new_channels();
+    let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(owner, &mut owner_crypto, &mut channels);
+    owner_client.open_chat(channel_id, io)?;
+
+    // Counted again from the log, the same sizes are charged.
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let mut channels = new_channels();
+    let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut member_crypto, &mut channels);
+    member_client.open_chat(channel_id, io)?;
+    member_client.set_compression(&channel_id, true)?;
+    member_client.set_quota(&channel_id, Some(quota))?;
+    owner_client.send_message(&channel_id, &text)?;
+    sync_once(&channel_id, &mut member_client, &owner_client)?;
+    assert_eq!(member_client.message_count(&channel_id)?, 3);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_gaps() -> Result<(), ClientError> {
+    sync_gaps::<RustCrypto>()
+}
+
+#[test]
+fn test_sync_gaps_ed25519() -> Result<(), ClientError> {
+    sync_gaps::<Ed25519Crypto>()
+}
+
+fn sync_gaps<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let owner_id = C::compute_id(&owner.public);
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut member_data = std::vec![0u8; 64 * SLAB_SIZE];
+    let count = 60;
+
+    let (channel_id, found) = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+
+        let channel_id = owner_client.init_chat("Test Chat", io)?;
+        owner_client.set_retention(&channel_id, Retention::DropOldest)?;
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.send_message(&channel_id, "before")?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        assert!(member_client.gaps(&channel_id)?.is_empty());
+        // A member's log only replays from a checkpoint.
+        member_client.checkpoint(&channel_id)?;
+        let before = member_client.message_count(&channel_id)?;
+        let have = owner_client.list_nodes(&channel_id)?.iter().map(|node| node.sequence).max();
+
+        // The owner drops most of these before the member syncs again.
+        for i in 0..count {
+            owner_client.send_message(&channel_id, &std::format!("message {}", i))?;
+        }
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+
+        let gaps = member_client.gaps(&channel_id)?;
+        assert_eq!(gaps.len(), 1);
+        let gap = gaps[0];
+        assert_eq!(gap.node, owner_id);
+        assert_eq!(Some(gap.after), have);
+        assert_eq!(gap.index, before);
+        assert!(gap.until > gap.after);
+
+        // What came after the gap was taken.
+        let received = member_client.message_count(&channel_id)?;
+        assert!(received > before);
+        assert_eq!(
+            member_client.get_message(&channel_id, received)?.text.as_str(),
+            std::format!("message {}", count - 1)
+        );
+        member_client.send_message(&channel_id, "after")?;
+        sync_once(&channel_id, &mut owner_client, &member_client)?;
+        assert!(owner_client.gaps(&channel_id)?.is_empty());
+
+        (channel_id, gap)
+    };
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut member_crypto, &mut channels);
+    client.open_chat(channel_id, io)?;
+    assert_eq!(client.gaps(&channel_id)?, &[found]);
+
+    Ok(())
+}
+
+#[test]
+fn test_fill_gaps_out_of_order() -> Result<(), ClientError> {
+    fill_gaps_out_of_order::<RustCrypto>()
+}
+
+#[test]
+fn test_fill_gaps_out_of_order_ed25519() -> Result<(), ClientError> {
+    fill_gaps_out_of_order::<Ed25519Crypto>()
+}
+
+fn fill_gaps_out_of_order<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let owner_id = C::compute_id(&owner.public);
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+
+    let (channel_id, expected) = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_chat("Test Chat", io)?;
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.send_message(&channel_id, "before")?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        member_client.checkpoint(&channel_id)?;
+        let have = member_client
+            .list_nodes(&channel_id)?
+            .iter()
+            .find(|node| node.node == owner_id)
+            .map(|node| node.sequence)
+            .unwrap();
+
+        // A gap as merging leaves it, reaching back past what has since
+        // arrived from the owner and on past what it sends next.
+        let gap = Gap {
+            node: owner_id,
+            after: 0,
+            until: have + 5,
+            index: 1,
+        };
+        member_client
+            .channels
+            .get_mut(&channel_id)
+            .unwrap()
+            .add_gaps(member_client.node_id, &[gap])?;
+
+        // What the owner sent up to `have` is still missing, only what
+        // came after is known.
+        owner_client.send_message(&channel_id, "one")?;
+        owner_client.send_message(&channel_id, "two")?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        let expected = [
+            Gap {
+                until: have,
+                ..gap
+            },
+            Gap {
+                after: have + 2,
+                ..gap
+            },
+        ];
+        assert_eq!(member_client.gaps(&channel_id)?, &expected);
+
+        (channel_id, expected)
+    };
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let mut channels = new_channels();
+    let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut member_crypto, &mut channels);
+    client.open_chat(channel_id, io)?;
+    assert_eq!(client.gaps(&channel_id)?, &expected);
+
+    Ok(())
+}
+
+#[test]
+fn test_confidential_chat() -> Result<(), ClientError> {
+    confidential_chat::<RustCrypto>()
+}
+
+#[test]
+fn test_confidential_chat_ed25519() -> Result<(), ClientError> {
+    confidential_chat::<Ed25519Crypto>()
+}
+
+fn confidential_chat<C: TestCrypto>() -> Result<(), ClientError> {
+    // With RSA an envelope carrying a wrapped secret is close to 1024
+    // bytes on its own.
+    const CONFIDENTIAL_SLAB: usize = 2 * SLAB_SIZE;
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let mut relay_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let relay = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+
+    let mut owner_data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let mut member_data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let mut relay_data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let early = "sent before the member joined";
+    let late = "sent after the member joined";
+
+    let channel_id = {
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_confidential_chat("Test Chat", io)?;
+        owner_client.send_message(&channel_id, early)?;
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.send_message(&channel_id, late)?;
+        assert_eq!(owner_client.get_message(&channel_id, 1)?.text, early);
+
+        // The member is sent the early message before its secret.
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        assert_eq!(member_client.get_message(&channel_id, 1)?.text, early);
+        assert_eq!(member_client.get_message(&channel_id, 2)?.text, late);
+
+        member_client.send_message(&channel_id, "reply")?;
+        sync_once(&channel_id, &mut owner_client, &member_client)?;
+        assert_eq!(owner_client.get_message(&channel_id, 3)?.text, "reply");
+        assert_eq!(member_client.get_message(&channel_id, 3)?.text, "reply");
+        member_client.checkpoint(&channel_id)?;
+
+        // A node holding the channel without being added passes it on
+        // but cannot read it.
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut relay_data)?;
+        let mut channels = new_channels();
+        let mut relay_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(relay, &mut relay_crypto, &mut channels);
+        relay_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut relay_client, &member_client)?;
+        assert_eq!(relay_client.message_count(&channel_id)?, 3);
+        assert!(matches!(
+            relay_client.get_message(&channel_id, 1),
+            Err(ClientError::NoChannelSecret)
+        ));
+
+        // A member that has lost the secret can't send rather than
+        // sending in the clear.
+        member_client.channels.get_mut(&channel_id).unwrap().secrets.clear();
+        assert!(matches!(
+            member_client.send_message(&channel_id, "in the clear"),
+            Err(ClientError::NoChannelSecret)
+        ));
+
+        // A member's chat message that was not encrypted is refused.
+        let plain: Protocol<C::PubSigningKey> = Protocol::ChatMessage(ChatMessage {
+            text: heapless::String::try_from("in the clear").unwrap(),
+        });
+        let member_id = C::compute_id(&member.public);
+        let channel = owner_client.channels.get_mut(&channel_id).unwrap();
+        let plain = channel.state.address(member_id, plain)?;
+        assert!(matches!(
+            channel.chat.accept_message(channel_id, member_id, &plain),
+            Err(ChatError::Unencrypted)
+        ));
+
+        channel_id
+    };
+
+    for data in [&owner_data, &member_data, &relay_data] {
+        for text in [early, late] {
+            assert!(data.windows(text.len()).all(|window| window != text.as_bytes()));
+        }
+    }
+
+    // The owner's secret comes back from the channel's first record
+    // and the member's from its checkpoint.
+    for (key_pair, data) in [(owner, &mut owner_data), (member, &mut member_data)] {
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(key_pair, &mut owner_crypto, &mut channels);
+        client.open_chat(channel_id, io)?;
+        assert_eq!(client.get_message(&channel_id, 2)?.text, late);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_direct_message() -> Result<(), ClientError> {
+    direct_message::<RustCrypto>()
+}
+
+#[test]
+fn test_direct_message_ed25519() -> Result<(), ClientError> {
+    direct_message::<Ed25519Crypto>()
+}
+
+fn direct_message<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let mut relay_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let relay = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+    let owner_id = C::compute_id(&owner.public);
+    let member_id = C::compute_id(&member.public);
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut relay_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let secret = "for the member only";
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_chat("Test Chat", io)?;
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.add_node(&channel_id, relay.public.clone(), "Relay")?;
+        owner_client.send_direct(&channel_id, member_id, secret)?;
+        owner_client.send_message(&channel_id, "to everyone")?;
+        // The sender can read back what it sent.
+        assert_eq!(owner_client.get_message(&channel_id, 1)?.text, secret);
+
+        // The member only hears of it through the relay.
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut relay_data)?;
+        let mut channels = new_channels();
+        let mut relay_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(relay, &mut relay_crypto, &mut channels);
+        relay_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut relay_client, &owner_client)?;
+        assert!(matches!(
+            relay_client.get_message(&channel_id, 1),
+            Err(ClientError::NotRecipient)
+        ));
+        assert_eq!(relay_client.get_message(&channel_id, 2)?.text, "to everyone");
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut member_client, &relay_client)?;
+        assert_eq!(member_client.get_message(&channel_id, 1)?.text, secret);
+
+        member_client.send_direct(&channel_id, owner_id, "reply")?;
+        sync_once(&channel_id, &mut owner_client, &member_client)?;
+        assert_eq!(owner_client.get_message(&channel_id, 3)?.text, "reply");
+        member_client.checkpoint(&channel_id)?;
+
+        channel_id
+    };
+
+    for data in [&owner_data, &relay_data] {
+        assert!(data.windows(secret.len()).all(|window| window != secret.as_bytes()));
+    }
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let mut channels = new_channels();
+    let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut member_crypto, &mut channels);
+    member_client.open_chat(channel_id, io)?;
+    assert_eq!(member_client.get_message(&channel_id, 1)?.text, secret);
+    assert_eq!(member_client.get_message(&channel_id, 3)?.text, "reply");
+
+    Ok(())
+}
+
+#[test]
+fn test_rekey() -> Result<(), ClientError> {
+    rekey::<RustCrypto>()
+}
+
+#[test]
+fn test_rekey_ed25519() -> Result<(), ClientError> {
+    rekey::<Ed25519Crypto>()
+}
+
+fn rekey<C: TestCrypto>() -> Result<(), ClientError> {
+    // With RSA an envelope carrying a wrapped secret is close to 1024
+    // bytes on its own.
+    const CONFIDENTIAL_SLAB: usize = 2 * SLAB_SIZE;
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let mut removed_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let removed = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+    let removed_id = C::compute_id(&removed.public);
+
+    let mut owner_data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let mut member_data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let mut removed_data = std::vec![0u8; 16 * CONFIDENTIAL_SLAB];
+    let before = "sent before the rekey";
+    let after = "sent after the rekey";
+
+    let channel_id = {
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_confidential_chat("Test Chat", io)?;
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.add_node(&channel_id, removed.public.clone(), "Removed")?;
+        owner_client.send_message(&channel_id, before)?;
+        owner_client.remove_node(&channel_id, &removed_id)?;
+        owner_client.send_message(&channel_id, after)?;
+        assert_eq!(owner_client.get_message(&channel_id, 1)?.text, before);
+        assert_eq!(owner_client.get_message(&channel_id, 2)?.text, after);
+
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        assert_eq!(member_client.get_message(&channel_id, 1)?.text, before);
+        assert_eq!(member_client.get_message(&channel_id, 2)?.text, after);
+        member_client.send_message(&channel_id, "reply")?;
+        member_client.checkpoint(&channel_id)?;
+
+        // The removed member keeps what it could read but is locked
+        // out of what came after.
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(&mut removed_data)?;
+        let mut channels = new_channels();
+        let mut removed_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(removed, &mut removed_crypto, &mut channels);
+        removed_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut removed_client, &member_client)?;
+        assert_eq!(removed_client.message_count(&channel_id)?, 3);
+        assert_eq!(removed_client.get_message(&channel_id, 1)?.text, before);
+        for index in [2, 3] {
+            assert!(matches!(
+                removed_client.get_message(&channel_id, index),
+                Err(ClientError::NoChannelSecret)
+            ));
+        }
+        assert!(matches!(
+            removed_client.send_message(&channel_id, "still here"),
+            Err(ClientError::NoChannelSecret)
+        ));
+
+        // Only the owner can rekey.
+        assert!(matches!(
+            member_client.rekey(&channel_id),
+            Err(ClientError::ChatError(ChatError::Unauthorized))
+        ));
+
+        channel_id
+    };
+
+    // Every epoch's secret comes back, the owner's from replaying its
+    // log and the member's from its checkpoint.
+    for (key_pair, data) in [(owner, &mut owner_data), (member, &mut member_data)] {
+        let io: MemIO<'_, CONFIDENTIAL_SLAB> = MemIO::new(data)?;
+        let mut channels = new_channels();
+        let mut client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, CONFIDENTIAL_SLAB>, C> =
+            Client::new(key_pair, &mut owner_crypto, &mut channels);
+        client.open_chat(channel_id, io)?;
+        assert_eq!(client.get_message(&channel_id, 1)?.text, before);
+        assert_eq!(client.get_message(&channel_id, 2)?.text, after);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_remove_node() -> Result<(), ClientError> {
+    remove_node::<RustCrypto>()
+}
+
+#[test]
+fn test_remove_node_ed25519() -> Result<(), ClientError> {
+    remove_node::<Ed25519Crypto>()
+}
+
+fn remove_node<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut member_crypto = C::new(&seed)?;
+    let mut removed_crypto = C::new(&seed)?;
+    let mut stale_crypto = C::new(&seed)?;
+    let mut late_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let member = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let removed = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+    let owner_id = C::compute_id(&owner.public);
+    let removed_id = C::compute_id(&removed.public);
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut member_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut removed_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut stale_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut late_data = std::vec![0u8; 16 * SLAB_SIZE];
+
+    let channel_id = {
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+        let mut channels = new_channels();
+        let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+        let channel_id = owner_client.init_chat("Test Chat", io)?;
+        owner_client.add_node(&channel_id, member.public.clone(), "Member")?;
+        owner_client.add_node(&channel_id, removed.public.clone(), "Removed")?;
+
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut removed_data)?;
+        let mut channels = new_channels();
+        let mut removed_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(removed.clone(), &mut removed_crypto, &mut channels);
+        removed_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut removed_client, &owner_client)?;
+        removed_client.send_message(&channel_id, "before")?;
+
+        // A second device of the removed node that never hears of it.
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut stale_data)?;
+        let mut channels = new_channels();
+        let mut stale_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(removed.clone(), &mut stale_crypto, &mut channels);
+        stale_client.add_channel(owner.public.clone(), channel_id, io)?;
+        sync_once(&channel_id, &mut stale_client, &removed_client)?;
+        let mut stale = [0u8; MEMBERSHIP_MAX + MAX_SIG];
+        let stale = owner_client
+            .membership(&channel_id, &mut stale)?
+            .ok_or(ClientError::Unreachable)?;
+
+        owner_client.send_message(&channel_id, "hello")?;
+        owner_client.remove_node(&channel_id, &removed_id)?;
+        assert!(matches!(
+            owner_client.remove_node(&channel_id, &owner_id),
+            Err(ClientError::ChatError(ChatError::Unauthorized))
+        ));
+
+        // What the removed node sent before it knew is refused without
+        // failing the sync. The member keeps a snapshot from before the
+        // removal, which must not add it back.
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+        let mut channels = new_channels();
+        let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut member_crypto, &mut channels);
+        member_client.add_channel(owner.public.clone(), channel_id, io)?;
+        member_client.receive_membership(&channel_id, stale)?;
+        sync_once(&channel_id, &mut member_client, &owner_client)?;
+        sync_once(&channel_id, &mut member_client, &removed_client)?;
+        assert_eq!(member_client.message_count(&channel_id)?, 1);
+        assert!(matches!(
+            member_client.remove_node(&channel_id, &owner_id),
+            Err(ClientError::ChatError(ChatError::Unauthorized))
+        ));
+        member_client.checkpoint(&channel_id)?;
+        stale_client.send_message(&channel_id, "stale")?;
+        stale_client.checkpoint(&channel_id)?;
+
+        // A peer that missed the removal and only has the owner's newer
+        // snapshot refuses the older one.
+        let mut fresh = [0u8; MEMBERSHIP_MAX + MAX_SIG];
+        let fresh = owner_client
+            .membership(&channel_id, &mut fresh)?
+            .ok_or(ClientError::Unreachable)?;
+        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut late_data)?;
+        let mut channels = new_channels();
+        let mut late_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+            Client::new(member.clone(), &mut late_crypto, &mut channels);
+        late_client.add_channel(owner.public.clone(), channel_id, io)?;
+        late_client.receive_membership(&channel_id, fresh)?;
+        assert!(matches!(
+            late_client.receive_membership(&channel_id, stale),
+            Err(ClientError::StaleMembership)
+        ));
+        let chat = &mut late_client.channels.get_mut(&channel_id).unwrap().chat;
+        assert!(chat.is_removed(&removed_id));
+        assert!(chat.users().all(|key| C::compute_id(key) != removed_id));
+
+        // Past `MAX_REMOVED` removals the oldest is forgotten.
+        for i in 0..=MAX_REMOVED as u8 {
+            chat.restore_removed(NodeId::new([i; 32]));
+        }
+        assert_eq!(chat.removed().len(), MAX_REMOVED);
+        assert!(!chat.is_removed(&NodeId::new([0; 32])));
+
+        // Once it has heard, the removed node can't send at all.
+        sync_once(&channel_id, &mut removed_client, &owner_client)?;
+        assert!(matches!(
+            removed_client.send_message(&channel_id, "after"),
+            Err(ClientError::ChatError(ChatError::Unauthorized))
+        ));
+
+        channel_id
+    };
+
+    // The removal comes back when the member reopens, what the
+    // removed node goes on sending is still refused.
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut member_data)?;
+    let mut channels = new_channels();
+    let mut member_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(member, &mut member_crypto, &mut channels);
+    member_client.open_chat(channel_id, io)?;
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut stale_data)?;
+    let mut channels = new_channels();
+    let mut stale_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(removed, &mut stale_crypto, &mut channels);
+    stale_client.open_chat(channel_id, io)?;
+    assert_eq!(stale_client.message_count(&channel_id)?, 2);
+
+    sync_once(&channel_id, &mut member_client, &stale_client)?;
+    assert_eq!(member_client.message_count(&channel_id)?, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_onboarding() -> Result<(), ClientError> {
+    onboarding::<RustCrypto>()
+}
+
+#[test]
+fn test_onboarding_ed25519() -> Result<(), ClientError> {
+    onboarding::<Ed25519Crypto>()
+}
+
+fn onboarding<C: TestCrypto>() -> Result<(), ClientError> {
+    let seed = [0; 128];
+    let mut owner_crypto = C::new(&seed)?;
+    let mut joiner_crypto = C::new(&seed)?;
+    let mut guesser_crypto = C::new(&seed)?;
+    let owner = C::test_keys();
+    let joiner = C::keys_from_pem(std::fs::read_to_string("src/test/key2.rsa").unwrap());
+    let guesser = C::keys_from_pem(std::fs::read_to_string("src/test/key3.rsa").unwrap());
+    let joiner_id = C::compute_id(&joiner.public);
+
+    let mut owner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut joiner_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let mut elsewhere_data = std::vec![0u8; 16 * SLAB_SIZE];
+    let password = b"correct horse";
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut owner_data)?;
+    let mut channels = new_channels();
+    let mut owner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(owner.clone(), &mut owner_crypto, &mut channels);
+    let channel_id = owner_client.init_chat("Test Chat", io)?;
+    owner_client.send_message(&channel_id, "welcome")?;
+    let (mut inviter, invite) = owner_client.invite(&channel_id, password)?;
+
+    // Answers made without the password are turned away.
+    let mut channels = new_channels();
+    let mut guesser_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(guesser, &mut guesser_crypto, &mut channels);
+    let (_, guess) = guesser_client.join(&invite, b"wrong horse", "Guesser")?;
+    assert!(matches!(
+        owner_client.admit(&mut inviter, &guess),
+        Err(ClientError::OnboardingError(OnboardingError::BadTag))
+    ));
+
+    // And use up the invite, so the password can't be guessed again.
+    let mut channels = new_channels();
+    let mut joiner_client: Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, C> =
+        Client::new(joiner.clone(), &mut joiner_crypto, &mut channels);
+    let (_, join) = joiner_client.join(&invite, password, "Joiner")?;
+    assert!(matches!(
+        owner_client.admit(&mut inviter, &join),
+        Err(ClientError::OnboardingError(OnboardingError::Spent))
+    ));
+
+    // Nor can the key in an answer be swapped.
+    let (mut inviter, invite) = owner_client.invite(&channel_id, password)?;
+    let (_, join) = joiner_client.join(&invite, password, "Joiner")?;
+    let mut forged = join.clone();
+    forged.key = owner.public.clone();
+    assert!(matches!(
+        owner_client.admit(&mut inviter, &forged),
+        Err(ClientError::OnboardingError(OnboardingError::BadTag))
+    ));
+    assert_eq!(owner_client.list_nodes(&channel_id)?.len(), 1);
+
+    let (mut inviter, invite) = owner_client.invite(&channel_id, password)?;
+    let (joining, join) = joiner_client.join(&invite, password, "Joiner")?;
+    let confirm = owner_client.admit(&mut inviter, &join)?;
+    assert!(owner_client
+        .list_nodes(&channel_id)?
+        .iter()
+        .any(|node| node.node == joiner_id));
+    assert!(matches!(
+        owner_client.admit(&mut inviter, &join),
+        Err(ClientError::OnboardingError(OnboardingError::Spent))
+    ));
+
+    // Or the channel the joiner is sent to.
+    let mut redirected = confirm.clone();
+    redirected.channel_id = C::new(&seed)?.channel_id_from_bytes(b"elsewhere");
+    assert!(matches!(
+        joiner_client.finish_join(&joining, &redirected, MemIO::new(&mut elsewhere_data)?),
+        Err(ClientError::OnboardingError(OnboardingError::BadTag))
+    ));
+
+    let io: MemIO<'_, SLAB_SIZE> = MemIO::new(&mut joiner_data)?;
+    assert_eq!(joiner_client.finish_join(&joining, &confirm, io)?, channel_id);
+    sync_once(&channel_id, &mut joiner_client, &owner_client)?;
+    assert_eq!(joiner_client.get_message(&channel_id, 1)?.text, "welcome");
+    joiner_client.send_message(&channel_id, "thanks")?;
+
+    Ok(())
+}
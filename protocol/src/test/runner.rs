@@ -5,6 +5,7 @@ use std::collections::HashMap;
 use std::format;
 use std::fs::read_to_string;
 use std::string::String;
+use std::vec;
 use std::vec::Vec;
 
 use serde_yaml;
@@ -14,16 +15,23 @@ use rsa::RsaPrivateKey;
 use rsa::RsaPublicKey;
 
 use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
 
 use super::*;
+use crate::crypto::ed25519::{self, Ed25519Crypto};
+use crate::crypto::rust::{self, RustCrypto};
 use crate::crypto::ChannelId;
+use crate::storage::file_io::{FileIO, FileMedia};
+use crate::storage::pool_io::{PooledIO, StoragePool};
 
 const MEGA_BYTE: usize = 1024 * 1024;
 const SLAB_SIZE: usize = 1024;
 const MAX_CHANNELS: usize = 4;
 const MAX_NODES: usize = 128;
 const RESPONSE_MAX: usize = 4096;
-
+pub const POOL_SLABS: usize = 256;
+pub const POOL_RANGES: usize = 16;
 
 #[derive(Debug, Deserialize)]
 enum TestCommands {
@@ -55,24 +63,137 @@ enum TestCommands {
         from: u64,
         count: u64,
     },
+    SetQuota {
+        channel: u64,
+        client: u64,
+        window: u32,
+        max_records: u32,
+        max_bytes: u32,
+    },
+    SetCompression {
+        channel: u64,
+        client: u64,
+        enabled: bool,
+    },
+}
+
+/// Storage the runner hands to each channel it creates.
+pub trait TestIO: IO + Sized + 'static {
+    fn create() -> Result<Self, ClientError>;
+}
+
+impl TestIO for MemIO<'static, SLAB_SIZE> {
+    fn create() -> Result<Self, ClientError> {
+        // This is a dance to allocate the buffer on the heap
+        // instead of allocating on the stack and moving to the heap
+        let vec_data = vec![0u8; MEGA_BYTE];
+        let boxed_data: Box<[u8; MEGA_BYTE]> = vec_data.into_boxed_slice().try_into().unwrap();
+        let data = into_mut(boxed_data);
+        Ok(MemIO::new(data)?)
+    }
+}
+
+impl TestIO for FileIO<SLAB_SIZE> {
+    fn create() -> Result<Self, ClientError> {
+        static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+        let file_number = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "finder-runner-{}-{}.db",
+            std::process::id(),
+            file_number
+        ));
+
+        let io = FileIO::open(&path, MEGA_BYTE / SLAB_SIZE)?;
+
+        // The open handle keeps the data alive until the runner exits.
+        std::fs::remove_file(&path).expect("could not unlink test file");
+        Ok(io)
+    }
+}
+
+/// Every channel the runner creates shares one pool, kept in a file.
+impl TestIO for PooledIO<'static, FileMedia, SLAB_SIZE, POOL_RANGES> {
+    fn create() -> Result<Self, ClientError> {
+        static POOL: OnceLock<StoragePool<FileMedia, SLAB_SIZE, POOL_RANGES>> = OnceLock::new();
+        static NEXT_KEY: AtomicUsize = AtomicUsize::new(0);
+
+        let pool = POOL.get_or_init(|| {
+            let path = std::env::temp_dir().join(format!("finder-pool-{}.db", std::process::id()));
+            let media = FileMedia::open(&path, POOL_SLABS * SLAB_SIZE).expect("could not create pool file");
+            // The open handle keeps the data alive until the runner exits.
+            std::fs::remove_file(&path).expect("could not unlink test file");
+            StoragePool::new(media).expect("could not open the pool")
+        });
+
+        let key = NEXT_KEY.fetch_add(1, Ordering::Relaxed) as u32;
+        Ok(pool.open(key, POOL_SLABS / POOL_RANGES - DbInfo::reserved_pages())?)
+    }
+}
+
+/// A `Crypto` backend the tests and scripts are run against.
+pub trait TestCrypto: Crypto + Sized + 'static {
+    /// Keeps the files of tests running against each backend apart.
+    const NAME: &'static str;
+
+    fn new(seed_bytes: &[u8]) -> Result<Self, CryptoError>;
+
+    /// The key pair tests that don't read a key file use.
+    fn test_keys() -> TestKeyPair<Self>;
+
+    /// The key pair for one of the key files in `src/test`.
+    fn keys_from_pem(pem: String) -> TestKeyPair<Self>;
+}
+
+pub type TestKeyPair<C> = KeyPair<<C as Crypto>::PrivateSigningKey, <C as Crypto>::PubSigningKey>;
+
+impl TestCrypto for RustCrypto {
+    const NAME: &'static str = "rsa";
+
+    fn new(seed_bytes: &[u8]) -> Result<Self, CryptoError> {
+        RustCrypto::new(seed_bytes)
+    }
+
+    fn test_keys() -> TestKeyPair<Self> {
+        rust::test::get_test_keys()
+    }
+
+    fn keys_from_pem(pem: String) -> TestKeyPair<Self> {
+        get_test_keys(pem)
+    }
 }
 
-pub struct TestRunner {
+impl TestCrypto for Ed25519Crypto {
+    const NAME: &'static str = "ed25519";
+
+    fn new(seed_bytes: &[u8]) -> Result<Self, CryptoError> {
+        Ed25519Crypto::new(seed_bytes)
+    }
+
+    fn test_keys() -> TestKeyPair<Self> {
+        ed25519::test::get_test_keys()
+    }
+
+    /// Derived from the RSA key file so the scripts name the same
+    /// nodes under either backend.
+    fn keys_from_pem(pem: String) -> TestKeyPair<Self> {
+        use sha2::{Digest, Sha256};
+
+        let secret: [u8; 32] = Sha256::digest(pem.as_bytes()).into();
+        let private = ed25519::SigningKey::from_bytes(&secret);
+        let public = ed25519::PublicKey::from(&private);
+        KeyPair { private, public }
+    }
+}
+
+pub struct TestRunner<I: TestIO, C: TestCrypto = RustCrypto> {
     channel_id_map: HashMap<u64, ChannelId>,
     clients: HashMap<
         u64,
-        &'static mut Client<
-            'static,
-            'static,
-            MAX_CHANNELS,
-            MAX_NODES,
-            MemIO<'static, SLAB_SIZE>,
-            RustCrypto,
-        >,
+        &'static mut Client<'static, 'static, MAX_CHANNELS, MAX_NODES, I, C>,
     >,
 }
 
-impl TestRunner {
+impl<I: TestIO, C: TestCrypto> TestRunner<I, C> {
     pub fn new() -> Self {
         Self {
             channel_id_map: HashMap::new(),
@@ -110,6 +231,18 @@ impl TestRunner {
                     from,
                     count,
                 } => self.check_message_count(channel, from, count)?,
+                SetQuota {
+                    channel,
+                    client,
+                    window,
+                    max_records,
+                    max_bytes,
+                } => self.set_quota(channel, client, Quota { window, max_records, max_bytes })?,
+                SetCompression {
+                    channel,
+                    client,
+                    enabled,
+                } => self.set_compression(channel, client, enabled)?,
             };
         }
 
@@ -120,12 +253,12 @@ impl TestRunner {
         let pem = read_to_string(format!("src/test/{}", key)).expect("could not read key");
 
         let seed = [0; 128];
-        let crypto = into_mut(Box::new(RustCrypto::new(&seed)?));
-        let key_pair = get_test_keys(pem);
+        let crypto = into_mut(Box::new(C::new(&seed)?));
+        let key_pair = C::keys_from_pem(pem);
 
         let channels = into_mut(Box::new(ClientChannels::new()));
 
-        let client: &mut Client<'_, '_, MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, RustCrypto> =
+        let client: &mut Client<'_, '_, MAX_CHANNELS, MAX_NODES, I, C> =
             into_mut(Box::new(Client::new(key_pair, crypto, channels)));
 
         self.clients.insert(client_id, client);
@@ -135,13 +268,7 @@ impl TestRunner {
     fn new_channel(&mut self, channel_id: u64, from: u64) -> Result<(), ClientError> {
         let client = self.clients.get_mut(&from).expect("could not get client");
 
-        // This is a dance to allocate the buffer on the heap
-        // instead of allocating on the stack and moving to the heap
-        let mut vec_data = Vec::with_capacity(MEGA_BYTE);
-        vec_data.resize(MEGA_BYTE, 0u8);
-        let boxed_data: Box<[u8; MEGA_BYTE]> = vec_data.into_boxed_slice().try_into().unwrap();
-        let data = into_mut(boxed_data);
-        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(data)?;
+        let io = I::create()?;
 
         let name_str = "Test Chat";
         let channel_id_real = client.init_chat(name_str, io)?;
@@ -178,14 +305,8 @@ impl TestRunner {
 
         let pub_key = to_add.get_pub_key();
 
-        // This is a dance to allocate the buffer on the heap
-        // instead of allocating on the stack and moving to the heap
-        let mut vec_data = Vec::with_capacity(MEGA_BYTE);
-        vec_data.resize(MEGA_BYTE, 0u8);
-        let boxed_data: Box<[u8; MEGA_BYTE]> = vec_data.into_boxed_slice().try_into().unwrap();
-        let data = into_mut(boxed_data);
-        let io: MemIO<'_, SLAB_SIZE> = MemIO::new(data)?;
-        
+        let io = I::create()?;
+
         to_add.add_channel(owner_key, channel_id_real.clone(), io)?;
         
         let client = self.clients.get_mut(&from)
@@ -245,8 +366,7 @@ impl TestRunner {
 
             let buffer = response.data.as_ref();
             let count = response.count;
-            client.receive_buffer(&channel_id_real, buffer, count)
-                .expect("Could not receive buffer");
+            client.receive_buffer(channel_id_real, buffer, count)?;
 
             break; //BOOG
         }
@@ -254,6 +374,24 @@ impl TestRunner {
     }
 
 
+    fn set_quota(&mut self, channel_id: u64, client: u64, quota: Quota) -> Result<(), ClientError> {
+        let client = self.clients.get_mut(&client)
+            .expect("could not get client");
+        let channel_id_real = self.channel_id_map.get(&channel_id)
+            .expect("no such channel");
+
+        client.set_quota(channel_id_real, Some(quota))
+    }
+
+    fn set_compression(&mut self, channel_id: u64, client: u64, enabled: bool) -> Result<(), ClientError> {
+        let client = self.clients.get_mut(&client)
+            .expect("could not get client");
+        let channel_id_real = self.channel_id_map.get(&channel_id)
+            .expect("no such channel");
+
+        client.set_compression(channel_id_real, enabled)
+    }
+
     fn check_message_count(&mut self, channel_id: u64, from: u64, expected: u64) -> Result<(), ClientError> {
         let client = self.clients.get_mut(&from)
             .expect("could not get client");
@@ -62,6 +62,11 @@ pub enum NetworkProtocol<const MAX_CHANNELS: usize, const MAX_NODES: usize, cons
     },
     SyncRequest(SyncRequest<MAX_NODES>),
     SyncResponse(SyncResponse<RESPONSE_MAX>),
+    /// A channel's signed membership snapshot, see `Client::membership`.
+    Membership {
+        channel_id: ChannelId,
+        data: Vec<u8, RESPONSE_MAX>,
+    },
 }
 
 
@@ -301,6 +306,14 @@ A: Eq + PartialEq + Hash + Debug + Clone,
 
             },
 
+            NetworkProtocol::Membership { channel_id, data } => {
+                log::info!("got membership for {:?}", &channel_id);
+                // A bad or stale snapshot only costs the peer that sent it.
+                if let Err(e) = client.receive_membership(&channel_id, &data) {
+                    log::info!("could not use membership {:?}", e);
+                }
+            },
+
             NetworkProtocol::Hello { pub_key_id, peer_count, channel_info } => {
                 for info in channel_info {
                     log::info!("got channel info {:?}", &info);
@@ -43,7 +43,7 @@ use protocol::{
     heap_type::StaticAllocation,
     storage::{
         IO,
-        mem_io::MemIO,
+        mem_io::{self, MemIO},
     },
     Client,
     ClientChannels,
@@ -52,8 +52,10 @@ use protocol::{
 
 use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPublicKey};
 
-const MEGA_BYTE: usize = 1024 ;//* 10; //* 1024;
 const SLAB_SIZE: usize = 1024;
+// Slabs in each channel's storage, after the pages the layout reserves.
+const MEMIO_SLABS: usize = 8;
+const MEMIO_SIZE: usize = mem_io::buffer_size::<SLAB_SIZE>(MEMIO_SLABS);
 const MAX_CHANNELS: usize = 2;
 const MAX_NODES: usize = 2;
 const ESP_NOW_MTU: u16 = 250;
@@ -64,8 +66,8 @@ const WIFI_HEAP: usize = 65536;
 const MESSAGE_MAX: usize = size_of::<NetworkProtocol<MAX_CHANNELS, MAX_NODES, MAX_RESPONSE>>();
 
 static MESSAGE_BUFFER: StaticAllocation<[u8; MESSAGE_MAX]> = StaticAllocation::wrap([0u8 ; MESSAGE_MAX]);
-static MEMIO_BUFFER: StaticAllocation<[u8; MEGA_BYTE]> = StaticAllocation::wrap([0u8; MEGA_BYTE]);
-static MEMIO_BUFFER2: StaticAllocation<[u8; MEGA_BYTE]> = StaticAllocation::wrap([0u8; MEGA_BYTE]);
+static MEMIO_BUFFER: StaticAllocation<[u8; MEMIO_SIZE]> = StaticAllocation::wrap([0u8; MEMIO_SIZE]);
+static MEMIO_BUFFER2: StaticAllocation<[u8; MEMIO_SIZE]> = StaticAllocation::wrap([0u8; MEMIO_SIZE]);
 
 static CHANNELS_CONST: StaticAllocation<
         ClientChannels<MAX_CHANNELS, MAX_NODES, MemIO<'_, SLAB_SIZE>, RustCrypto>,
@@ -134,6 +136,8 @@ fn main() -> ! {
     let nodes = client.list_nodes(&channel_id).unwrap();
     //assert_eq!(nodes.len(), 1);
     log::info!("got {} nodes", nodes.len());
+    let stats = client.storage_stats(&channel_id).unwrap();
+    log::info!("storage {:?}", stats);
     
     //// end protocol /////
 